@@ -3,7 +3,7 @@
 //! # Features
 //!
 //! This module is empty when no features are enabled. To implement deadlines
-//! for `Instant` you can enable one of the following features:
+//! for `Instant` and `Duration` you can enable one of the following features:
 //!
 //! - `async-io`: use this when using the `async-std` or `smol` runtimes.
 //! - `tokio`: use this when using the `tokio` runtime.
@@ -37,46 +37,77 @@ use std::time::Instant;
 
 use pin_project_lite::pin_project;
 
+/// The clock of the `async-io` timer backend.
+///
+/// See [`Clock`](crate::clock::Clock).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncIoClock;
+
+impl crate::clock::Clock for AsyncIoClock {
+    type Instant = Instant;
+    type Sleep = Sleep;
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn remaining(&self, deadline: Instant) -> Option<std::time::Duration> {
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn instant_after(&self, duration: std::time::Duration) -> Option<Instant> {
+        Instant::now().checked_add(duration)
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Sleep {
+            timer: Timer::at(deadline),
+        }
+    }
+}
+
 pin_project! {
-    /// A future that times out after a duration of time.
+    /// A future which completes at an instant.
+    ///
+    /// This future is returned by [`AsyncIoClock`]'s `sleep_until`.
     #[must_use = "Futures do nothing unless polled or .awaited"]
     #[derive(Debug)]
-    pub(crate) struct Deadline {
-        instant: Instant,
+    pub struct Sleep {
         #[pin]
-        delay: Timer,
-    }
-}
-
-impl Clone for Deadline {
-    fn clone(&self) -> Self {
-        Self {
-            instant: self.instant,
-            delay: Timer::at(self.instant),
-        }
+        timer: Timer,
     }
 }
 
-impl Future for Deadline {
+impl Future for Sleep {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
-        match this.delay.poll(cx) {
+        match self.project().timer.poll(cx) {
             Poll::Ready(_) => Poll::Ready(()),
             Poll::Pending => Poll::Pending,
         }
     }
 }
 
-impl Into<crate::Deadline> for std::time::Instant {
-    fn into(self) -> crate::Deadline {
-        let deadline = Deadline {
-            instant: self,
-            delay: Timer::at(self),
-        };
-        crate::Deadline {
-            kind: crate::deadline::DeadlineKind::AsyncIo { t: deadline },
+impl From<Instant> for crate::Deadline {
+    fn from(instant: Instant) -> crate::Deadline {
+        use crate::clock::Clock;
+        #[cfg(feature = "test-util")]
+        if let Some(deadline) =
+            crate::test::mocked(|| instant.saturating_duration_since(Instant::now()))
+        {
+            return deadline;
+        }
+        AsyncIoClock.deadline(instant)
+    }
+}
+
+impl From<std::time::Duration> for crate::Deadline {
+    fn from(duration: std::time::Duration) -> crate::Deadline {
+        #[cfg(feature = "test-util")]
+        if let Some(deadline) = crate::test::mocked(|| duration) {
+            return deadline;
         }
+        (Instant::now() + duration).into()
     }
 }
@@ -1,51 +1,84 @@
-use async_std::task::JoinHandle;
+//! Extension methods and types for `async_std::task::JoinHandle`.
+//!
+//! # Features
+//!
+//! This module is only available when the `async-std` feature is enabled.
 
-/// Extend the `Task` type `until` method.
-pub trait TaskExt {
-    /// Run a future until it resolves, or until a deadline is hit.
-    fn timeout_at<T>(self, target: T) -> TimeoutAt<Self>
+use crate::{deadline::TimedOutError, Deadline};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use async_std::task::{self, JoinHandle};
+use pin_project_lite::pin_project;
+
+/// Extend the `JoinHandle` type with the `timeout_at` method.
+pub trait JoinHandleExt<T: Send + 'static> {
+    /// Await a task until it resolves, or until a deadline is hit.
+    ///
+    /// If the deadline is hit first the task is cancelled.
+    fn timeout_at<D>(self, target: D) -> TimeoutAt<T>
+    where
+        Self: Sized,
+        D: Into<Deadline>;
+}
+
+impl<T: Send + 'static> JoinHandleExt<T> for JoinHandle<T> {
+    fn timeout_at<D>(self, target: D) -> TimeoutAt<T>
     where
         Self: Sized,
-        T: Into<Deadline>,
+        D: Into<Deadline>,
     {
         TimeoutAt {
             deadline: target.into(),
-            join_handle: self,
+            join_handle: Some(self),
         }
     }
 }
 
-impl<T> JoinHandleExt<T> for JoinHandle<T> {}
-
 pin_project! {
-    /// Run a future until it resolves, or until a deadline is hit.
+    /// Await a task until it resolves, or until a deadline is hit.
     ///
-    /// This method is returned by [`FutureExt::deadline`].
+    /// This method is returned by [`JoinHandleExt::timeout_at`].
     #[must_use = "Futures do nothing unless polled or .awaited"]
     #[derive(Debug)]
-    pub struct TimeoutAt<F> {
-        #[pin]
-        futur_handlee: F,
+    pub struct TimeoutAt<T> {
+        join_handle: Option<JoinHandle<T>>,
         #[pin]
         deadline: Deadline,
     }
 }
 
-impl<F> Future for TimeoutAt<F>
-where
-    F: Future,
-{
-    type Output = Result<F::Output, TimedOutError>;
+impl<T: Send + 'static> Future for TimeoutAt<T> {
+    type Output = Result<T, TimedOutError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
-        if let Poll::Ready(()) = this.deadline.poll(cx) {
-            let _fut = this.join_handle.cancel();
-            return Poll::Ready(Err(TimedOutError::new()));
+        let mut this = self.project();
+        let join_handle = this
+            .join_handle
+            .as_mut()
+            .expect("`TimeoutAt` polled after completion");
+        if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+            // Cancelling a task is asynchronous, so hand it off to the executor.
+            let join_handle = this.join_handle.take().unwrap();
+            task::spawn(async move {
+                join_handle.cancel().await;
+            });
+            return Poll::Ready(Err(this.deadline.timed_out()));
         }
-        match this.join_handle.poll(cx) {
+        match Pin::new(join_handle).poll(cx) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready(it) => Poll::Ready(Ok(it)),
+            Poll::Ready(it) => {
+                this.join_handle.take();
+                Poll::Ready(Ok(it))
+            }
         }
     }
 }
+
+/// Lets code which used `async_std::future::timeout` keep its error handling.
+impl From<async_std::future::TimeoutError> for TimedOutError {
+    fn from(_: async_std::future::TimeoutError) -> TimedOutError {
+        TimedOutError::opaque()
+    }
+}
@@ -0,0 +1,51 @@
+use std::cell::RefCell;
+
+use crate::Deadline;
+
+std::thread_local! {
+    static CURRENT: RefCell<Option<Deadline>> = const { RefCell::new(None) };
+}
+
+/// Returns the deadline budget of the current task, if it has one.
+///
+/// The budget is set by wrapping a future with
+/// [`FutureExt::with_deadline`](crate::future::FutureExt::with_deadline), and
+/// is visible to everything that future calls while it is being polled.
+/// Library code can use it to inherit the caller's remaining time, rather
+/// than picking its own timeout.
+///
+/// ```
+/// use stop_token::prelude::*;
+/// use stop_token::{current_deadline, Deadline};
+///
+/// async fn query() -> Result<(), stop_token::TimedOutError> {
+///     let budget = current_deadline().unwrap_or_else(Deadline::never);
+///     async { /* .. */ }.timeout_at(budget).await
+/// }
+///
+/// # async_std::task::block_on(async {
+/// assert!(query().with_deadline(Deadline::elapsed()).await.is_err());
+/// assert!(query().await.is_ok());
+/// # });
+/// ```
+pub fn current_deadline() -> Option<Deadline> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Makes `deadline` the current budget until the returned guard is dropped.
+pub(crate) fn enter(deadline: Deadline) -> Entered {
+    let previous = CURRENT.with(|current| current.replace(Some(deadline)));
+    Entered { previous }
+}
+
+/// Restores the previous budget once dropped, even on panic.
+pub(crate) struct Entered {
+    previous: Option<Deadline>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
@@ -0,0 +1,128 @@
+//! Failure injection: make deadlines fire early under test.
+//!
+//! Timeout handling is notoriously under-tested, because in integration tests
+//! deadlines are rarely hit. With chaos enabled, time-based deadlines are
+//! shortened to a random point between their creation and their original
+//! expiry, so the timeout paths get exercised.
+//!
+//! This applies to the deadlines of every time backend, and to those of
+//! custom [`Clock`](crate::clock::Clock)s which implement
+//! [`Clock::instant_after`](crate::clock::Clock::instant_after). Deadlines on
+//! other clocks are hit right away instead. Deadlines backed by a
+//! [`StopToken`](crate::StopToken) or an arbitrary future are never affected.
+//!
+//! The decisions are random. Use [`seed`] to make them reproducible, and a
+//! probability of `0.0` or `1.0` to make them certain.
+//!
+//! # Features
+//!
+//! This module is only available when the `chaos` feature is enabled. Chaos is
+//! off until [`enable`] or [`enable_for`] is called.
+//!
+//! # Examples
+//!
+//! ```
+//! // Make half of all deadlines created from now on fire early.
+//! stop_token::chaos::enable(0.5);
+//! # stop_token::chaos::disable();
+//! ```
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// The probability of firing early, stored as `f64` bits.
+static PROBABILITY: AtomicU64 = AtomicU64::new(0);
+
+/// Makes deadlines fire early with the given probability.
+///
+/// The probability is clamped to the `0.0..=1.0` range. Only deadlines created
+/// after this call are affected.
+pub fn enable(probability: f64) {
+    let probability = probability.clamp(0.0, 1.0);
+    PROBABILITY.store(probability.to_bits(), Ordering::Relaxed);
+}
+
+/// Stops making deadlines fire early.
+///
+/// This does not affect probabilities configured per label.
+pub fn disable() {
+    enable(0.0);
+}
+
+fn labels() -> &'static Mutex<HashMap<String, f64>> {
+    static LABELS: OnceLock<Mutex<HashMap<String, f64>>> = OnceLock::new();
+    LABELS.get_or_init(Default::default)
+}
+
+/// Makes deadlines with the given label fire early with the given probability.
+///
+/// This applies when a deadline is labelled with
+/// [`Deadline::labelled`](crate::Deadline::labelled), in addition to the global
+/// probability set with [`enable`].
+pub fn enable_for(label: &str, probability: f64) {
+    let probability = probability.clamp(0.0, 1.0);
+    labels()
+        .lock()
+        .unwrap()
+        .insert(label.to_owned(), probability);
+}
+
+/// Stops making deadlines with the given label fire early.
+pub fn disable_for(label: &str) {
+    labels().lock().unwrap().remove(label);
+}
+
+/// Returns the current probability of a deadline firing early.
+pub fn probability() -> f64 {
+    f64::from_bits(PROBABILITY.load(Ordering::Relaxed))
+}
+
+/// Seeds the random number generator of the current thread.
+///
+/// Whether a deadline fires early, and how early, is drawn from a generator
+/// local to the thread creating the deadline. Seeding it makes a test which
+/// creates its deadlines on one thread reproducible.
+pub fn seed(seed: u64) {
+    // xorshift gets stuck on zero.
+    STATE.with(|state| state.set(seed | 1));
+}
+
+/// Returns the probability configured for `label`, if any.
+pub(crate) fn probability_for(label: &str) -> Option<f64> {
+    labels().lock().unwrap().get(label).copied()
+}
+
+/// Decides whether a deadline with `remaining` time left fires early, with
+/// the given probability, and returns the shortened time left if so.
+pub(crate) fn shorten(probability: f64, remaining: Duration) -> Option<Duration> {
+    if probability <= 0.0 || random() >= probability {
+        return None;
+    }
+    Some(remaining.mul_f64(random()))
+}
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new({
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(0);
+        hasher.finish() | 1
+    });
+}
+
+/// Returns a random number in `0.0..1.0`.
+fn random() -> f64 {
+    // xorshift64*
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
@@ -0,0 +1,15 @@
+cfg_duration_backend! {
+    use ::chrono::{DateTime, TimeZone, Utc};
+
+    use crate::Deadline;
+
+    /// A deadline at a wall-clock time, in any time zone.
+    ///
+    /// Times in the past produce a deadline which is already hit.
+    impl<Tz: TimeZone> From<DateTime<Tz>> for Deadline {
+        fn from(time: DateTime<Tz>) -> Deadline {
+            let remaining = time.signed_duration_since(Utc::now());
+            Deadline::after(remaining.to_std().unwrap_or_default())
+        }
+    }
+}
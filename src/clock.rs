@@ -0,0 +1,188 @@
+//! Pluggable timer backends.
+//!
+//! A [`Clock`] tells the time and sleeps until a given instant. The
+//! `async-io` and `tokio` backends are clocks (`AsyncIoClock` and
+//! `TokioClock`), and custom executors or simulators can provide their own,
+//! without this crate having to know about them.
+//!
+//! # Thread-per-core runtimes
+//!
+//! Deadlines are `Send`, and so is [`Clock::Sleep`]. The timers of
+//! thread-per-core runtimes such as `glommio`, and of `io_uring` runtimes
+//! such as `monoio`, are `!Send`, so they can't be wrapped in a `Clock`.
+//! With the `local` feature, they can drive a deadline through
+//! `Deadline::from_local_future` instead, as long as the deadline stays on
+//! the thread it was created on.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::future::{ready, Ready};
+//! use stop_token::clock::Clock;
+//! use stop_token::prelude::*;
+//!
+//! /// A clock on which every instant has already passed.
+//! #[derive(Clone)]
+//! struct Expired;
+//!
+//! impl Clock for Expired {
+//!     type Instant = u64;
+//!     type Sleep = Ready<()>;
+//!
+//!     fn now(&self) -> u64 {
+//!         u64::MAX
+//!     }
+//!
+//!     fn sleep_until(&self, _deadline: u64) -> Ready<()> {
+//!         ready(())
+//!     }
+//! }
+//!
+//! # async_std::task::block_on(async {
+//! let deadline = Expired.deadline(10);
+//! assert!(std::future::pending::<()>().timeout_at(deadline).await.is_err());
+//! # });
+//! ```
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use crate::deadline::DeadlineKind;
+use crate::Deadline;
+
+/// A source of time, which can sleep until an instant.
+pub trait Clock: Clone + Send + Sync + 'static {
+    /// A point in time on this clock.
+    type Instant: Copy + Ord + Send + Sync + 'static;
+
+    /// A future which completes once the clock reaches an instant.
+    type Sleep: Future<Output = ()> + Send + 'static;
+
+    /// Returns the current time on this clock.
+    fn now(&self) -> Self::Instant;
+
+    /// Sleeps until the clock reaches `deadline`.
+    fn sleep_until(&self, deadline: Self::Instant) -> Self::Sleep;
+
+    /// Returns how long until the clock reaches `deadline`, or `None` if the
+    /// clock can't tell.
+    ///
+    /// This backs [`Deadline::remaining`]. The default implementation returns
+    /// `None`.
+    fn remaining(&self, deadline: Self::Instant) -> Option<Duration> {
+        let _ = deadline;
+        None
+    }
+
+    /// Returns the instant `duration` from now, or `None` if the clock can't
+    /// tell.
+    ///
+    /// This lets the `chaos` feature make deadlines on this clock fire early.
+    /// The default implementation returns `None`, and such deadlines are hit
+    /// right away instead.
+    fn instant_after(&self, duration: Duration) -> Option<Self::Instant> {
+        let _ = duration;
+        None
+    }
+
+    /// Creates a deadline which is hit once the clock reaches `instant`.
+    ///
+    /// Every clone of the deadline sleeps on its own. The `Instant` and
+    /// `Duration` deadlines of the built-in backends are created this way
+    /// too.
+    fn deadline(&self, instant: Self::Instant) -> Deadline {
+        let at: Arc<dyn Timer> = Arc::new(At {
+            clock: self.clone(),
+            instant,
+        });
+        Deadline::from_kind(DeadlineKind::Clock {
+            t: ClockDeadline {
+                delay: at.sleep(),
+                at,
+            },
+        })
+    }
+}
+
+/// A clock together with the instant a deadline is hit at.
+trait Timer: Send + Sync {
+    fn sleep(&self) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+    fn remaining(&self) -> Option<Duration>;
+    #[cfg(feature = "chaos")]
+    fn shortened(&self, remaining: Duration) -> Option<Arc<dyn Timer>>;
+}
+
+struct At<C: Clock> {
+    clock: C,
+    instant: C::Instant,
+}
+
+impl<C: Clock> Timer for At<C> {
+    fn sleep(&self) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(self.clock.sleep_until(self.instant))
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        self.clock.remaining(self.instant)
+    }
+
+    #[cfg(feature = "chaos")]
+    fn shortened(&self, remaining: Duration) -> Option<Arc<dyn Timer>> {
+        let instant = self.clock.instant_after(remaining)?;
+        Some(Arc::new(At {
+            clock: self.clock.clone(),
+            instant,
+        }))
+    }
+}
+
+/// A deadline on a custom [`Clock`].
+pub(crate) struct ClockDeadline {
+    delay: Pin<Box<dyn Future<Output = ()> + Send>>,
+    at: Arc<dyn Timer>,
+}
+
+impl ClockDeadline {
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.at.remaining()
+    }
+
+    /// Returns a deadline on the same clock with `remaining` time left, if
+    /// the clock can tell when that is.
+    #[cfg(feature = "chaos")]
+    pub(crate) fn shortened(&self, remaining: Duration) -> Option<ClockDeadline> {
+        let at = self.at.shortened(remaining)?;
+        Some(ClockDeadline {
+            delay: at.sleep(),
+            at,
+        })
+    }
+}
+
+impl fmt::Debug for ClockDeadline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClockDeadline").finish_non_exhaustive()
+    }
+}
+
+impl Clone for ClockDeadline {
+    fn clone(&self) -> Self {
+        ClockDeadline {
+            delay: self.at.sleep(),
+            at: self.at.clone(),
+        }
+    }
+}
+
+impl Future for ClockDeadline {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.delay.as_mut().poll(cx)
+    }
+}
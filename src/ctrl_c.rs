@@ -0,0 +1,59 @@
+use std::sync::OnceLock;
+
+use crate::{StopSource, StopToken};
+
+/// The source which is cancelled by Ctrl-C, set up on first use.
+fn source() -> &'static StopSource {
+    static SOURCE: OnceLock<StopSource> = OnceLock::new();
+    SOURCE.get_or_init(|| {
+        let source = StopSource::new();
+        let trigger = source.trigger();
+        ctrlc::set_handler(move || trigger.fire()).expect("failed to install the Ctrl-C handler");
+        source
+    })
+}
+
+/// Produces a `StopToken` which stops once the process receives Ctrl-C.
+///
+/// This covers `SIGINT` on Unix and `CTRL_C_EVENT`/`CTRL_BREAK_EVENT` on
+/// Windows. The handler is installed by the first call, and stays installed
+/// for the lifetime of the process. To shut down gracefully when the console
+/// window is closed on Windows, see the `windows_console` module.
+///
+/// ```no_run
+/// use async_std::prelude::*;
+/// use async_std::stream;
+/// use stop_token::prelude::*;
+///
+/// # async_std::task::block_on(async {
+/// let mut work = stream::repeat(12u8).timeout_at(stop_token::ctrl_c());
+/// while let Some(Ok(ev)) = work.next().await {
+///     println!("{}", ev);
+/// }
+/// # });
+/// ```
+///
+/// # Panics
+///
+/// Panics if the handler can't be installed, for example because another
+/// Ctrl-C handler has already been installed through the `ctrlc` crate.
+pub fn ctrl_c() -> StopToken {
+    source().token()
+}
+
+impl StopSource {
+    /// Creates a new `StopSource` which is cancelled once the process
+    /// receives Ctrl-C.
+    ///
+    /// The source can still be cancelled or dropped early. See [`ctrl_c`] for
+    /// the signals covered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the Ctrl-C handler can't be installed.
+    pub fn from_ctrl_c() -> StopSource {
+        let source = StopSource::new();
+        source.attach_to(&ctrl_c());
+        source
+    }
+}
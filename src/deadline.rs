@@ -1,114 +1,792 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
 use core::fmt;
-use std::{
-    error::Error,
-    future::Future,
-    io,
-    pin::Pin,
-    task::{Context, Poll},
-};
+use core::future::{Future, IntoFuture};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::{error::Error, io};
 
+use crate::utils::{Mutex, WakerList};
+#[cfg(feature = "std")]
+use crate::StopSource;
 use crate::StopToken;
 
+/// What hit a deadline.
+///
+/// See [`TimedOutError::kind`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub enum TimeoutKind {
+    /// A token stopped, which usually means a deliberate cancellation.
+    Stopped,
+    /// A timer expired.
+    Expired,
+}
+
 /// An error returned when a future times out.
 #[derive(Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub struct TimedOutError {
-    _private: (),
+    kind: TimeoutKind,
+    label: Option<&'static str>,
+    elapsed: Option<Duration>,
+    budget: Option<Duration>,
 }
 
 impl fmt::Debug for TimedOutError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("TimeoutError").finish()
+        let mut f = f.debug_struct("TimeoutError");
+        f.field("kind", &self.kind);
+        if let Some(label) = self.label {
+            f.field("label", &label);
+        }
+        if let Some(elapsed) = self.elapsed {
+            f.field("elapsed", &elapsed);
+        }
+        if let Some(budget) = self.budget {
+            f.field("budget", &budget);
+        }
+        f.finish()
     }
 }
 
 impl TimedOutError {
-    pub(crate) fn new() -> Self {
-        Self { _private: () }
+    /// Returns the label of the deadline that was hit, if it had one.
+    ///
+    /// See [`Deadline::labelled`].
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
+    }
+
+    /// Returns whether a token stopped or a timer expired.
+    ///
+    /// When a deadline combines both and they fire together, the token wins.
+    /// Deadlines built from arbitrary futures count as timers.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::{Deadline, StopSource, TimeoutKind};
+    ///
+    /// # async_std::task::block_on(async {
+    /// let shutdown = StopSource::new();
+    /// let deadline = Deadline::never().or_token(&shutdown.token());
+    ///
+    /// drop(shutdown);
+    /// let err = std::future::pending::<()>().timeout_at(deadline).await.unwrap_err();
+    /// assert_eq!(err.kind(), TimeoutKind::Stopped);
+    /// # });
+    /// ```
+    pub fn kind(&self) -> TimeoutKind {
+        self.kind
+    }
+
+    /// Creates an error for a timeout reported by another timer, which carries
+    /// no details.
+    #[cfg(any(feature = "async-std", feature = "tokio"))]
+    pub(crate) fn opaque() -> TimedOutError {
+        TimedOutError {
+            kind: TimeoutKind::Expired,
+            label: None,
+            elapsed: None,
+            budget: None,
+        }
+    }
+
+    /// Returns how long the deadline ran before it was hit, counted from its
+    /// creation.
+    ///
+    /// This is `None` without the `std` feature, and on `wasm32` without the
+    /// `wasm` feature, where there is no clock to read.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.elapsed
+    }
+
+    /// Returns the time the deadline allowed when it was created, if it could
+    /// tell.
+    ///
+    /// This is `None` for deadlines built from tokens or arbitrary futures,
+    /// see [`Deadline::remaining`].
+    pub fn budget(&self) -> Option<Duration> {
+        self.budget
     }
 }
 
+#[cfg(feature = "std")]
 impl Error for TimedOutError {}
 
-impl Into<io::Error> for TimedOutError {
-    fn into(self) -> io::Error {
-        io::Error::new(io::ErrorKind::TimedOut, "Future has timed out")
+#[cfg(feature = "std")]
+impl From<TimedOutError> for io::Error {
+    fn from(err: TimedOutError) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, err)
     }
 }
 
 impl fmt::Display for TimedOutError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        "Future has timed out".fmt(f)
+        "Future has timed out".fmt(f)?;
+        // How long it ran is only telling next to the budget it had.
+        if let (Some(elapsed), Some(budget)) = (self.elapsed, self.budget) {
+            let elapsed = Duration::from_millis(elapsed.as_millis() as u64);
+            write!(f, " after {:?} (budget {:?})", elapsed, budget)?;
+        }
+        if let Some(label) = self.label {
+            write!(f, " waiting on {}", label)?;
+        }
+        Ok(())
     }
 }
 
-pin_project_lite::pin_project! {
-    /// A future that times out after a duration of time.
-    #[must_use = "Futures do nothing unless polled or .awaited"]
-    #[derive(Debug)]
-    pub struct Deadline {
-        #[pin]
-        pub(crate) kind: DeadlineKind,
+/// A future that times out after a duration of time.
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug, Clone)]
+pub struct Deadline {
+    pub(crate) kind: DeadlineKind,
+    pub(crate) label: Option<&'static str>,
+    /// The time left when the deadline was created.
+    budget: Option<Duration>,
+    #[cfg(feature = "std")]
+    created: crate::utils::Created,
+    /// Whether hitting the deadline has been reported already.
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    reported: bool,
+}
+
+/// The backends a `Deadline` can be built from.
+///
+/// Every variant is `Unpin`, so no pin projection is needed.
+#[derive(Debug, Clone)]
+pub(crate) enum DeadlineKind {
+    StopToken {
+        t: StopToken,
+    },
+    Shared {
+        t: SharedDeadline,
+    },
+    Any {
+        t: Vec<Deadline>,
+    },
+    Clock {
+        t: crate::clock::ClockDeadline,
+    },
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    Wasm {
+        t: crate::wasm::Deadline,
+    },
+}
+
+impl Deadline {
+    pub(crate) fn from_kind(kind: DeadlineKind) -> Self {
+        #[cfg(feature = "chaos")]
+        let kind = {
+            let mut kind = kind;
+            kind.chaos(crate::chaos::probability());
+            kind
+        };
+        // A token which has already stopped has no time left, but it never
+        // had a time budget either.
+        let budget = match kind {
+            DeadlineKind::StopToken { .. } => None,
+            _ => kind.remaining(),
+        };
+        Deadline {
+            budget,
+            kind,
+            label: None,
+            #[cfg(feature = "std")]
+            created: Default::default(),
+            #[cfg(any(feature = "tracing", feature = "metrics"))]
+            reported: false,
+        }
+    }
+
+    /// Creates a deadline which is never hit.
+    ///
+    /// This is useful for APIs which take an `impl Into<Deadline>`, when the
+    /// caller has no timeout to apply.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::Deadline;
+    ///
+    /// # async_std::task::block_on(async {
+    /// assert_eq!(async { 1 }.timeout_at(Deadline::never()).await, Ok(1));
+    /// # });
+    /// ```
+    pub fn never() -> Self {
+        StopToken::never().into()
+    }
+
+    /// Creates a deadline which has already been hit.
+    ///
+    /// This is useful in tests, and for paths which should fail fast.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::Deadline;
+    ///
+    /// # async_std::task::block_on(async {
+    /// assert!(async { 1 }.timeout_at(Deadline::elapsed()).await.is_err());
+    /// # });
+    /// ```
+    pub fn elapsed() -> Self {
+        StopToken::already_cancelled().into()
+    }
+
+    /// Attaches a label to this deadline.
+    ///
+    /// The label is included in the [`TimedOutError`] returned when the
+    /// deadline is hit, so a timeout deep in a pipeline identifies which
+    /// budget was exceeded.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::{Deadline, StopToken};
+    ///
+    /// # async_std::task::block_on(async {
+    /// let deadline = Deadline::from(StopToken::already_cancelled()).labelled("db-query");
+    /// let err = std::future::pending::<()>().timeout_at(deadline).await.unwrap_err();
+    /// assert_eq!(err.label(), Some("db-query"));
+    /// assert_eq!(err.to_string(), "Future has timed out waiting on db-query");
+    /// # });
+    /// ```
+    pub fn labelled(mut self, label: &'static str) -> Self {
+        #[cfg(feature = "chaos")]
+        if let Some(probability) = crate::chaos::probability_for(label) {
+            self.kind.chaos(probability);
+        }
+        self.label = Some(label);
+        self
+    }
+
+    /// Combines this deadline with a token, producing a deadline which is hit
+    /// when either the deadline is hit or the token stops.
+    ///
+    /// This gives "time OR shutdown" semantics to APIs which accept a single
+    /// deadline. The label of this deadline is kept.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::{Deadline, StopSource, StopToken};
+    ///
+    /// # async_std::task::block_on(async {
+    /// let shutdown = StopSource::new();
+    /// let deadline = Deadline::from(StopToken::never()).or_token(&shutdown.token());
+    ///
+    /// drop(shutdown);
+    /// assert!(std::future::pending::<()>().timeout_at(deadline).await.is_err());
+    /// # });
+    /// ```
+    pub fn or_token(self, token: &StopToken) -> Self {
+        let label = self.label;
+        let mut deadline = Deadline::from_kind(DeadlineKind::Any {
+            t: alloc::vec![self, token.clone().into()],
+        });
+        deadline.label = label;
+        deadline
+    }
+
+    /// Combines several deadlines or tokens into one, which is hit as soon as
+    /// the first of them is hit.
+    ///
+    /// This avoids nesting combinators when a request deadline, an idle
+    /// deadline and a shutdown token all apply at once. Combining no
+    /// deadlines at all produces a deadline which is never hit.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::{Deadline, StopSource, StopToken};
+    ///
+    /// # async_std::task::block_on(async {
+    /// let shutdown = StopSource::new();
+    /// let deadline = Deadline::earliest([
+    ///     Deadline::never(),
+    ///     StopToken::never().into(),
+    ///     shutdown.token().into(),
+    /// ]);
+    ///
+    /// drop(shutdown);
+    /// assert!(std::future::pending::<()>().timeout_at(deadline).await.is_err());
+    /// # });
+    /// ```
+    pub fn earliest<I>(deadlines: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Deadline>,
+    {
+        Deadline::from_kind(DeadlineKind::Any {
+            t: deadlines.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Creates a deadline which is hit once `future` completes.
+    ///
+    /// This is the extension point for timers this crate has no backend for,
+    /// such as those of custom runtimes or simulated clocks. Implementing
+    /// `From<YourTimer> for Deadline` with it lets `YourTimer` be passed to
+    /// [`FutureExt::timeout_at`](crate::future::FutureExt::timeout_at) and
+    /// the other combinators. Clones of the deadline share `future`, which is
+    /// polled on behalf of all of them.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::Deadline;
+    ///
+    /// struct Expired;
+    ///
+    /// impl From<Expired> for Deadline {
+    ///     fn from(_: Expired) -> Deadline {
+    ///         Deadline::from_future(std::future::ready(()))
+    ///     }
+    /// }
+    ///
+    /// # async_std::task::block_on(async {
+    /// let result = std::future::pending::<()>().timeout_at(Expired).await;
+    /// assert!(result.is_err());
+    /// # });
+    /// ```
+    pub fn from_future<F>(future: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        Deadline::from_kind(DeadlineKind::Shared {
+            t: SharedDeadline::new(future),
+        })
+    }
+
+    /// Creates a deadline which is hit once `future` completes, for futures
+    /// which aren't `Send`.
+    ///
+    /// This plugs in the timers of thread-per-core runtimes, such as `glommio`
+    /// and `monoio`, whose tasks never leave the thread they were spawned
+    /// on. The deadline is `Send` like any other, but `future` is only ever
+    /// polled on the thread which called this method.
+    ///
+    /// ```
+    /// use std::rc::Rc;
+    /// use stop_token::prelude::*;
+    /// use stop_token::Deadline;
+    ///
+    /// let local = Rc::new(());
+    /// let deadline = Deadline::from_local_future(async move {
+    ///     let _local = local;
+    /// });
+    ///
+    /// # async_std::task::block_on(async {
+    /// let result = std::future::pending::<()>().timeout_at(deadline).await;
+    /// assert!(result.is_err());
+    /// # });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Polling the deadline or one of its clones on another thread panics,
+    /// and so does dropping the last of them there.
+    #[cfg(feature = "local")]
+    pub fn from_local_future<F>(future: F) -> Self
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let mut future = send_wrapper::SendWrapper::new(Box::pin(future));
+        Deadline::from_future(core::future::poll_fn(move |cx| (*future).as_mut().poll(cx)))
+    }
+
+    /// Returns the label of this deadline, if it has one.
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
+    }
+
+    /// Returns how much time is left before this deadline is hit.
+    ///
+    /// This is `None` when there is no telling, for deadlines built from
+    /// tokens which haven't stopped yet, from arbitrary futures, or from
+    /// clocks which don't implement [`Clock::remaining`](crate::clock::Clock::remaining).
+    /// A deadline combining several others reports the earliest of those
+    /// which can tell.
+    ///
+    /// ```
+    /// use stop_token::{Deadline, StopToken};
+    /// use std::time::Duration;
+    ///
+    /// assert_eq!(Deadline::elapsed().remaining(), Some(Duration::ZERO));
+    /// assert_eq!(Deadline::from(StopToken::never()).remaining(), None);
+    /// ```
+    pub fn remaining(&self) -> Option<Duration> {
+        self.kind.remaining()
+    }
+
+    /// Returns the instant this deadline is hit at, if it can be told.
+    ///
+    /// This is the current time plus [`Deadline::remaining`], so it is
+    /// always `None` on `wasm32-unknown-unknown`, where `Instant` isn't
+    /// supported.
+    #[cfg(feature = "std")]
+    pub fn expires_at(&self) -> Option<std::time::Instant> {
+        if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            return None;
+        }
+        self.remaining()
+            .and_then(|remaining| std::time::Instant::now().checked_add(remaining))
+    }
+
+    /// Creates the error returned when this deadline is hit.
+    pub(crate) fn timed_out(&self) -> TimedOutError {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            label = self.label,
+            elapsed = ?self.created.elapsed(),
+            "future timed out"
+        );
+        let kind = if self.kind.stopped() {
+            TimeoutKind::Stopped
+        } else {
+            TimeoutKind::Expired
+        };
+        TimedOutError {
+            kind,
+            label: self.label,
+            #[cfg(feature = "std")]
+            elapsed: self.created.elapsed(),
+            #[cfg(not(feature = "std"))]
+            elapsed: None,
+            budget: self.budget,
+        }
+    }
+
+    /// Runs `f` once this deadline is hit, even if nothing is polling it.
+    ///
+    /// The deadline is driven to completion on a background thread. Dropping
+    /// the returned guard before the deadline is hit cancels the callback.
+    /// `f` runs at most once, on that thread.
+    ///
+    /// Every call spawns an OS thread of its own, which stays blocked until
+    /// the deadline is hit or the guard is dropped. This suits a handful of
+    /// long-lived deadlines, such as a process-wide shutdown timer, but not
+    /// one per request: there, awaiting the deadline from a task on the
+    /// application's runtime is much cheaper.
+    #[cfg(feature = "std")]
+    pub fn on_expire<F>(self, f: F) -> ExpireGuard
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let source = StopSource::new();
+        let disarmed = source.token();
+        std::thread::Builder::new()
+            .name("stop-token-expire".into())
+            .spawn(move || {
+                // Check the guard first, so dropping it always wins a tie.
+                let deadline = alloc::boxed::Box::pin(self);
+                if !crate::utils::block_on(crate::utils::first_wins(disarmed, deadline)) {
+                    f();
+                }
+            })
+            .expect("failed to spawn the expiry thread");
+        ExpireGuard { _source: source }
     }
 }
 
-cfg_if::cfg_if! {
-    if #[cfg(all(feature = "tokio", feature = "async-io"))] {
-        pin_project_lite::pin_project! {
-            #[project = DeadlineKindProj]
-            #[derive(Debug, Clone)]
-            pub(crate) enum DeadlineKind {
-                StopToken{ #[pin]t: StopToken},
-                Tokio{#[pin]t: crate::tokio::Deadline},
-                AsyncIo{#[pin]t: crate::async_io::Deadline},
+cfg_duration_backend! {
+    impl Deadline {
+        /// Creates a deadline which is hit once `duration` has elapsed.
+        ///
+        /// This uses the same backend as `Into<Deadline>` for `Duration`,
+        /// and falls back to `tokio` when that is the only one enabled.
+        ///
+        /// # Panics
+        ///
+        /// With the `tokio` fallback, this panics when called outside a tokio
+        /// runtime, as `tokio::time::sleep_until` does.
+        pub(crate) fn after(duration: std::time::Duration) -> Deadline {
+            #[cfg(feature = "test-util")]
+            if let Some(deadline) = crate::test::mocked(|| duration) {
+                return deadline;
             }
+            #[cfg(any(
+                feature = "async-io",
+                feature = "futures-timer",
+                all(target_arch = "wasm32", feature = "wasm"),
+            ))]
+            return duration.into();
+            #[cfg(not(any(
+                feature = "async-io",
+                feature = "futures-timer",
+                all(target_arch = "wasm32", feature = "wasm"),
+            )))]
+            return (::tokio::time::Instant::now() + duration).into();
         }
-    } else if #[cfg(feature = "tokio")] {
-        pin_project_lite::pin_project! {
-            #[project = DeadlineKindProj]
-            #[derive(Debug, Clone)]
-            pub(crate) enum DeadlineKind {
-                StopToken{ #[pin]t: StopToken},
-                Tokio{#[pin]t: crate::tokio::Deadline},
-            }
+    }
+
+    /// A deadline at a wall-clock time.
+    ///
+    /// The time is converted to a duration from now when the deadline is
+    /// created, so adjusting the system clock afterwards doesn't move the
+    /// deadline. Times in the past produce a deadline which is already hit.
+    impl From<std::time::SystemTime> for Deadline {
+        fn from(time: std::time::SystemTime) -> Deadline {
+            let remaining = time.duration_since(crate::utils::system_now());
+            Deadline::after(remaining.unwrap_or_default())
         }
-    } else if #[cfg(feature = "async-io")] {
-        pin_project_lite::pin_project! {
-            #[project = DeadlineKindProj]
-            #[derive(Debug, Clone)]
-            pub(crate) enum DeadlineKind {
-                StopToken{ #[pin]t: StopToken},
-                AsyncIo{#[pin]t: crate::async_io::Deadline},
+    }
+}
+
+/// A guard that keeps an expiry callback armed.
+///
+/// This type is returned by [`Deadline::on_expire`]. Dropping it cancels the
+/// callback if it hasn't run yet.
+#[cfg(feature = "std")]
+#[must_use = "Dropping the guard immediately cancels the callback"]
+#[derive(Debug)]
+pub struct ExpireGuard {
+    _source: StopSource,
+}
+
+/// Awaiting a borrowed deadline waits on a clone of it.
+impl IntoFuture for &Deadline {
+    type Output = ();
+    type IntoFuture = Deadline;
+
+    fn into_future(self) -> Deadline {
+        self.clone()
+    }
+}
+
+impl Future for Deadline {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let poll = match &mut self.kind {
+            DeadlineKind::StopToken { t } => t.poll_stopped(cx),
+            DeadlineKind::Shared { t } => Pin::new(t).poll(cx),
+            DeadlineKind::Clock { t } => Pin::new(t).poll(cx),
+            DeadlineKind::Any { t } => {
+                if t.iter_mut().any(|t| Pin::new(t).poll(cx).is_ready()) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
             }
-        }
-    } else {
-        pin_project_lite::pin_project! {
-            #[project = DeadlineKindProj]
-            #[derive(Debug, Clone)]
-            pub(crate) enum DeadlineKind {
-                StopToken{ #[pin]t: StopToken},
+            #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+            DeadlineKind::Wasm { t } => Pin::new(t).poll(cx),
+        };
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        if poll.is_ready() && !self.reported {
+            self.reported = true;
+            let elapsed = self.created.elapsed();
+            #[cfg(feature = "tracing")]
+            tracing::debug!(label = self.label, elapsed = ?elapsed, "deadline hit");
+            #[cfg(feature = "metrics")]
+            {
+                let labels: Vec<_> = self
+                    .label
+                    .map(|label| metrics::Label::new("deadline", label))
+                    .into_iter()
+                    .collect();
+                metrics::counter!("stop_token_deadlines_hit_total", labels.clone()).increment(1);
+                if let Some(elapsed) = elapsed {
+                    metrics::histogram!("stop_token_time_to_deadline_seconds", labels)
+                        .record(elapsed);
+                }
             }
         }
+        poll
+    }
+}
+
+impl DeadlineKind {
+    /// Returns whether a token this deadline waits on has stopped.
+    fn stopped(&self) -> bool {
+        match self {
+            DeadlineKind::StopToken { t } => t.is_stopped(),
+            DeadlineKind::Shared { t } => t.shared.tokens.iter().any(StopToken::is_stopped),
+            DeadlineKind::Any { t } => t.iter().any(|t| t.kind.stopped()),
+            _ => false,
+        }
+    }
+
+    /// Collects the tokens this deadline waits on.
+    fn tokens(&self, tokens: &mut Vec<StopToken>) {
+        match self {
+            DeadlineKind::StopToken { t } => tokens.push(t.clone()),
+            DeadlineKind::Shared { t } => tokens.extend(t.shared.tokens.iter().cloned()),
+            DeadlineKind::Any { t } => t.iter().for_each(|t| t.kind.tokens(tokens)),
+            _ => {}
+        }
+    }
+
+    fn remaining(&self) -> Option<Duration> {
+        match self {
+            DeadlineKind::StopToken { t } => t.is_stopped().then_some(Duration::ZERO),
+            DeadlineKind::Shared { .. } => None,
+            DeadlineKind::Clock { t } => t.remaining(),
+            DeadlineKind::Any { t } => t.iter().filter_map(Deadline::remaining).min(),
+            #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+            DeadlineKind::Wasm { t } => Some(t.remaining()),
+        }
+    }
+}
+
+#[cfg(feature = "chaos")]
+impl DeadlineKind {
+    /// Makes this deadline fire early with the given probability.
+    ///
+    /// Deadlines which wait on tokens or futures are left alone, and so are
+    /// combined ones, whose parts have been through this already.
+    fn chaos(&mut self, probability: f64) {
+        let remaining = match self {
+            DeadlineKind::StopToken { .. }
+            | DeadlineKind::Shared { .. }
+            | DeadlineKind::Any { .. } => return,
+            _ => self.remaining(),
+        };
+        let remaining = match remaining {
+            Some(remaining) => remaining,
+            None => return,
+        };
+        let remaining = match crate::chaos::shorten(probability, remaining) {
+            Some(remaining) => remaining,
+            None => return,
+        };
+        let shortened = match self {
+            DeadlineKind::Clock { t } => t.shortened(remaining).map(|t| DeadlineKind::Clock { t }),
+            #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+            DeadlineKind::Wasm { .. } => Some(DeadlineKind::Wasm {
+                t: crate::wasm::Deadline::at(
+                    js_sys::Date::now() + remaining.as_secs_f64() * 1000.0,
+                ),
+            }),
+            _ => None,
+        };
+        // Clocks which can't tell when `remaining` will have elapsed get a
+        // deadline which is hit right away.
+        *self = shortened.unwrap_or_else(|| DeadlineKind::Shared {
+            t: SharedDeadline::new(core::future::ready(())),
+        });
     }
 }
 
-impl Clone for Deadline {
+/// A deadline which is driven once on behalf of all of its clones.
+///
+/// Clones of a regular `Deadline` each register their own timer. Clones of a
+/// `SharedDeadline` poll a single underlying future, and are all woken when
+/// it completes.
+#[derive(Debug)]
+pub(crate) struct SharedDeadline {
+    shared: Arc<Shared>,
+    slot: Option<usize>,
+}
+
+struct Shared {
+    future: Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    /// The tokens the future waits on, to tell what hit the deadline.
+    tokens: Vec<StopToken>,
+    done: AtomicBool,
+    wakers: Mutex<WakerList>,
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shared")
+            .field("done", &self.done.load(Ordering::Acquire))
+            .finish()
+    }
+}
+
+impl SharedDeadline {
+    pub(crate) fn new<F>(future: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        SharedDeadline::with_tokens(future, Vec::new())
+    }
+
+    /// Shares `deadline`, keeping track of the tokens it waits on.
+    pub(crate) fn of(deadline: Deadline) -> Self {
+        let mut tokens = Vec::new();
+        deadline.kind.tokens(&mut tokens);
+        SharedDeadline::with_tokens(deadline, tokens)
+    }
+
+    fn with_tokens<F>(future: F, tokens: Vec<StopToken>) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        SharedDeadline {
+            shared: Arc::new(Shared {
+                future: Mutex::new(Box::pin(future)),
+                tokens,
+                done: AtomicBool::new(false),
+                wakers: Mutex::new(WakerList::default()),
+            }),
+            slot: None,
+        }
+    }
+}
+
+impl Clone for SharedDeadline {
     fn clone(&self) -> Self {
-        Self {
-            kind: self.kind.clone(),
+        SharedDeadline {
+            shared: self.shared.clone(),
+            slot: None,
         }
     }
 }
 
-impl Future for Deadline {
+impl Future for SharedDeadline {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match self.project().kind.project() {
-            DeadlineKindProj::StopToken { t } => t.poll(cx),
-            #[cfg(feature = "tokio")]
-            DeadlineKindProj::Tokio { t } => t.poll(cx),
-            #[cfg(feature = "async-io")]
-            DeadlineKindProj::AsyncIo { t } => t.poll(cx),
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.shared.done.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+
+        // Register before polling, so a wakeup in between isn't lost.
+        this.shared
+            .wakers
+            .lock()
+            .register(&mut this.slot, cx.waker());
+
+        let waker = Waker::from(this.shared.clone());
+        let mut future = this.shared.future.lock();
+        if future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+        {
+            this.shared.done.store(true, Ordering::Release);
+            drop(future);
+            this.shared.wake_all();
+            return Poll::Ready(());
         }
+        Poll::Pending
+    }
+}
+
+impl Drop for SharedDeadline {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.shared.wakers.lock().remove(slot);
+        }
+    }
+}
+
+impl Shared {
+    fn wake_all(&self) {
+        let wakers = self.wakers.lock().take_all();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl Wake for Shared {
+    fn wake(self: Arc<Self>) {
+        self.wake_all();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_all();
     }
 }
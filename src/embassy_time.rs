@@ -0,0 +1,71 @@
+//! Create deadlines with `embassy-time`.
+//!
+//! This lets embedded async firmware use `Instant` and `Duration` deadlines
+//! from `embassy-time`, along with `StopToken`, without `std`.
+//!
+//! # Features
+//!
+//! This module is only available when the `embassy-time` feature is enabled.
+//! A time driver must be linked in, as with any use of `embassy-time`.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use embassy_time::Duration;
+//! use stop_token::prelude::*;
+//!
+//! #[embassy_executor::task]
+//! async fn blink(mut led: Output<'static>, token: StopToken) {
+//!     let mut ticks = Ticker::every(Duration::from_millis(500));
+//!     while ticks.next().timeout_at(token.clone()).await.is_ok() {
+//!         led.toggle();
+//!     }
+//! }
+//! ```
+
+use core::convert::TryFrom;
+
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::clock::Clock;
+
+/// The clock of the `embassy-time` backend.
+///
+/// See [`Clock`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    type Instant = Instant;
+    type Sleep = Timer;
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn remaining(&self, deadline: Instant) -> Option<core::time::Duration> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        Some(core::time::Duration::from_micros(remaining.as_micros()))
+    }
+
+    fn instant_after(&self, duration: core::time::Duration) -> Option<Instant> {
+        let micros = u64::try_from(duration.as_micros()).ok()?;
+        Instant::now().checked_add(Duration::from_micros(micros))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Timer {
+        Timer::at(deadline)
+    }
+}
+
+impl From<Instant> for crate::Deadline {
+    fn from(instant: Instant) -> crate::Deadline {
+        EmbassyClock.deadline(instant)
+    }
+}
+
+impl From<Duration> for crate::Deadline {
+    fn from(duration: Duration) -> crate::Deadline {
+        EmbassyClock.deadline(Instant::now() + duration)
+    }
+}
@@ -1,11 +1,15 @@
 //! Extension methods and types for the `Future` trait.
 
-use crate::{deadline::TimedOutError, Deadline};
+use crate::deadline::{DeadlineKind, SharedDeadline, TimedOutError};
+use crate::Deadline;
+use alloc::vec::Vec;
 use core::future::Future;
 use core::pin::Pin;
 
+use core::task::{Context, Poll};
+use futures_core::future::FusedFuture;
+use futures_core::ready;
 use pin_project_lite::pin_project;
-use std::task::{Context, Poll};
 
 /// Extend the `Future` trait with the `until` method.
 pub trait FutureExt: Future {
@@ -18,16 +22,221 @@ pub trait FutureExt: Future {
         TimeoutAt {
             deadline: target.into(),
             future: self,
+            done: false,
+        }
+    }
+
+    /// Run a future until it resolves, or until a deadline is hit.
+    ///
+    /// This is another name for [`FutureExt::timeout_at`].
+    fn until<T>(self, target: T) -> TimeoutAt<Self>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+    {
+        self.timeout_at(target)
+    }
+
+    /// Run a fallible future until it resolves, or until a deadline is hit,
+    /// converting the timeout into the future's own error type.
+    ///
+    /// This saves flattening `Result<Result<T, E>, TimedOutError>` by hand.
+    ///
+    /// ```
+    /// use std::io;
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopToken;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let read = std::future::pending::<io::Result<Vec<u8>>>();
+    /// let err = read.try_until(StopToken::already_cancelled()).await.unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    /// # });
+    /// ```
+    fn try_until<D, T, E>(self, target: D) -> TryUntil<Self>
+    where
+        Self: Future<Output = Result<T, E>> + Sized,
+        D: Into<Deadline>,
+        E: From<TimedOutError>,
+    {
+        TryUntil {
+            inner: self.timeout_at(target),
+        }
+    }
+
+    /// Run a future until it resolves, or until a deadline is hit, running
+    /// `on_cancel` if the deadline is hit.
+    ///
+    /// `on_cancel` runs exactly once, synchronously, before the error is
+    /// returned. This suits releasing resources which must be released on
+    /// cancellation, such as unlocking or aborting a transaction.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopToken;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let mut aborted = false;
+    /// let result = std::future::pending::<()>()
+    ///     .until_with(StopToken::already_cancelled(), |_| aborted = true)
+    ///     .await;
+    /// assert!(result.is_err());
+    /// assert!(aborted);
+    /// # });
+    /// ```
+    fn until_with<T, C>(self, target: T, on_cancel: C) -> UntilWith<Self, C>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+        C: FnOnce(&TimedOutError),
+    {
+        UntilWith {
+            inner: self.timeout_at(target),
+            on_cancel: Some(on_cancel),
+        }
+    }
+
+    /// Run a future until it resolves, or until a deadline is hit, driving
+    /// `cleanup` to completion if the deadline is hit.
+    ///
+    /// Once the deadline is hit this future stops being polled, and the error
+    /// is only returned after `cleanup` has completed. `cleanup` isn't polled
+    /// at all if this future resolves first.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopToken;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let result = std::future::pending::<()>()
+    ///     .until_then(StopToken::already_cancelled(), async {
+    ///         // .. flush buffers, say goodbye to peers ..
+    ///     })
+    ///     .await;
+    /// assert!(result.is_err());
+    /// # });
+    /// ```
+    fn until_then<T, C>(self, target: T, cleanup: C) -> UntilThen<Self, C>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+        C: Future<Output = ()>,
+    {
+        UntilThen {
+            inner: self.timeout_at(target),
+            cleanup,
+            stopped: None,
+            done: false,
+        }
+    }
+
+    /// Run a future until it resolves, or until a deadline is hit, treating
+    /// both as normal outcomes.
+    ///
+    /// This is [`FutureExt::timeout_at`] for code where stopping isn't an
+    /// error, such as waiting for either the next message or shutdown.
+    ///
+    /// ```
+    /// use stop_token::future::Outcome;
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopToken;
+    ///
+    /// # async_std::task::block_on(async {
+    /// match std::future::pending::<u8>().until_or(StopToken::already_cancelled()).await {
+    ///     Outcome::Completed(message) => println!("received {}", message),
+    ///     Outcome::Stopped(_) => println!("shutting down"),
+    /// }
+    /// # });
+    /// ```
+    fn until_or<T>(self, target: T) -> UntilOr<Self>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+    {
+        UntilOr {
+            inner: self.timeout_at(target),
+        }
+    }
+
+    /// Make `target` the deadline budget of this future.
+    ///
+    /// While this future is polled, [`current_deadline`](crate::current_deadline)
+    /// returns the budget, so nested calls can inherit it. A budget which is
+    /// already set by an enclosing `with_deadline` still applies: the
+    /// earliest of the two is used. This doesn't time the future out by
+    /// itself, combine it with [`FutureExt::timeout_at`] for that.
+    #[cfg(feature = "std")]
+    fn with_deadline<T>(self, target: T) -> WithDeadline<Self>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+    {
+        WithDeadline {
+            future: self,
+            deadline: target.into(),
+            effective: None,
+        }
+    }
+
+    /// Run `f` if this future is dropped before it completes.
+    ///
+    /// Cooperative cancellation relies on futures running to completion after
+    /// their token has stopped. This detects futures which were dropped
+    /// instead, for example to log a warning or count them in a metric.
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use stop_token::prelude::*;
+    ///
+    /// static DROPPED: AtomicBool = AtomicBool::new(false);
+    ///
+    /// let work = std::future::pending::<()>().on_drop_incomplete(|| {
+    ///     DROPPED.store(true, Ordering::Relaxed);
+    /// });
+    /// drop(work);
+    /// assert!(DROPPED.load(Ordering::Relaxed));
+    /// ```
+    fn on_drop_incomplete<D>(self, f: D) -> OnDropIncomplete<Self, D>
+    where
+        Self: Sized,
+        D: FnOnce(),
+    {
+        OnDropIncomplete {
+            future: self,
+            on_drop: Some(f),
         }
     }
 }
 
 impl<F: Future> FutureExt for F {}
 
+/// Wrap a batch of futures with the same deadline.
+///
+/// This is equivalent to calling [`FutureExt::timeout_at`] on each future with
+/// a clone of the deadline, except that the wrapped futures share a single
+/// underlying timer registration instead of registering one timer each.
+pub fn wrap_all<I, T>(futures: I, target: T) -> Vec<TimeoutAt<I::Item>>
+where
+    I: IntoIterator,
+    I::Item: Future,
+    T: Into<Deadline>,
+{
+    let deadline = target.into();
+    let label = deadline.label();
+    let mut deadline = Deadline::from_kind(DeadlineKind::Shared {
+        t: SharedDeadline::of(deadline),
+    });
+    deadline.label = label;
+    futures
+        .into_iter()
+        .map(|future| future.timeout_at(deadline.clone()))
+        .collect()
+}
+
 pin_project! {
     /// Run a future until it resolves, or until a deadline is hit.
     ///
-    /// This method is returned by [`FutureExt::deadline`].
+    /// This future is returned by [`FutureExt::timeout_at`].
     #[must_use = "Futures do nothing unless polled or .awaited"]
     #[derive(Debug)]
     pub struct TimeoutAt<F> {
@@ -35,6 +244,58 @@ pin_project! {
         future: F,
         #[pin]
         deadline: Deadline,
+        done: bool,
+    }
+}
+
+impl<F> TimeoutAt<F> {
+    /// Returns the deadline this future runs until.
+    ///
+    /// This lets code inspect the budget left, see [`Deadline::remaining`].
+    pub fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    /// Replaces the deadline this future runs until, keeping the state of
+    /// the underlying future.
+    ///
+    /// This lets the timeout be extended while the future is in flight.
+    pub fn set_deadline<T: Into<Deadline>>(self: Pin<&mut Self>, target: T) {
+        self.project().deadline.set(target.into());
+    }
+
+    /// Maps the timeout error into another error type, such as a variant of
+    /// a domain error enum.
+    ///
+    /// `f` is handed the [`TimedOutError`], so its label and timings can be
+    /// carried into the new error.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopToken;
+    ///
+    /// #[derive(Debug, PartialEq)]
+    /// enum Error {
+    ///     Deadline(Option<&'static str>),
+    /// }
+    ///
+    /// # async_std::task::block_on(async {
+    /// let deadline = stop_token::Deadline::elapsed().labelled("render");
+    /// let result = async { 1 }
+    ///     .until(deadline)
+    ///     .map_timeout(|err| Error::Deadline(err.label()))
+    ///     .await;
+    /// assert_eq!(result, Err(Error::Deadline(Some("render"))));
+    /// # });
+    /// ```
+    pub fn map_timeout<M, E>(self, f: M) -> MapTimeout<F, M>
+    where
+        M: FnOnce(TimedOutError) -> E,
+    {
+        MapTimeout {
+            inner: self,
+            f: Some(f),
+        }
     }
 }
 
@@ -45,13 +306,396 @@ where
     type Output = Result<F::Output, TimedOutError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
-        if let Poll::Ready(()) = this.deadline.poll(cx) {
-            return Poll::Ready(Err(TimedOutError::new()));
+        let mut this = self.project();
+        if *this.done {
+            // Like `futures::future::Fuse`, never resolve twice.
+            return Poll::Pending;
+        }
+        if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+            *this.done = true;
+            return Poll::Ready(Err(this.deadline.timed_out()));
         }
         match this.future.poll(cx) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready(it) => Poll::Ready(Ok(it)),
+            Poll::Ready(it) => {
+                *this.done = true;
+                Poll::Ready(Ok(it))
+            }
+        }
+    }
+}
+
+impl<F: Future> FusedFuture for TimeoutAt<F> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+pin_project! {
+    /// Run a future until it resolves, or until a deadline is hit, mapping the
+    /// timeout error.
+    ///
+    /// This future is returned by [`TimeoutAt::map_timeout`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct MapTimeout<F, M> {
+        #[pin]
+        inner: TimeoutAt<F>,
+        f: Option<M>,
+    }
+}
+
+impl<F, M, E> FusedFuture for MapTimeout<F, M>
+where
+    F: Future,
+    M: FnOnce(TimedOutError) -> E,
+{
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+impl<F, M, E> Future for MapTimeout<F, M>
+where
+    F: Future,
+    M: FnOnce(TimedOutError) -> E,
+{
+    type Output = Result<F::Output, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        match ready!(this.inner.poll(cx)) {
+            Ok(output) => Poll::Ready(Ok(output)),
+            Err(err) => {
+                let f = this.f.take().expect("`MapTimeout` polled after completion");
+                Poll::Ready(Err(f(err)))
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Run a fallible future until it resolves, or until a deadline is hit.
+    ///
+    /// This future is returned by [`FutureExt::try_until`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct TryUntil<F> {
+        #[pin]
+        inner: TimeoutAt<F>,
+    }
+}
+
+impl<F, T, E> FusedFuture for TryUntil<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<TimedOutError>,
+{
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+impl<F, T, E> Future for TryUntil<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<TimedOutError>,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match ready!(self.project().inner.poll(cx)) {
+            Ok(result) => Poll::Ready(result),
+            Err(err) => Poll::Ready(Err(err.into())),
+        }
+    }
+}
+
+pin_project! {
+    /// Run a future until it resolves, or until a deadline is hit, running a
+    /// closure if the deadline is hit.
+    ///
+    /// This future is returned by [`FutureExt::until_with`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct UntilWith<F, C> {
+        #[pin]
+        inner: TimeoutAt<F>,
+        on_cancel: Option<C>,
+    }
+}
+
+impl<F, C> FusedFuture for UntilWith<F, C>
+where
+    F: Future,
+    C: FnOnce(&TimedOutError),
+{
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+impl<F, C> Future for UntilWith<F, C>
+where
+    F: Future,
+    C: FnOnce(&TimedOutError),
+{
+    type Output = Result<F::Output, TimedOutError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        if let Err(err) = &result {
+            if let Some(on_cancel) = this.on_cancel.take() {
+                on_cancel(err);
+            }
+        }
+        Poll::Ready(result)
+    }
+}
+
+pin_project! {
+    /// Run a future until it resolves, or until a deadline is hit, followed
+    /// by a cleanup future if the deadline is hit.
+    ///
+    /// This future is returned by [`FutureExt::until_then`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct UntilThen<F, C> {
+        #[pin]
+        inner: TimeoutAt<F>,
+        #[pin]
+        cleanup: C,
+        stopped: Option<TimedOutError>,
+        done: bool,
+    }
+}
+
+impl<F, C> Future for UntilThen<F, C>
+where
+    F: Future,
+    C: Future<Output = ()>,
+{
+    type Output = Result<F::Output, TimedOutError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if *this.done {
+            // The cleanup future has completed, and must not be polled again.
+            return Poll::Pending;
+        }
+        let err = match this.stopped {
+            Some(err) => *err,
+            None => match ready!(this.inner.poll(cx)) {
+                Ok(output) => {
+                    *this.done = true;
+                    return Poll::Ready(Ok(output));
+                }
+                Err(err) => *this.stopped.insert(err),
+            },
+        };
+        ready!(this.cleanup.poll(cx));
+        *this.done = true;
+        Poll::Ready(Err(err))
+    }
+}
+
+impl<F, C> FusedFuture for UntilThen<F, C>
+where
+    F: Future,
+    C: Future<Output = ()>,
+{
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+/// The outcome of a future run with [`FutureExt::until_or`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Outcome<T> {
+    /// The future resolved before the deadline was hit.
+    Completed(T),
+    /// The deadline was hit first.
+    Stopped(TimedOutError),
+}
+
+impl<T> Outcome<T> {
+    /// Converts the outcome into the result [`FutureExt::timeout_at`] returns.
+    pub fn into_result(self) -> Result<T, TimedOutError> {
+        match self {
+            Outcome::Completed(output) => Ok(output),
+            Outcome::Stopped(err) => Err(err),
+        }
+    }
+}
+
+pin_project! {
+    /// Run a future until it resolves, or until a deadline is hit.
+    ///
+    /// This future is returned by [`FutureExt::until_or`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct UntilOr<F> {
+        #[pin]
+        inner: TimeoutAt<F>,
+    }
+}
+
+impl<F: Future> FusedFuture for UntilOr<F> {
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
+}
+
+impl<F: Future> Future for UntilOr<F> {
+    type Output = Outcome<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match ready!(self.project().inner.poll(cx)) {
+            Ok(output) => Poll::Ready(Outcome::Completed(output)),
+            Err(err) => Poll::Ready(Outcome::Stopped(err)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pin_project! {
+    /// Run a future with a deadline budget.
+    ///
+    /// This future is returned by [`FutureExt::with_deadline`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct WithDeadline<F> {
+        #[pin]
+        future: F,
+        deadline: Deadline,
+        // The deadline combined with the enclosing budget, set on first poll.
+        effective: Option<Deadline>,
+    }
+}
+
+#[cfg(feature = "std")]
+impl<F: Future> Future for WithDeadline<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let deadline = &*this.deadline;
+        let effective = this
+            .effective
+            .get_or_insert_with(|| match crate::current_deadline() {
+                Some(outer) => {
+                    let mut earliest = Deadline::earliest([outer, deadline.clone()]);
+                    earliest.label = deadline.label();
+                    earliest
+                }
+                None => deadline.clone(),
+            });
+        let _entered = crate::budget::enter(effective.clone());
+        this.future.poll(cx)
+    }
+}
+
+pin_project! {
+    /// Run a closure if a future is dropped before it completes.
+    ///
+    /// This future is returned by [`FutureExt::on_drop_incomplete`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct OnDropIncomplete<F, D>
+    where
+        D: FnOnce(),
+    {
+        #[pin]
+        future: F,
+        on_drop: Option<D>,
+    }
+
+    impl<F, D> PinnedDrop for OnDropIncomplete<F, D>
+    where
+        D: FnOnce(),
+    {
+        fn drop(this: Pin<&mut Self>) {
+            if let Some(f) = this.project().on_drop.take() {
+                f();
+            }
+        }
+    }
+}
+
+impl<F, D> Future for OnDropIncomplete<F, D>
+where
+    F: Future,
+    D: FnOnce(),
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = ready!(this.future.poll(cx));
+        *this.on_drop = None;
+        Poll::Ready(output)
+    }
+}
+
+cfg_duration_backend! {
+    /// Sleep for `duration`, waking up early once a token stops.
+    ///
+    /// Resolves to `Ok(())` if the whole duration has elapsed, and to the
+    /// timeout error of `stop` if it was hit first. This is the sleep of a
+    /// backoff loop which must not hold up shutdown:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use stop_token::future::sleep_until_stopped;
+    /// use stop_token::StopSource;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let shutdown = StopSource::new();
+    /// let token = shutdown.token();
+    /// assert!(sleep_until_stopped(Duration::from_millis(1), token.clone()).await.is_ok());
+    ///
+    /// drop(shutdown);
+    /// assert!(sleep_until_stopped(Duration::from_secs(60), token).await.is_err());
+    /// # }
+    /// ```
+    ///
+    /// This function is only available when a backend which can count down
+    /// a `Duration` is enabled.
+    pub fn sleep_until_stopped<T>(duration: core::time::Duration, stop: T) -> SleepUntilStopped
+    where
+        T: Into<Deadline>,
+    {
+        SleepUntilStopped {
+            sleep: Deadline::after(duration),
+            stop: stop.into(),
+        }
+    }
+
+    pin_project! {
+        /// Sleep for a duration, or until a token stops.
+        ///
+        /// This future is returned by [`sleep_until_stopped`].
+        #[must_use = "Futures do nothing unless polled or .awaited"]
+        #[derive(Debug)]
+        pub struct SleepUntilStopped {
+            #[pin]
+            sleep: Deadline,
+            #[pin]
+            stop: Deadline,
+        }
+    }
+
+    impl Future for SleepUntilStopped {
+        type Output = Result<(), TimedOutError>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut this = self.project();
+            if let Poll::Ready(()) = this.stop.as_mut().poll(cx) {
+                return Poll::Ready(Err(this.stop.timed_out()));
+            }
+            this.sleep.poll(cx).map(Ok)
         }
     }
 }
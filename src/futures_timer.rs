@@ -0,0 +1,103 @@
+//! Create deadlines with `futures-timer`.
+//!
+//! `futures-timer` runs its timers on a helper thread, so it works with any
+//! executor, including the bare `futures` ones, without pulling in the
+//! `async-io` or `tokio` reactors.
+//!
+//! # Features
+//!
+//! This module is only available when the `futures-timer` feature is
+//! enabled. It implements `Into<Deadline>` for `Instant` and `Duration`,
+//! unless the `async-io` feature is enabled as well, in which case those
+//! deadlines use `async-io`; [`FuturesTimerClock`] can still be used
+//! explicitly.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use stop_token::prelude::*;
+//!
+//! # async_std::task::block_on(async {
+//! let work = std::future::pending::<()>().timeout_at(Duration::from_millis(10));
+//! assert!(work.await.is_err());
+//! # });
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use ::futures_timer::Delay;
+
+/// The clock of the `futures-timer` backend.
+///
+/// See [`Clock`](crate::clock::Clock).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FuturesTimerClock;
+
+impl crate::clock::Clock for FuturesTimerClock {
+    type Instant = Instant;
+    type Sleep = Sleep;
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn remaining(&self, deadline: Instant) -> Option<std::time::Duration> {
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn instant_after(&self, duration: std::time::Duration) -> Option<Instant> {
+        Instant::now().checked_add(duration)
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Sleep {
+            delay: Delay::new(deadline.saturating_duration_since(Instant::now())),
+        }
+    }
+}
+
+/// A future which completes at an instant.
+///
+/// This future is returned by [`FuturesTimerClock`]'s `sleep_until`.
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct Sleep {
+    delay: Delay,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.delay).poll(cx)
+    }
+}
+
+#[cfg(not(feature = "async-io"))]
+impl From<Instant> for crate::Deadline {
+    fn from(instant: Instant) -> crate::Deadline {
+        use crate::clock::Clock;
+        #[cfg(feature = "test-util")]
+        if let Some(deadline) =
+            crate::test::mocked(|| instant.saturating_duration_since(Instant::now()))
+        {
+            return deadline;
+        }
+        FuturesTimerClock.deadline(instant)
+    }
+}
+
+#[cfg(not(feature = "async-io"))]
+impl From<std::time::Duration> for crate::Deadline {
+    fn from(duration: std::time::Duration) -> crate::Deadline {
+        #[cfg(feature = "test-util")]
+        if let Some(deadline) = crate::test::mocked(|| duration) {
+            return deadline;
+        }
+        (Instant::now() + duration).into()
+    }
+}
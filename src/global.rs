@@ -0,0 +1,46 @@
+//! A process-wide `StopSource`.
+//!
+//! Binaries often need a single, app-wide shutdown signal. Rather than
+//! threading a token through every constructor, any part of the program can
+//! grab a token from this module, and whoever handles shutdown calls
+//! [`cancel`].
+//!
+//! ```
+//! use stop_token::prelude::*;
+//!
+//! # async_std::task::block_on(async {
+//! let work = std::future::pending::<()>().timeout_at(stop_token::global::token());
+//!
+//! stop_token::global::cancel();
+//! assert!(work.await.is_err());
+//! # });
+//! ```
+//!
+//! The global source is created on first use and is never dropped, so its
+//! tokens only complete once [`cancel`] is called.
+
+use std::sync::OnceLock;
+
+use crate::{StopSource, StopToken};
+
+fn source() -> &'static StopSource {
+    static SOURCE: OnceLock<StopSource> = OnceLock::new();
+    SOURCE.get_or_init(StopSource::new)
+}
+
+/// Produces a new `StopToken`, associated with the global source.
+pub fn token() -> StopToken {
+    source().token()
+}
+
+/// Cancels all tokens of the global source.
+///
+/// Cancellation is permanent: tokens produced afterwards are cancelled too.
+pub fn cancel() {
+    source().cancel();
+}
+
+/// Returns `true` if the global source has been cancelled.
+pub fn is_cancelled() -> bool {
+    source().is_cancelled()
+}
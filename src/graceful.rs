@@ -0,0 +1,154 @@
+use alloc::sync::Arc;
+
+use crate::{StopSource, StopToken};
+
+/// How far a [`GracefulSource`] has gone in stopping its tokens.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum StopLevel {
+    /// No stop has been requested.
+    Running,
+    /// A graceful stop has been requested: work in progress should be
+    /// finished, but no new work should be started.
+    Soft,
+    /// A hard stop has been requested: work should be abandoned right away.
+    Hard,
+}
+
+/// A source which can first request a graceful stop, and later force a hard
+/// stop.
+///
+/// Its tokens carry both signals, see [`GracefulToken`]. A hard stop implies
+/// a soft stop, so code which only looks at the soft token stops either way.
+/// Dropping a `GracefulSource` forces a hard stop.
+///
+/// ```
+/// use stop_token::{GracefulSource, StopLevel};
+///
+/// let source = GracefulSource::new();
+/// let token = source.token();
+/// assert_eq!(token.level(), StopLevel::Running);
+///
+/// source.cancel_soft();
+/// assert_eq!(token.level(), StopLevel::Soft);
+///
+/// drop(source);
+/// assert_eq!(token.level(), StopLevel::Hard);
+/// ```
+#[derive(Debug)]
+pub struct GracefulSource {
+    soft: StopSource,
+    hard: Arc<StopSource>,
+}
+
+impl Default for GracefulSource {
+    fn default() -> GracefulSource {
+        let hard = Arc::new(StopSource::new());
+        let soft = StopSource::new();
+        soft.attach_to(&hard.token());
+        GracefulSource { soft, hard }
+    }
+}
+
+impl GracefulSource {
+    /// Creates a new `GracefulSource`.
+    pub fn new() -> GracefulSource {
+        GracefulSource::default()
+    }
+
+    /// Produces a new `GracefulToken`, associated with this source.
+    pub fn token(&self) -> GracefulToken {
+        GracefulToken {
+            soft: self.soft.token(),
+            hard: self.hard.token(),
+        }
+    }
+
+    /// Requests a graceful stop, completing the soft tokens.
+    pub fn cancel_soft(&self) {
+        self.soft.cancel();
+    }
+
+    /// Forces a hard stop, completing both the soft and the hard tokens.
+    pub fn cancel_hard(&self) {
+        self.hard.cancel();
+    }
+
+    /// Requests a graceful stop now, and forces a hard stop once `grace` has
+    /// elapsed.
+    ///
+    /// The grace period is timed on a background thread, which exits early
+    /// if the hard stop happens before then, for example because the source
+    /// is dropped.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use stop_token::GracefulSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = GracefulSource::new();
+    /// let token = source.token();
+    ///
+    /// source.cancel_with_grace(Duration::from_millis(10));
+    /// token.soft().await;
+    /// // .. drain the work in flight ..
+    /// token.hard().await;
+    /// # });
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn cancel_with_grace(&self, grace: std::time::Duration) {
+        self.cancel_soft();
+        let hard = Arc::downgrade(&self.hard);
+        let stopped = self.hard.token();
+        std::thread::Builder::new()
+            .name("stop-token-grace".into())
+            .spawn(move || {
+                if crate::utils::block_on_timeout(stopped, grace).is_none() {
+                    if let Some(hard) = hard.upgrade() {
+                        hard.cancel();
+                    }
+                }
+            })
+            .expect("failed to spawn the grace period thread");
+    }
+
+    /// Returns how far this source has gone in stopping its tokens.
+    pub fn level(&self) -> StopLevel {
+        self.token().level()
+    }
+}
+
+/// A pair of tokens produced by a [`GracefulSource`].
+///
+/// The soft token stops once a graceful stop is requested, and the hard token
+/// once the stop is forced. Stream combinators such as
+/// [`StreamExt::timeout_at_graceful`](crate::stream::StreamExt::timeout_at_graceful)
+/// take both, finishing the current item on a soft stop and bailing out on a
+/// hard stop.
+#[derive(Debug, Clone)]
+pub struct GracefulToken {
+    soft: StopToken,
+    hard: StopToken,
+}
+
+impl GracefulToken {
+    /// Returns the token which stops once a graceful stop is requested.
+    pub fn soft(&self) -> StopToken {
+        self.soft.clone()
+    }
+
+    /// Returns the token which stops once the stop is forced.
+    pub fn hard(&self) -> StopToken {
+        self.hard.clone()
+    }
+
+    /// Returns how far the source has gone in stopping this token.
+    pub fn level(&self) -> StopLevel {
+        if self.hard.is_stopped() {
+            StopLevel::Hard
+        } else if self.soft.is_stopped() {
+            StopLevel::Soft
+        } else {
+            StopLevel::Running
+        }
+    }
+}
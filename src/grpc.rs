@@ -0,0 +1,107 @@
+//! Propagate deadlines across RPC hops with the `grpc-timeout` header.
+//!
+//! gRPC carries the time left for a call in a header, as a positive integer of
+//! at most 8 digits followed by a unit: `H`, `M`, `S`, `m`, `u` or `n`, for
+//! hours, minutes, seconds, milliseconds, microseconds and nanoseconds. A
+//! client [`encode`]s its remaining budget into the header, and the server
+//! `decode`s it back into a deadline, given a time backend.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use stop_token::grpc;
+//!
+//! assert_eq!(grpc::decode_duration("150m"), Ok(Duration::from_millis(150)));
+//! assert!(grpc::decode_duration("1.5S").is_err());
+//! ```
+
+use alloc::string::String;
+use core::fmt;
+use core::time::Duration;
+
+use crate::Deadline;
+
+/// The name of the header carrying the timeout.
+pub const HEADER: &str = "grpc-timeout";
+
+const MAX_VALUE: u128 = 99_999_999;
+
+const UNITS: [(char, u128); 6] = [
+    ('n', 1),
+    ('u', 1_000),
+    ('m', 1_000_000),
+    ('S', 1_000_000_000),
+    ('M', 60 * 1_000_000_000),
+    ('H', 60 * 60 * 1_000_000_000),
+];
+
+/// An error returned when a `grpc-timeout` header value is malformed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InvalidTimeout {
+    _private: (),
+}
+
+impl fmt::Display for InvalidTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "invalid grpc-timeout header value".fmt(f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidTimeout {}
+
+/// Encodes the time left before `deadline` is hit as a header value.
+///
+/// The finest unit which fits is used, rounding up. Returns `None` when the
+/// deadline can't tell how much time is left, see [`Deadline::remaining`].
+///
+/// ```
+/// use stop_token::{grpc, Deadline};
+///
+/// assert_eq!(grpc::encode(&Deadline::elapsed()).as_deref(), Some("0n"));
+/// assert_eq!(grpc::encode(&Deadline::never()), None);
+/// ```
+pub fn encode(deadline: &Deadline) -> Option<String> {
+    deadline.remaining().map(encode_duration)
+}
+
+/// Encodes a duration as a header value, with the finest unit which fits,
+/// rounding up.
+pub fn encode_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    let (unit, value) = UNITS
+        .iter()
+        .map(|&(unit, scale)| (unit, nanos.div_ceil(scale)))
+        .find(|&(_, value)| value <= MAX_VALUE)
+        .unwrap_or(('H', MAX_VALUE));
+    alloc::format!("{}{}", value, unit)
+}
+
+/// Decodes a header value into a duration.
+pub fn decode_duration(value: &str) -> Result<Duration, InvalidTimeout> {
+    let invalid = InvalidTimeout { _private: () };
+    let unit = value.chars().last().ok_or(invalid)?;
+    let digits = &value[..value.len() - unit.len_utf8()];
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid);
+    }
+    let value: u64 = digits.parse().map_err(|_| invalid)?;
+    match unit {
+        'n' => Ok(Duration::from_nanos(value)),
+        'u' => Ok(Duration::from_micros(value)),
+        'm' => Ok(Duration::from_millis(value)),
+        'S' => Ok(Duration::from_secs(value)),
+        'M' => Ok(Duration::from_secs(value * 60)),
+        'H' => Ok(Duration::from_secs(value * 60 * 60)),
+        _ => Err(invalid),
+    }
+}
+
+cfg_duration_backend! {
+    /// Decodes a header value into a deadline, which is hit once the time it
+    /// carries has elapsed.
+    pub fn decode(value: &str) -> Result<Deadline, InvalidTimeout> {
+        decode_duration(value).map(Deadline::after)
+    }
+}
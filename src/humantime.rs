@@ -0,0 +1,34 @@
+cfg_duration_backend! {
+    use ::humantime::DurationError;
+
+    use crate::Deadline;
+
+    impl Deadline {
+        /// Parses a human-readable duration, such as `"30s"` or `"1h 30m"`,
+        /// into a deadline which is hit once that duration has elapsed.
+        ///
+        /// The format is that of [`humantime::parse_duration`], which makes
+        /// this handy for timeouts read from configuration files.
+        ///
+        /// ```
+        /// use stop_token::prelude::*;
+        /// use stop_token::Deadline;
+        ///
+        /// # async_std::task::block_on(async {
+        /// let deadline = Deadline::parse("10ms").unwrap();
+        /// assert!(std::future::pending::<()>().timeout_at(deadline).await.is_err());
+        ///
+        /// assert!(Deadline::parse("soon").is_err());
+        /// # });
+        /// ```
+        pub fn parse(s: &str) -> Result<Deadline, DurationError> {
+            ::humantime::parse_duration(s).map(Deadline::after)
+        }
+    }
+
+    impl From<::humantime::Duration> for Deadline {
+        fn from(duration: ::humantime::Duration) -> Deadline {
+            Deadline::after(duration.into())
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Shut `hyper` connections down gracefully.
+//!
+//! Stopping a server takes two steps: stop accepting connections, and then
+//! let the connections which are open finish the requests in flight. The
+//! first step is a matter of stopping the accept loop, for example with
+//! [`StreamExt::timeout_at`](crate::stream::StreamExt::timeout_at) on a
+//! stream of incoming connections. A [`StopToken`](crate::StopToken) is also a
+//! `Future<Output = ()>`, so it can be handed to hyper 0.14's
+//! `Server::with_graceful_shutdown` as is.
+//!
+//! [`GracefulConnection`] takes care of the second step. Once its shutdown
+//! token stops, it asks the connection to shut down gracefully: the requests
+//! in flight are answered, and then the connection is closed instead of
+//! being kept alive. Connections which are still busy once the drain timeout
+//! has elapsed, or once a hard stop is requested, are dropped.
+//!
+//! # Features
+//!
+//! This module is only available when the `hyper` feature is enabled, which
+//! also enables the `tokio` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::convert::Infallible;
+//! use std::time::Duration;
+//! use hyper::server::conn::http1;
+//! use hyper::service::service_fn;
+//! use hyper::{Request, Response};
+//! use hyper_util::rt::TokioIo;
+//! use stop_token::hyper::GracefulConnection;
+//! use stop_token::prelude::*;
+//! use stop_token::GracefulSource;
+//! use tokio::net::TcpListener;
+//!
+//! # async fn serve() -> std::io::Result<()> {
+//! let listener = TcpListener::bind("127.0.0.1:8080").await?;
+//! let shutdown = GracefulSource::new();
+//!
+//! while let Ok(Ok((stream, _))) = listener.accept().timeout_at(shutdown.token().soft()).await {
+//!     let service = service_fn(|_: Request<_>| async {
+//!         Ok::<_, Infallible>(Response::new(String::from("hello")))
+//!     });
+//!     let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+//!     let token = shutdown.token();
+//!     tokio::spawn(async move {
+//!         let conn = GracefulConnection::new(conn, token.soft())
+//!             .drain_timeout(Duration::from_secs(30))
+//!             .hard_stop(token.hard());
+//!         let _ = conn.await;
+//!     });
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::error::Error;
+use std::time::Duration;
+
+use ::hyper::body::{Body, Incoming};
+use ::hyper::rt::{Read, Write};
+use ::hyper::server::conn::http1;
+use ::hyper::service::HttpService;
+use futures_core::ready;
+use pin_project_lite::pin_project;
+
+use crate::{Deadline, TimedOutError};
+
+/// A connection which can be asked to shut down gracefully.
+///
+/// This is implemented for hyper's HTTP/1 connections. Other connections,
+/// such as HTTP/2 ones, can implement it by calling their own
+/// `graceful_shutdown` method.
+pub trait GracefulShutdown: Future {
+    /// Asks the connection to finish the requests in flight, and then to
+    /// close.
+    fn graceful_shutdown(self: Pin<&mut Self>);
+}
+
+impl<I, S, B> GracefulShutdown for http1::Connection<I, S>
+where
+    S: HttpService<Incoming, ResBody = B>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    I: Read + Write + Unpin,
+    B: Body + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn graceful_shutdown(self: Pin<&mut Self>) {
+        http1::Connection::graceful_shutdown(self)
+    }
+}
+
+impl<I, S, B> GracefulShutdown for http1::UpgradeableConnection<I, S>
+where
+    S: HttpService<Incoming, ResBody = B>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    I: Read + Write + Unpin + Send + 'static,
+    B: Body + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn graceful_shutdown(self: Pin<&mut Self>) {
+        http1::UpgradeableConnection::graceful_shutdown(self)
+    }
+}
+
+pin_project! {
+    /// Serve a connection until it closes, shutting it down gracefully once a
+    /// shutdown token stops.
+    ///
+    /// This future resolves to the connection's output, or to the timeout
+    /// error if the connection was dropped because the drain timeout elapsed
+    /// or a hard stop was requested. See the [module documentation](self).
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct GracefulConnection<C> {
+        #[pin]
+        conn: C,
+        shutdown: Deadline,
+        hard: Option<Deadline>,
+        drain: Option<Duration>,
+        draining: bool,
+    }
+}
+
+impl<C: GracefulShutdown> GracefulConnection<C> {
+    /// Serves `conn` until it closes, shutting it down gracefully once
+    /// `shutdown` is hit.
+    ///
+    /// Without a drain timeout or a hard stop, the connection is given all
+    /// the time it needs to answer the requests in flight.
+    pub fn new<T: Into<Deadline>>(conn: C, shutdown: T) -> GracefulConnection<C> {
+        GracefulConnection {
+            conn,
+            shutdown: shutdown.into(),
+            hard: None,
+            drain: None,
+            draining: false,
+        }
+    }
+
+    /// Drops the connection if it is still busy once `drain` has elapsed
+    /// since the shutdown token stopped.
+    pub fn drain_timeout(mut self, drain: Duration) -> GracefulConnection<C> {
+        self.drain = Some(drain);
+        self
+    }
+
+    /// Drops the connection once `target` is hit, whether or not it is
+    /// shutting down already, such as once
+    /// [`GracefulToken::hard`](crate::GracefulToken::hard) stops.
+    pub fn hard_stop<T: Into<Deadline>>(mut self, target: T) -> GracefulConnection<C> {
+        self.hard = Some(match self.hard.take() {
+            Some(hard) => Deadline::earliest([hard, target.into()]),
+            None => target.into(),
+        });
+        self
+    }
+}
+
+impl<C> GracefulConnection<C> {
+    /// Returns `true` if the shutdown token has stopped, and the connection is
+    /// finishing the requests in flight.
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+}
+
+impl<C: GracefulShutdown> Future for GracefulConnection<C> {
+    type Output = Result<C::Output, TimedOutError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        if !*this.draining && Pin::new(&mut *this.shutdown).poll(cx).is_ready() {
+            *this.draining = true;
+            this.conn.as_mut().graceful_shutdown();
+            if let Some(drain) = *this.drain {
+                let drain = Deadline::after(drain);
+                *this.hard = Some(match this.hard.take() {
+                    Some(hard) => Deadline::earliest([hard, drain]),
+                    None => drain,
+                });
+            }
+        }
+        if let Some(hard) = this.hard.as_mut() {
+            if Pin::new(&mut *hard).poll(cx).is_ready() {
+                return Poll::Ready(Err(hard.timed_out()));
+            }
+        }
+        let output = ready!(this.conn.poll(cx));
+        Poll::Ready(Ok(output))
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::utils::{Mutex, Timestamp};
+use crate::Deadline;
+
+/// A deadline which is hit once nothing has happened for a while.
+///
+/// Each call to [`IdleDeadline::touch`] pushes the expiry back by the idle
+/// timeout. Clones share the same activity, so a connection can hand one to
+/// its reader and another to its writer, and time out only once both go
+/// quiet.
+///
+/// ```
+/// use async_std::prelude::*;
+/// use std::time::Duration;
+/// use stop_token::prelude::*;
+/// use stop_token::IdleDeadline;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let idle = IdleDeadline::new(Duration::from_millis(10));
+/// let mut work = async_std::stream::repeat(1).timeout_at(idle.deadline());
+/// for _ in 0..3 {
+///     idle.touch();
+///     assert_eq!(work.next().await, Some(Ok(1)));
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct IdleDeadline {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    timeout: Duration,
+    last_touched: Mutex<Timestamp>,
+}
+
+impl IdleDeadline {
+    /// Creates a deadline which is hit once `timeout` passes without a call
+    /// to [`IdleDeadline::touch`], starting from now.
+    pub fn new(timeout: Duration) -> IdleDeadline {
+        IdleDeadline {
+            inner: Arc::new(Inner {
+                timeout,
+                last_touched: Mutex::new(Timestamp::now()),
+            }),
+        }
+    }
+
+    /// Records activity, pushing the expiry back by the idle timeout.
+    pub fn touch(&self) {
+        *self.inner.last_touched.lock() = Timestamp::now();
+    }
+
+    /// Returns the idle timeout.
+    pub fn timeout(&self) -> Duration {
+        self.inner.timeout
+    }
+
+    /// Creates a deadline which is hit once this idle deadline expires.
+    ///
+    /// The deadline keeps following calls to `touch` made after it was
+    /// created.
+    pub fn deadline(&self) -> Deadline {
+        let inner = self.inner.clone();
+        Deadline::from_future(async move {
+            loop {
+                let expiry = *inner.last_touched.lock() + inner.timeout;
+                let remaining = expiry.saturating_duration_since(Timestamp::now());
+                if remaining.is_zero() {
+                    return;
+                }
+                // Sleep until the expiry seen now, then check for activity.
+                Deadline::after(remaining).await;
+            }
+        })
+    }
+}
+
+impl From<&IdleDeadline> for Deadline {
+    fn from(idle: &IdleDeadline) -> Deadline {
+        idle.deadline()
+    }
+}
+
+impl From<IdleDeadline> for Deadline {
+    fn from(idle: IdleDeadline) -> Deadline {
+        idle.deadline()
+    }
+}
@@ -0,0 +1,658 @@
+//! Cancellation for IO-heavy code.
+
+use crate::stream::EndReason;
+use crate::Deadline;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use std::io;
+
+#[cfg(any(feature = "futures-io", feature = "tokio"))]
+use futures_core::ready;
+#[cfg(feature = "futures-io")]
+use futures_core::{stream::FusedStream, Stream};
+use pin_project_lite::pin_project;
+use std::task::{Context, Poll};
+
+/// Run an IO future until it resolves, or until a deadline is hit.
+///
+/// This mirrors `async_std::io::timeout`, but accepts anything that converts
+/// into a [`Deadline`], including a [`StopToken`](crate::StopToken). Hitting
+/// the deadline resolves to an error of kind [`io::ErrorKind::TimedOut`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io;
+/// use stop_token::StopSource;
+///
+/// # async_std::task::block_on(async {
+/// let source = StopSource::new();
+/// let token = source.token();
+/// drop(source);
+///
+/// let res = stop_token::io::timeout(token, async {
+///     async_std::task::sleep(std::time::Duration::from_secs(10)).await;
+///     Ok(())
+/// })
+/// .await;
+/// assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+/// # });
+/// ```
+pub fn timeout<T, F, D>(target: D, future: F) -> Timeout<F>
+where
+    F: Future<Output = io::Result<T>>,
+    D: Into<Deadline>,
+{
+    Timeout {
+        future,
+        deadline: target.into(),
+    }
+}
+
+pin_project! {
+    /// Run an IO future until it resolves, or until a deadline is hit.
+    ///
+    /// This future is returned by [`timeout`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct Timeout<F> {
+        #[pin]
+        future: F,
+        #[pin]
+        deadline: Deadline,
+    }
+}
+
+impl<F, T> Future for Timeout<F>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    type Output = io::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+            return Poll::Ready(Err(this.deadline.timed_out().into()));
+        }
+        this.future.poll(cx)
+    }
+}
+
+pin_project! {
+    /// A reader which fails with [`io::ErrorKind::TimedOut`] once a deadline
+    /// is hit.
+    ///
+    /// Wrapping a transport once makes every protocol layered on top of it
+    /// cancellable. The deadline is checked before each read, and reads which
+    /// have returned data are never affected, so no bytes are lost: a read
+    /// which is still pending hasn't taken any bytes yet. Once the deadline
+    /// has been hit, every read fails.
+    ///
+    /// `ReadUntil` implements `AsyncRead` from `futures-io` when the
+    /// `futures-io` feature is enabled, as used by `async-std` and `smol`,
+    /// and `AsyncRead` from `tokio` when the `tokio` feature is enabled.
+    #[derive(Debug)]
+    pub struct ReadUntil<R> {
+        #[pin]
+        reader: R,
+        #[pin]
+        deadline: Deadline,
+        stopped: bool,
+    }
+}
+
+impl<R> ReadUntil<R> {
+    /// Wraps `reader`, such that reading from it fails once `target` is hit.
+    pub fn new<T: Into<Deadline>>(reader: R, target: T) -> ReadUntil<R> {
+        ReadUntil {
+            reader,
+            deadline: target.into(),
+            stopped: false,
+        }
+    }
+
+    /// Returns the deadline this reader runs until.
+    pub fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    /// Returns `true` if the deadline has been hit, and reads fail.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Unwraps this reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Fails once the deadline has been hit, remembering that it has been.
+#[cfg(any(feature = "futures-io", feature = "tokio"))]
+fn check(
+    mut deadline: Pin<&mut Deadline>,
+    stopped: &mut bool,
+    cx: &mut Context<'_>,
+) -> io::Result<()> {
+    if !*stopped && deadline.as_mut().poll(cx).is_ready() {
+        *stopped = true;
+    }
+    if *stopped {
+        return Err(deadline.timed_out().into());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "futures-io")]
+impl<R: futures_io::AsyncRead> futures_io::AsyncRead for ReadUntil<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        check(this.deadline, this.stopped, cx)?;
+        this.reader.poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: ::tokio::io::AsyncRead> ::tokio::io::AsyncRead for ReadUntil<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ::tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.project();
+        check(this.deadline, this.stopped, cx)?;
+        this.reader.poll_read(cx, buf)
+    }
+}
+
+pin_project! {
+    /// A writer which shuts down and fails with [`io::ErrorKind::TimedOut`]
+    /// once a deadline is hit.
+    ///
+    /// The deadline is checked before each write. Once it has been hit, the
+    /// next write flushes and closes the underlying writer instead, so the
+    /// data written so far reaches the peer and the connection is closed
+    /// cleanly, and then fails. Writes which are still pending haven't taken
+    /// any bytes yet, so no write is cut in half.
+    ///
+    /// Like [`ReadUntil`], `WriteUntil` implements `AsyncWrite` from
+    /// `futures-io` when the `futures-io` feature is enabled, and
+    /// `AsyncWrite` from `tokio` when the `tokio` feature is enabled.
+    #[derive(Debug)]
+    pub struct WriteUntil<W> {
+        #[pin]
+        writer: W,
+        #[pin]
+        deadline: Deadline,
+        stopped: bool,
+        closed: bool,
+    }
+}
+
+impl<W> WriteUntil<W> {
+    /// Wraps `writer`, such that writing to it fails once `target` is hit.
+    pub fn new<T: Into<Deadline>>(writer: W, target: T) -> WriteUntil<W> {
+        WriteUntil {
+            writer,
+            deadline: target.into(),
+            stopped: false,
+            closed: false,
+        }
+    }
+
+    /// Returns the deadline this writer runs until.
+    pub fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    /// Returns `true` if the deadline has been hit, and writes fail.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Unwraps this writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<W: futures_io::AsyncWrite> futures_io::AsyncWrite for WriteUntil<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut this = self.project();
+        if let Err(err) = check(this.deadline, this.stopped, cx) {
+            if !*this.closed {
+                ready!(this.writer.as_mut().poll_flush(cx))?;
+                ready!(this.writer.poll_close(cx))?;
+                *this.closed = true;
+            }
+            return Poll::Ready(Err(err));
+        }
+        this.writer.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        if *this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        this.writer.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        if *this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(this.writer.poll_close(cx))?;
+        *this.closed = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<W: ::tokio::io::AsyncWrite> ::tokio::io::AsyncWrite for WriteUntil<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut this = self.project();
+        if let Err(err) = check(this.deadline, this.stopped, cx) {
+            if !*this.closed {
+                ready!(this.writer.as_mut().poll_flush(cx))?;
+                ready!(this.writer.poll_shutdown(cx))?;
+                *this.closed = true;
+            }
+            return Poll::Ready(Err(err));
+        }
+        this.writer.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        if *this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        this.writer.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        if *this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(this.writer.poll_shutdown(cx))?;
+        *this.closed = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Read lines from `reader` until it is exhausted, or until a deadline is hit.
+///
+/// The deadline is only honored at line boundaries: once a line has been
+/// started, it is read to its end even if the deadline is hit in between, so
+/// a partial line is never handed back, and no bytes of the next line are
+/// taken. The lines don't include their `\n` or `\r\n` endings.
+///
+/// The returned stream implements `Stream` for readers implementing
+/// `AsyncBufRead` from `futures-io` when the `futures-io` feature is enabled.
+/// For readers implementing `AsyncBufRead` from `tokio`, it provides
+/// `next_line` when the `tokio` feature is enabled. Once it has ended,
+/// [`LinesUntil::end_reason`] tells whether the reader was exhausted or the
+/// deadline was hit.
+pub fn lines_until<R, T>(reader: R, target: T) -> LinesUntil<R>
+where
+    T: Into<Deadline>,
+{
+    LinesUntil {
+        reader,
+        deadline: target.into(),
+        buf: Vec::new(),
+        reason: None,
+    }
+}
+
+pin_project! {
+    /// A stream of the lines of a reader, which ends at a line boundary once
+    /// a deadline is hit.
+    ///
+    /// This stream is returned by [`lines_until`].
+    #[must_use = "Streams do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct LinesUntil<R> {
+        #[pin]
+        reader: R,
+        #[pin]
+        deadline: Deadline,
+        buf: Vec<u8>,
+        reason: Option<EndReason>,
+    }
+}
+
+impl<R> LinesUntil<R> {
+    /// Returns why the stream ended, or `None` if it hasn't ended yet.
+    pub fn end_reason(&self) -> Option<EndReason> {
+        self.reason
+    }
+
+    /// Unwraps this stream, returning the underlying reader.
+    ///
+    /// Bytes of a line which has been started but not finished are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Moves the bytes up to and including the first `\n` of `available` into
+/// `buf`, returning how many bytes were moved and whether the line is
+/// complete.
+#[cfg(any(feature = "futures-io", feature = "tokio"))]
+fn take_line(buf: &mut Vec<u8>, available: &[u8]) -> (usize, bool) {
+    match available.iter().position(|&byte| byte == b'\n') {
+        Some(i) => {
+            buf.extend_from_slice(&available[..=i]);
+            (i + 1, true)
+        }
+        None => {
+            buf.extend_from_slice(available);
+            (available.len(), false)
+        }
+    }
+}
+
+/// Takes the line out of `buf`, without its line ending.
+#[cfg(any(feature = "futures-io", feature = "tokio"))]
+fn finish_line(buf: &mut Vec<u8>) -> io::Result<String> {
+    let mut line = core::mem::take(buf);
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    String::from_utf8(line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Polls the next line of a `LinesUntil`, for either family of IO traits.
+#[cfg(any(feature = "futures-io", feature = "tokio"))]
+macro_rules! poll_next_line {
+    ($this:ident, $cx:ident) => {
+        loop {
+            if $this.reason.is_some() {
+                return Poll::Ready(None);
+            }
+            if $this.buf.is_empty() && $this.deadline.as_mut().poll($cx).is_ready() {
+                *$this.reason = Some(EndReason::TimedOut);
+                return Poll::Ready(None);
+            }
+            let available = ready!($this.reader.as_mut().poll_fill_buf($cx))?;
+            if available.is_empty() {
+                *$this.reason = Some(EndReason::Exhausted);
+                if $this.buf.is_empty() {
+                    return Poll::Ready(None);
+                }
+                // The last line doesn't have to end with a newline.
+                return Poll::Ready(Some(finish_line($this.buf)));
+            }
+            let (used, complete) = take_line($this.buf, available);
+            $this.reader.as_mut().consume(used);
+            if complete {
+                return Poll::Ready(Some(finish_line($this.buf)));
+            }
+        }
+    };
+}
+
+#[cfg(feature = "futures-io")]
+impl<R: futures_io::AsyncBufRead> Stream for LinesUntil<R> {
+    type Item = io::Result<String>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        poll_next_line!(this, cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<R: futures_io::AsyncBufRead> FusedStream for LinesUntil<R> {
+    fn is_terminated(&self) -> bool {
+        self.reason.is_some()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: ::tokio::io::AsyncBufRead> LinesUntil<R> {
+    /// Returns the next line, or `None` once the reader is exhausted or the
+    /// deadline has been hit.
+    ///
+    /// Tokio's readers don't produce streams, so this mirrors
+    /// `tokio::io::Lines::next_line` instead.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>>
+    where
+        R: Unpin,
+    {
+        core::future::poll_fn(|cx| Pin::new(&mut *self).poll_next_line(cx)).await
+    }
+
+    /// Polls for the next line, or `None` once the reader is exhausted or the
+    /// deadline has been hit.
+    pub fn poll_next_line(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<Option<String>>> {
+        self.poll_line(cx).map(Option::transpose)
+    }
+
+    fn poll_line(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<String>>> {
+        let mut this = self.project();
+        poll_next_line!(this, cx)
+    }
+}
+
+/// Copy all bytes from `reader` into `writer` until the reader is exhausted,
+/// or until a deadline is hit.
+///
+/// The bytes are copied in chunks, and the deadline is only checked in
+/// between them, so a chunk which has been read is always written out in
+/// full. Once the deadline is hit, the writer is flushed and the copy fails
+/// with a [`CopyStopped`] error of kind [`io::ErrorKind::TimedOut`]. Either
+/// way, the number of bytes copied is reported, which is what a proxy
+/// needs to account for a connection.
+///
+/// This function is only available when the `futures-io` feature is enabled.
+///
+/// # Examples
+///
+/// ```
+/// # async_std::task::block_on(async {
+/// use async_std::io::Cursor;
+/// use stop_token::StopToken;
+///
+/// let mut written = Vec::new();
+/// let copied = stop_token::io::copy_until(Cursor::new(b"hello"), &mut written, StopToken::never()).await;
+/// assert_eq!(copied.unwrap(), 5);
+/// assert_eq!(written, b"hello");
+/// # });
+/// ```
+#[cfg(feature = "futures-io")]
+pub fn copy_until<R, W, T>(reader: R, writer: W, target: T) -> CopyUntil<R, W>
+where
+    R: futures_io::AsyncRead,
+    W: futures_io::AsyncWrite,
+    T: Into<Deadline>,
+{
+    CopyUntil {
+        reader,
+        writer,
+        deadline: target.into(),
+        buf: vec![0; 8 * 1024].into_boxed_slice(),
+        pos: 0,
+        cap: 0,
+        copied: 0,
+        stopped: None,
+    }
+}
+
+#[cfg(feature = "futures-io")]
+pin_project! {
+    /// Copy the bytes of a reader into a writer until the reader is
+    /// exhausted, or until a deadline is hit.
+    ///
+    /// This future is returned by [`copy_until`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    pub struct CopyUntil<R, W> {
+        #[pin]
+        reader: R,
+        #[pin]
+        writer: W,
+        #[pin]
+        deadline: Deadline,
+        buf: Box<[u8]>,
+        pos: usize,
+        cap: usize,
+        copied: u64,
+        stopped: Option<io::Error>,
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<R: fmt::Debug, W: fmt::Debug> fmt::Debug for CopyUntil<R, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CopyUntil")
+            .field("reader", &self.reader)
+            .field("writer", &self.writer)
+            .field("deadline", &self.deadline)
+            .field("copied", &self.copied)
+            .finish()
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<R, W> Future for CopyUntil<R, W>
+where
+    R: futures_io::AsyncRead,
+    W: futures_io::AsyncWrite,
+{
+    type Output = Result<u64, CopyStopped>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let stop = |copied, error| Poll::Ready(Err(CopyStopped { copied, error }));
+        loop {
+            if this.stopped.is_some() {
+                if let Err(err) = ready!(this.writer.as_mut().poll_flush(cx)) {
+                    return stop(*this.copied, err);
+                }
+                return stop(*this.copied, this.stopped.take().unwrap());
+            }
+
+            // In between two chunks.
+            if *this.pos == *this.cap {
+                if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+                    *this.stopped = Some(this.deadline.timed_out().into());
+                    continue;
+                }
+                match ready!(this.reader.as_mut().poll_read(cx, this.buf)) {
+                    Ok(0) => {
+                        if let Err(err) = ready!(this.writer.as_mut().poll_flush(cx)) {
+                            return stop(*this.copied, err);
+                        }
+                        return Poll::Ready(Ok(*this.copied));
+                    }
+                    Ok(n) => {
+                        *this.pos = 0;
+                        *this.cap = n;
+                    }
+                    Err(err) => return stop(*this.copied, err),
+                }
+            }
+
+            while *this.pos < *this.cap {
+                let chunk = &this.buf[*this.pos..*this.cap];
+                match ready!(this.writer.as_mut().poll_write(cx, chunk)) {
+                    Ok(0) => return stop(*this.copied, io::ErrorKind::WriteZero.into()),
+                    Ok(n) => {
+                        *this.pos += n;
+                        *this.copied += n as u64;
+                    }
+                    Err(err) => return stop(*this.copied, err),
+                }
+            }
+        }
+    }
+}
+
+/// The error returned by `copy_until` when the copy didn't complete.
+///
+/// It carries the number of bytes copied until then, along with the cause:
+/// an error of kind [`io::ErrorKind::TimedOut`] if the deadline was hit, or
+/// the error the reader or writer failed with.
+#[derive(Debug)]
+pub struct CopyStopped {
+    copied: u64,
+    error: io::Error,
+}
+
+impl CopyStopped {
+    /// Returns the number of bytes copied before the copy stopped.
+    pub fn copied(&self) -> u64 {
+        self.copied
+    }
+
+    /// Returns the error which stopped the copy.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+
+    /// Returns `true` if the copy stopped because the deadline was hit.
+    pub fn is_timeout(&self) -> bool {
+        self.error.kind() == io::ErrorKind::TimedOut
+    }
+
+    /// Unwraps this error, returning the error which stopped the copy.
+    pub fn into_error(self) -> io::Error {
+        self.error
+    }
+}
+
+impl fmt::Display for CopyStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "copy stopped after {} bytes: {}",
+            self.copied, self.error
+        )
+    }
+}
+
+impl std::error::Error for CopyStopped {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<CopyStopped> for io::Error {
+    fn from(err: CopyStopped) -> io::Error {
+        err.error
+    }
+}
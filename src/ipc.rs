@@ -0,0 +1,82 @@
+//! Share cancellation with other processes.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+use crate::utils::block_on;
+use crate::{StopSource, StopToken};
+
+impl StopSource {
+    /// Exports a file descriptor which becomes readable once this source is
+    /// cancelled or dropped.
+    ///
+    /// Hand the descriptor to another process, for example by letting a
+    /// forked worker inherit it, and build a token from it there with
+    /// [`StopToken::from_fd`], so a supervisor's shutdown reaches its
+    /// workers. The descriptor is one end of a Unix socket pair, and a byte
+    /// is written to the other end once the source stops. Reading it from a
+    /// thread, a reactor or `poll(2)` all work.
+    ///
+    /// Each call waits for the source on a background thread of its own,
+    /// which exits once the source stops.
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use stop_token::StopSource;
+    ///
+    /// let source = StopSource::new();
+    /// let mut fd = std::fs::File::from(source.export_fd()?);
+    ///
+    /// drop(source);
+    /// assert_eq!(fd.read(&mut [0; 8])?, 1);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn export_fd(&self) -> io::Result<OwnedFd> {
+        let (mut notify, exported) = UnixStream::pair()?;
+        let token = self.token();
+        thread::Builder::new()
+            .name("stop-token-export".into())
+            .spawn(move || {
+                block_on(token);
+                // The peer may be gone already, and then there's nobody to
+                // tell.
+                let _ = notify.write_all(&[1]);
+            })?;
+        Ok(exported.into())
+    }
+}
+
+impl StopToken {
+    /// Produces a `StopToken` which stops once `fd` becomes readable, for
+    /// example because another process cancelled the source it was exported
+    /// from with [`StopSource::export_fd`].
+    ///
+    /// Any descriptor which becomes readable on cancellation works, such as
+    /// the read end of a pipe or an `eventfd`. Reaching the end of the file,
+    /// for example because the other end was closed, stops the token as
+    /// well, and so does an error reading from `fd`.
+    ///
+    /// `fd` is read from a background thread, which exits once it becomes
+    /// readable.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<StopToken> {
+        let source = StopSource::new();
+        let token = source.token();
+        let mut file = File::from(fd);
+        thread::Builder::new()
+            .name("stop-token-fd".into())
+            .spawn(move || {
+                // Eight bytes, so that `eventfd`s can be read too.
+                let mut buf = [0; 8];
+                while let Err(err) = file.read(&mut buf) {
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        break;
+                    }
+                }
+                drop(source);
+            })?;
+        Ok(token)
+    }
+}
@@ -0,0 +1,159 @@
+//! Share cancellation with other processes on Windows.
+
+use std::io;
+use std::os::windows::io::OwnedHandle;
+use std::thread;
+
+use crate::utils::block_on;
+use crate::{StopSource, StopToken};
+
+impl StopSource {
+    /// Creates a named event which is set once this source is cancelled or
+    /// dropped, and which cancels this source once it is set.
+    ///
+    /// Another process can build a token from the event with
+    /// [`StopToken::open_event`], so a supervisor's shutdown reaches its
+    /// workers, or set the event itself to cancel this source. The event is a
+    /// manual-reset one, so it stays set for every process waiting on it. If
+    /// an event with this name exists already, it is used instead of creating
+    /// one, and if it is set already, the source is cancelled right away.
+    ///
+    /// The event lives as long as a handle to it is open, so keep the
+    /// returned handle around for as long as other processes may open it, or
+    /// let a child process inherit it.
+    ///
+    /// Each call waits for the source and the event on two background threads
+    /// of its own, which exit once the source stops.
+    ///
+    /// ```no_run
+    /// use stop_token::{StopSource, StopToken};
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// let _event = source.export_event("Local\\my-app-shutdown")?;
+    ///
+    /// // In another process:
+    /// let token = StopToken::open_event("Local\\my-app-shutdown")?;
+    /// # drop(source);
+    /// token.await;
+    /// # Ok::<(), std::io::Error>(())
+    /// # });
+    /// ```
+    pub fn export_event(&self, name: &str) -> io::Result<OwnedHandle> {
+        let event = sys::create_event(name)?;
+        let notify = event.try_clone()?;
+        let token = self.token();
+        thread::Builder::new()
+            .name("stop-token-export".into())
+            .spawn(move || {
+                block_on(token);
+                // There's nobody to tell if setting the event fails.
+                let _ = sys::set_event(&notify);
+            })?;
+        // Once the source stops, the thread above sets the event, so this
+        // wait always ends.
+        let wait = event.try_clone()?;
+        let trigger = self.trigger();
+        thread::Builder::new()
+            .name("stop-token-import".into())
+            .spawn(move || {
+                if sys::wait(&wait).is_ok() {
+                    trigger.cancel();
+                }
+            })?;
+        Ok(event)
+    }
+}
+
+impl StopToken {
+    /// Produces a `StopToken` which stops once the named event `name` is
+    /// set, for example because another process cancelled the source it was
+    /// exported from with [`StopSource::export_event`].
+    ///
+    /// The event has to exist already.
+    pub fn open_event(name: &str) -> io::Result<StopToken> {
+        StopToken::from_handle(sys::open_event(name)?)
+    }
+
+    /// Produces a `StopToken` which stops once `handle` is signaled.
+    ///
+    /// Any handle which can be waited on works, such as an event, or a
+    /// process, to stop once another process exits. An error waiting on
+    /// `handle` stops the token as well.
+    ///
+    /// `handle` is waited on from a background thread, which exits once it is
+    /// signaled.
+    pub fn from_handle(handle: OwnedHandle) -> io::Result<StopToken> {
+        let source = StopSource::new();
+        let token = source.token();
+        thread::Builder::new()
+            .name("stop-token-handle".into())
+            .spawn(move || {
+                let _ = sys::wait(&handle);
+                drop(source);
+            })?;
+        Ok(token)
+    }
+}
+
+/// Safe wrappers around the Win32 event functions.
+#[allow(unsafe_code)]
+mod sys {
+    use std::io;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::{HANDLE, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::Threading::{
+        CreateEventW, OpenEventW, SetEvent, WaitForSingleObject, INFINITE,
+        SYNCHRONIZATION_SYNCHRONIZE,
+    };
+
+    /// Encodes `name` as a NUL-terminated UTF-16 string.
+    fn wide(name: &str) -> Vec<u16> {
+        name.encode_utf16().chain(Some(0)).collect()
+    }
+
+    /// Takes ownership of a handle returned by a Win32 function, which
+    /// returns null on failure.
+    fn owned(handle: HANDLE) -> io::Result<OwnedHandle> {
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the handle was just returned by a successful call, and
+        // nothing else owns it.
+        Ok(unsafe { OwnedHandle::from_raw_handle(handle) })
+    }
+
+    /// Creates or opens a manual-reset event which starts out unset.
+    pub(super) fn create_event(name: &str) -> io::Result<OwnedHandle> {
+        let name = wide(name);
+        // SAFETY: `name` is NUL-terminated and outlives the call, and null
+        // security attributes select the defaults.
+        owned(unsafe { CreateEventW(ptr::null(), 1, 0, name.as_ptr()) })
+    }
+
+    /// Opens an existing event, with just enough access to wait on it.
+    pub(super) fn open_event(name: &str) -> io::Result<OwnedHandle> {
+        let name = wide(name);
+        // SAFETY: `name` is NUL-terminated and outlives the call.
+        owned(unsafe { OpenEventW(SYNCHRONIZATION_SYNCHRONIZE, 0, name.as_ptr()) })
+    }
+
+    pub(super) fn set_event(event: &OwnedHandle) -> io::Result<()> {
+        // SAFETY: `event` is an open handle for as long as it is borrowed.
+        if unsafe { SetEvent(event.as_raw_handle()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Blocks until `handle` is signaled.
+    pub(super) fn wait(handle: &OwnedHandle) -> io::Result<()> {
+        // SAFETY: `handle` is an open handle for as long as it is borrowed.
+        match unsafe { WaitForSingleObject(handle.as_raw_handle(), INFINITE) } {
+            WAIT_OBJECT_0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
@@ -83,9 +83,9 @@
 //!
 //! use std::time::{Duration, Instant};
 //!
-//! # #[cfg(feature = "tokio")]
+//! # #[cfg(not(feature = "async-io"))]
 //! # fn main() {}
-//! # #[cfg(not(feature = "tokio"))]
+//! # #[cfg(feature = "async-io")]
 //! #[async_std::main]
 //! async fn main() {
 //!     // Create a stream that generates numbers for 100 millis.
@@ -106,31 +106,223 @@
 //!
 //! - `async-io`: for use with the `async-std` or `smol` runtimes.
 //! - `tokio`: for use with the `tokio` runtime.
+//! - `futures-timer`: for use with any executor, through `futures-timer`.
+//!
+//! `async-io` and `futures-timer` also implement `Into<Deadline>` for
+//! `Duration`, counted from now. When both are enabled, `async-io` is used.
+//! In the browser, the `wasm` feature implements it on top of `setTimeout`.
+//! On embedded targets, the `embassy-time` feature implements
+//! `Into<Deadline>` for the `Instant` and `Duration` types of `embassy-time`,
+//! and works without `std`.
+//!
+//! The `async-io`, `futures-timer`, `tokio` and `wasm` backends also
+//! implement `Into<Deadline>` for `SystemTime`, to stop at a wall-clock time.
+//! The `chrono` feature does the same for `chrono::DateTime`, in any time
+//! zone, and the `time` feature for `time::OffsetDateTime` and
+//! `time::PrimitiveDateTime`, the latter being taken to be in UTC. The
+//! wall-clock time is converted to a duration from now when the deadline is
+//! created, and counted down by the backend, so adjusting the system clock
+//! afterwards doesn't move the deadline. These backends also provide
+//! `IdleDeadline`, for inactivity timeouts, and `RecurringDeadline`, for
+//! periodic ones. The `cron` feature lets the latter follow a cron schedule.
+//! They also provide the `retry` module, which retries operations with
+//! backoff within a deadline. When `tokio` is the only one of these backends
+//! enabled, everything which counts down a `Duration` uses tokio's timer, and
+//! panics when used outside a tokio runtime.
+//!
+//! The `humantime` feature adds `Deadline::parse`, which reads durations
+//! such as `"1h 30m"`, and implements `Into<Deadline>` for
+//! `humantime::Duration`.
+//!
+//! Other timers, such as those of custom runtimes or simulated clocks, can be
+//! plugged in by implementing [`clock::Clock`], or through
+//! [`Deadline::from_future`].
+//!
+//! The `local` feature adds `Deadline::from_local_future`, which takes
+//! futures that aren't `Send`, such as the timers of thread-per-core
+//! runtimes, and polls them on the thread which created the deadline.
+//!
+//! The `tokio` and `async-std` features also convert the timeout errors of
+//! `tokio::time::timeout` and `async_std::future::timeout` into
+//! `TimedOutError`, so code moving over to deadlines can keep its error
+//! handling. Those errors can't be built outside their crates, so there is
+//! no conversion the other way around.
+//!
+//! The `io` module's readers and writers implement the `AsyncRead` and
+//! `AsyncWrite` traits of `futures-io`, as used by `async-std` and `smol`,
+//! when the `futures-io` feature is enabled, and those of `tokio` when the
+//! `tokio` feature is enabled.
+//!
+//! The `serde` feature adds `Budget`, which records the time left before a
+//! deadline is hit and its wall-clock expiry in a serializable form, so
+//! deadlines can be embedded in RPC payloads. With a time backend, it
+//! converts back into a `Deadline`.
+//!
+//! The `tower` feature adds the `tower` module, whose `StopLayer` applies a
+//! shutdown token, a timeout and per-request deadlines to every call of a
+//! `tower` service.
+//!
+//! The `hyper` feature adds the `hyper` module, whose `GracefulConnection`
+//! shuts `hyper` connections down gracefully once a token stops, and drops
+//! those which take too long to drain. It enables the `tokio` feature.
+//!
+//! The `ctrl-c` feature adds `ctrl_c` and `StopSource::from_ctrl_c`, which
+//! stop once the process receives Ctrl-C. On Unix, the `signal` feature adds
+//! the `signal` module, which builds sources from arbitrary signals.
+//!
+//! On Unix, the `ipc` feature adds `StopSource::export_fd` and
+//! `StopToken::from_fd`, which share cancellation with other processes
+//! through a file descriptor. On Windows, it adds `StopSource::export_event`,
+//! `StopToken::open_event` and `StopToken::from_handle`, which do the same
+//! through a named event.
+//!
+//! The `tracing` feature emits `tracing` events at the debug level when a
+//! `StopSource` is cancelled, when a `Deadline` is hit, and when a
+//! `TimedOutError` is returned, with the source name or deadline label and the
+//! time elapsed since it was created.
+//!
+//! The `metrics` feature records the same occurrences through the `metrics`
+//! crate: the `stop_token_cancellations_total` and
+//! `stop_token_deadlines_hit_total` counters, and the
+//! `stop_token_time_to_cancel_seconds` and
+//! `stop_token_time_to_deadline_seconds` histograms. They are labelled with
+//! the source name (`source`) or deadline label (`deadline`), when set.
+//!
+//! The `test-util` feature adds `StopSource::wake_count`, and the `test`
+//! module, whose `MockClock` lets tests move time forward by hand, including
+//! for the `Instant` and `Duration` deadlines of the built-in backends.
+//!
+//! The `std` feature is enabled by default. Without it, the crate is `no_std`
+//! and only needs `alloc`: `StopSource`, `StopToken`, `Deadline` and the
+//! `future`, `stream` and `sink` combinators are available, while the `io` module,
+//! the `std::io::Error` conversion,
+//! [`GracefulSource::cancel_with_grace`], [`Deadline::on_expire`],
+//! [`current_deadline`] and the time backends require `std`.
 //!
 //! # Lineage
 //!
 //! The cancellation system is a subset of `C#` [`CancellationToken / CancellationTokenSource`](https://docs.microsoft.com/en-us/dotnet/standard/threading/cancellation-in-managed-threads).
 //! The `StopToken / StopTokenSource` terminology is borrowed from [C++ paper P0660](https://wg21.link/p0660).
 
-#![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(unsafe_code)]
 #![deny(missing_debug_implementations, nonstandard_style, rust_2018_idioms)]
 #![warn(missing_docs, future_incompatible, unreachable_pub)]
 
+extern crate alloc;
+
+/// Declares items which need a time backend that can count down a
+/// `Duration`, see `Deadline::after`.
+macro_rules! cfg_duration_backend {
+    ($($item:item)*) => {
+        $(
+            #[cfg(any(
+                feature = "async-io",
+                feature = "futures-timer",
+                feature = "tokio",
+                all(target_arch = "wasm32", feature = "wasm"),
+            ))]
+            $item
+        )*
+    };
+}
+
+pub mod clock;
 pub mod future;
+#[cfg(feature = "std")]
+pub mod global;
+pub mod grpc;
+#[cfg(feature = "std")]
+pub mod io;
+cfg_duration_backend! {
+    pub mod retry;
+}
+pub mod service;
+pub mod shutdown;
+pub mod sink;
 pub mod stream;
 
 #[cfg(any(feature = "async-io", feature = "docs"))]
 pub mod async_io;
-#[cfg(feature = "async_std")]
+#[cfg(feature = "async-std")]
 pub mod async_std;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "embassy-time")]
+pub mod embassy_time;
+#[cfg(feature = "futures-timer")]
+pub mod futures_timer;
+#[cfg(feature = "hyper")]
+pub mod hyper;
+#[cfg(feature = "mobile")]
+pub mod mobile;
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(all(unix, feature = "signal"))]
+pub mod signal;
+#[cfg(feature = "test-util")]
+pub mod test;
 #[cfg(feature = "tokio")]
 pub mod tokio;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+pub mod wasm;
+#[cfg(all(windows, feature = "windows-console"))]
+pub mod windows_console;
+#[cfg(all(windows, feature = "windows-service"))]
+pub mod windows_service;
 
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "chrono")]
+mod chrono;
+#[cfg(feature = "ctrl-c")]
+mod ctrl_c;
 mod deadline;
+mod graceful;
+#[cfg(feature = "humantime")]
+mod humantime;
+#[cfg(all(unix, feature = "ipc"))]
+mod ipc;
+#[cfg(all(windows, feature = "ipc"))]
+mod ipc_windows;
+cfg_duration_backend! {
+    mod idle;
+    mod recurring;
+}
+mod scope;
+#[cfg(feature = "serde")]
+mod serde;
 mod stop_source;
+mod task_tracker;
+#[cfg(feature = "time")]
+mod time;
+mod utils;
 
-pub use deadline::{Deadline, TimedOutError};
-pub use stop_source::{StopSource, StopToken};
+pub use deadline::{Deadline, TimedOutError, TimeoutKind};
+pub use graceful::{GracefulSource, GracefulToken, StopLevel};
+pub use scope::{scope, Scope, Scoped};
+pub use stop_source::{
+    CancelGuard, Checkpoint, Propagation, StopFlag, StopSource, StopToken, WaitAcknowledged,
+    YieldBudget, YieldNowIfNeeded,
+};
+pub use task_tracker::{TaskTracker, Tracked, WaitTracked};
+
+#[cfg(feature = "std")]
+pub use budget::current_deadline;
+#[cfg(feature = "ctrl-c")]
+pub use ctrl_c::ctrl_c;
+#[cfg(feature = "std")]
+pub use deadline::ExpireGuard;
+cfg_duration_backend! {
+    pub use idle::IdleDeadline;
+    pub use recurring::RecurringDeadline;
+}
+#[cfg(feature = "serde")]
+pub use serde::Budget;
+#[cfg(feature = "std")]
+pub use stop_source::StopTrigger;
 
 /// A prelude for `stop-token`.
 pub mod prelude {
@@ -0,0 +1,130 @@
+//! Cancellation driven by mobile app lifecycle callbacks.
+//!
+//! Apps embedding a Rust async core on Android or iOS need to shut it down
+//! when the host app stops (`onStop` / `onDestroy`,
+//! `applicationWillTerminate`). A [`LifecycleSource`] hands out a plain integer
+//! [`LifecycleHandle`] which the platform side can hold on to and pass back
+//! through FFI to cancel the source.
+//!
+//! # Features
+//!
+//! This module is only available when the `mobile` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use stop_token::mobile::{stop_token_lifecycle_stop, LifecycleSource};
+//!
+//! let source = LifecycleSource::new();
+//! let token = source.token();
+//!
+//! // Hand the raw handle to the host app, e.g. as a `jlong` or `uint64_t`.
+//! let raw = source.handle().into_raw();
+//!
+//! // Later, from the lifecycle callback:
+//! assert!(stop_token_lifecycle_stop(raw));
+//! # async_std::task::block_on(token);
+//! ```
+//!
+//! This crate does not export unmangled symbols itself. Re-export
+//! [`stop_token_lifecycle_stop`] from your own FFI layer (e.g. a
+//! `#[no_mangle]` wrapper or a JNI function) under whatever name the host app
+//! expects.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::{StopSource, StopToken};
+
+fn registry() -> &'static Mutex<HashMap<u64, Arc<StopSource>>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<u64, Arc<StopSource>>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// A `StopSource` which can be cancelled through a [`LifecycleHandle`].
+///
+/// Dropping the source cancels its tokens and invalidates the handle.
+#[derive(Debug)]
+pub struct LifecycleSource {
+    source: Arc<StopSource>,
+    handle: LifecycleHandle,
+}
+
+impl Default for LifecycleSource {
+    fn default() -> LifecycleSource {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let source = Arc::new(StopSource::new());
+        registry().lock().unwrap().insert(id, source.clone());
+        LifecycleSource {
+            source,
+            handle: LifecycleHandle(id),
+        }
+    }
+}
+
+impl LifecycleSource {
+    /// Creates a new `LifecycleSource`.
+    pub fn new() -> LifecycleSource {
+        LifecycleSource::default()
+    }
+
+    /// Produces a new `StopToken`, associated with this source.
+    pub fn token(&self) -> StopToken {
+        self.source.token()
+    }
+
+    /// Returns the handle the host app can use to cancel this source.
+    pub fn handle(&self) -> LifecycleHandle {
+        self.handle
+    }
+}
+
+impl Drop for LifecycleSource {
+    fn drop(&mut self) {
+        registry().lock().unwrap().remove(&self.handle.0);
+    }
+}
+
+/// An FFI-safe handle to a [`LifecycleSource`].
+///
+/// The handle is a plain integer and stays valid to use after the source has
+/// been dropped; stopping a dropped source does nothing.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct LifecycleHandle(u64);
+
+impl LifecycleHandle {
+    /// Creates a handle from its raw representation.
+    pub fn from_raw(raw: u64) -> LifecycleHandle {
+        LifecycleHandle(raw)
+    }
+
+    /// Returns the raw representation of this handle.
+    pub fn into_raw(self) -> u64 {
+        self.0
+    }
+
+    /// Cancels the source behind this handle.
+    ///
+    /// Returns `false` if the source no longer exists.
+    pub fn stop(self) -> bool {
+        let source = registry().lock().unwrap().get(&self.0).cloned();
+        match source {
+            Some(source) => {
+                source.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Cancels the [`LifecycleSource`] behind a raw handle.
+///
+/// Returns `false` if the source no longer exists.
+pub extern "C" fn stop_token_lifecycle_stop(handle: u64) -> bool {
+    LifecycleHandle::from_raw(handle).stop()
+}
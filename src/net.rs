@@ -0,0 +1,271 @@
+//! A graceful accept loop for servers.
+//!
+//! [`serve`] wires together the pieces a server needs to shut down between
+//! messages rather than mid-message: it accepts connections until a shutdown
+//! token stops, hands each connection a child token, optionally cancels
+//! connections which have been idle for too long, and finally drains the
+//! remaining connections.
+//!
+//! The accept loop is generic over the runtime: it takes any stream of
+//! incoming connections, such as `async_std::net::TcpListener::incoming` or a
+//! `tokio_stream::wrappers::TcpListenerStream`, and drives the connection
+//! handlers itself, without spawning tasks.
+//!
+//! # Features
+//!
+//! This module is only available when the `net` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use async_std::io::BufReader;
+//! use async_std::net::TcpListener;
+//! use async_std::prelude::*;
+//! use stop_token::prelude::*;
+//! use stop_token::StopSource;
+//!
+//! # async_std::task::block_on(async {
+//! let listener = TcpListener::bind("127.0.0.1:8080").await?;
+//! let shutdown = StopSource::new();
+//!
+//! let served = stop_token::net::serve(listener.incoming(), shutdown.token(), |stream, token| async move {
+//!     // Echo lines back until the connection closes or the server shuts down.
+//!     let mut lines = BufReader::new(&stream).lines().timeout_at(token);
+//!     while let Some(Ok(Ok(line))) = lines.next().await {
+//!         let _ = (&stream).write_all(format!("{}\n", line).as_bytes()).await;
+//!     }
+//! })
+//! .await;
+//! println!("served {} connections", served.accepted);
+//! # Ok::<(), std::io::Error>(())
+//! # });
+//! ```
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Wake, Waker};
+
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+
+use crate::{Deadline, StopSource, StopToken};
+
+/// Accept connections from `incoming` and run `handler` on each of them until
+/// `shutdown` stops.
+///
+/// Every connection is handed a token which stops once `shutdown` stops, or
+/// once the connection has been idle for too long (see
+/// [`Serve::idle_timeout`]). Handlers are expected to finish soon after their
+/// token stops. Once `shutdown` has stopped, no more connections are accepted,
+/// and the future resolves once every handler has finished, or once the
+/// deadline set with [`Serve::drain_timeout`] is hit.
+///
+/// Errors from `incoming` are counted and otherwise ignored, as accepting a
+/// connection usually fails for reasons specific to that connection.
+pub fn serve<I, C, H, Fut>(incoming: I, shutdown: StopToken, handler: H) -> Serve<I, H, Fut>
+where
+    I: Stream<Item = io::Result<C>>,
+    H: FnMut(C, StopToken) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    Serve {
+        incoming,
+        shutdown,
+        handler,
+        idle_timeout: None,
+        drain_timeout: None,
+        connections: Vec::new(),
+        outer: Arc::new(Mutex::new(None)),
+        drain: None,
+        accepting: true,
+        served: Served::default(),
+    }
+}
+
+/// The outcome of a [`serve`] call.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Served {
+    /// The number of connections which were accepted.
+    pub accepted: usize,
+    /// The number of errors returned while accepting connections.
+    pub accept_errors: usize,
+    /// The number of connections which were cancelled for being idle.
+    pub idle: usize,
+    /// The number of connections which were still running when the drain
+    /// deadline was hit, and were dropped.
+    pub aborted: usize,
+}
+
+pin_project! {
+    /// Accept connections and run a handler on each of them until shutdown.
+    ///
+    /// This future is returned by [`serve`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    pub struct Serve<I, H, Fut> {
+        #[pin]
+        incoming: I,
+        shutdown: StopToken,
+        handler: H,
+        idle_timeout: Option<Box<dyn FnMut() -> Deadline + Send>>,
+        drain_timeout: Option<Box<dyn FnOnce() -> Deadline + Send>>,
+        connections: Vec<Connection<Fut>>,
+        outer: Arc<Mutex<Option<Waker>>>,
+        drain: Option<Deadline>,
+        accepting: bool,
+        served: Served,
+    }
+}
+
+impl<I, H, Fut> fmt::Debug for Serve<I, H, Fut> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Serve")
+            .field("connections", &self.connections.len())
+            .field("accepting", &self.accepting)
+            .field("served", &self.served)
+            .finish()
+    }
+}
+
+impl<I, H, Fut> Serve<I, H, Fut> {
+    /// Cancels connections which haven't made progress before the deadline
+    /// returned by `idle`.
+    ///
+    /// A connection makes progress whenever its handler is woken. `idle` is
+    /// called to get a fresh deadline every time that happens, for example
+    /// `|| (Instant::now() + Duration::from_secs(30)).into()`.
+    pub fn idle_timeout<F>(mut self, idle: F) -> Self
+    where
+        F: FnMut() -> Deadline + Send + 'static,
+    {
+        self.idle_timeout = Some(Box::new(idle));
+        self
+    }
+
+    /// Drops the connections which are still running at the deadline returned
+    /// by `drain`.
+    ///
+    /// `drain` is called once `shutdown` has stopped. Without a drain
+    /// deadline, connections are waited on for as long as they take.
+    pub fn drain_timeout<F>(mut self, drain: F) -> Self
+    where
+        F: FnOnce() -> Deadline + Send + 'static,
+    {
+        self.drain_timeout = Some(Box::new(drain));
+        self
+    }
+}
+
+struct Connection<Fut> {
+    future: Pin<Box<Fut>>,
+    source: StopSource,
+    waker: Arc<ConnectionWaker>,
+    idle: Option<Deadline>,
+}
+
+/// Wakes the accept loop, remembering which connection needs to be polled.
+struct ConnectionWaker {
+    woken: AtomicBool,
+    outer: Arc<Mutex<Option<Waker>>>,
+}
+
+impl Wake for ConnectionWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(waker) = &*self.outer.lock().unwrap() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl<I, C, H, Fut> Future for Serve<I, H, Fut>
+where
+    I: Stream<Item = io::Result<C>>,
+    H: FnMut(C, StopToken) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    type Output = Served;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        *this.outer.lock().unwrap() = Some(cx.waker().clone());
+
+        if *this.accepting && this.shutdown.poll_stopped(cx).is_ready() {
+            *this.accepting = false;
+            *this.drain = this.drain_timeout.take().map(|drain| drain());
+            // Give every connection a chance to observe the shutdown before
+            // the drain deadline is checked.
+            for connection in this.connections.iter() {
+                connection.waker.woken.store(true, Ordering::Release);
+            }
+        }
+
+        while *this.accepting {
+            match this.incoming.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(stream))) => {
+                    this.served.accepted += 1;
+                    let source = StopSource::new();
+                    source.attach_to(this.shutdown);
+                    let future = Box::pin((this.handler)(stream, source.token()));
+                    this.connections.push(Connection {
+                        future,
+                        source,
+                        waker: Arc::new(ConnectionWaker {
+                            woken: AtomicBool::new(true),
+                            outer: this.outer.clone(),
+                        }),
+                        idle: None,
+                    });
+                }
+                Poll::Ready(Some(Err(_))) => this.served.accept_errors += 1,
+                Poll::Ready(None) => *this.accepting = false,
+                Poll::Pending => break,
+            }
+        }
+
+        let idle_timeout = this.idle_timeout;
+        let served = &mut *this.served;
+        this.connections.retain_mut(|connection| {
+            if connection.waker.woken.swap(false, Ordering::AcqRel) {
+                let waker = Waker::from(connection.waker.clone());
+                let mut cx = Context::from_waker(&waker);
+                if connection.future.as_mut().poll(&mut cx).is_ready() {
+                    return false;
+                }
+                if !connection.source.is_cancelled() {
+                    connection.idle = idle_timeout.as_mut().map(|idle| idle());
+                }
+            }
+            // The idle deadline is polled with the outer waker, so that it
+            // firing doesn't count as progress.
+            if let Some(idle) = &mut connection.idle {
+                if Pin::new(idle).poll(cx).is_ready() {
+                    connection.idle = None;
+                    connection.source.cancel();
+                    served.idle += 1;
+                }
+            }
+            true
+        });
+
+        if let Some(drain) = this.drain {
+            if Pin::new(drain).poll(cx).is_ready() {
+                this.served.aborted += this.connections.len();
+                this.connections.clear();
+            }
+        }
+
+        if !*this.accepting && this.connections.is_empty() {
+            return Poll::Ready(*this.served);
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use crate::utils::Timestamp;
+use crate::Deadline;
+
+/// A deadline which recurs on a schedule.
+///
+/// Each call to [`RecurringDeadline::next_deadline`] produces a deadline for
+/// the next occurrence, so a work loop can stop at every occurrence, do its
+/// periodic chore, and start again. Combine the deadline with a shutdown
+/// token through [`Deadline::or_token`], so shutdown still stops the loop.
+///
+/// ```
+/// use std::time::Duration;
+/// use stop_token::prelude::*;
+/// use stop_token::{RecurringDeadline, StopSource};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let shutdown = StopSource::new();
+/// let token = shutdown.token();
+/// let mut rotate = RecurringDeadline::every(Duration::from_millis(10));
+///
+/// for _ in 0..3 {
+///     let deadline = rotate.next_deadline().or_token(&token);
+///     let _ = std::future::pending::<()>().timeout_at(deadline).await;
+///     if token.is_stopped() {
+///         break;
+///     }
+///     // .. rotate the logs ..
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RecurringDeadline {
+    schedule: Schedule,
+}
+
+#[derive(Debug, Clone)]
+enum Schedule {
+    Interval {
+        period: Duration,
+        next: Timestamp,
+    },
+    #[cfg(feature = "cron")]
+    Cron {
+        schedule: Box<::cron::Schedule>,
+        last: Option<::chrono::DateTime<::chrono::Utc>>,
+    },
+}
+
+impl RecurringDeadline {
+    /// Creates a deadline which recurs every `period`, starting one period
+    /// from now.
+    ///
+    /// Occurrences stay on the same grid however late `next_deadline` is
+    /// called. Occurrences which have already passed by then are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn every(period: Duration) -> RecurringDeadline {
+        assert!(!period.is_zero(), "the period must be greater than zero");
+        RecurringDeadline {
+            schedule: Schedule::Interval {
+                period,
+                next: Timestamp::now() + period,
+            },
+        }
+    }
+
+    /// Creates a deadline which recurs on a cron schedule, in UTC.
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use stop_token::RecurringDeadline;
+    ///
+    /// // At the top of every hour.
+    /// let schedule = cron::Schedule::from_str("0 0 * * * *").unwrap();
+    /// let mut rotate = RecurringDeadline::cron(schedule);
+    /// let deadline = rotate.next_deadline();
+    /// ```
+    #[cfg(feature = "cron")]
+    pub fn cron(schedule: ::cron::Schedule) -> RecurringDeadline {
+        RecurringDeadline {
+            schedule: Schedule::Cron {
+                schedule: Box::new(schedule),
+                last: None,
+            },
+        }
+    }
+
+    /// Creates a deadline for the next occurrence.
+    ///
+    /// A schedule with no occurrences left produces a deadline which is never
+    /// hit.
+    pub fn next_deadline(&mut self) -> Deadline {
+        match &mut self.schedule {
+            Schedule::Interval { period, next } => {
+                let now = Timestamp::now();
+                if *next <= now {
+                    let behind = now.saturating_duration_since(*next);
+                    let missed = behind.as_nanos() / period.as_nanos() + 1;
+                    *next += Duration::from_nanos((missed * period.as_nanos()) as u64);
+                }
+                let deadline = Deadline::after(next.saturating_duration_since(now));
+                *next += *period;
+                deadline
+            }
+            #[cfg(feature = "cron")]
+            Schedule::Cron { schedule, last } => {
+                let now = ::chrono::Utc::now();
+                let from = last.map_or(now, |last| last.max(now));
+                match schedule.after(&from).next() {
+                    Some(time) => {
+                        *last = Some(time);
+                        time.into()
+                    }
+                    None => Deadline::never(),
+                }
+            }
+        }
+    }
+}
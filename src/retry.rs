@@ -0,0 +1,238 @@
+//! Retry fallible operations within a deadline.
+//!
+//! [`retry_until`] retries an operation with exponential backoff, but never
+//! past the overall deadline: once the deadline is hit, the attempt in
+//! flight or the backoff sleep is abandoned, and a backoff which would end
+//! after the deadline isn't started at all.
+//!
+//! # Features
+//!
+//! This module is only available when a backend which can count down a
+//! `Duration` is enabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use stop_token::retry::{retry_until, Backoff};
+//! use stop_token::StopToken;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let mut attempts = 0;
+//! let backoff = Backoff::exponential(Duration::from_millis(1));
+//! let result = retry_until(StopToken::never(), backoff, || {
+//!     attempts += 1;
+//!     let attempt = attempts;
+//!     async move {
+//!         if attempt < 3 {
+//!             Err("connection refused")
+//!         } else {
+//!             Ok(attempt)
+//!         }
+//!     }
+//! })
+//! .await;
+//! assert_eq!(result.unwrap(), 3);
+//! # }
+//! ```
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::error::Error;
+use std::time::Duration;
+
+use futures_core::ready;
+use pin_project_lite::pin_project;
+
+use crate::{Deadline, TimedOutError};
+
+/// How long to wait in between two attempts.
+///
+/// The delay starts out at the initial delay, and doubles after every failed
+/// attempt, up to the maximum delay.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Backoff {
+    initial: Duration,
+    max_delay: Duration,
+    max_retries: Option<u32>,
+}
+
+impl Default for Backoff {
+    /// Starts at 100 milliseconds, waits at most 10 seconds, and retries until
+    /// the deadline is hit.
+    fn default() -> Backoff {
+        Backoff::exponential(Duration::from_millis(100))
+    }
+}
+
+impl Backoff {
+    /// Creates a backoff which starts at `initial` and doubles after every
+    /// failed attempt, up to 10 seconds.
+    pub fn exponential(initial: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max_delay: Duration::from_secs(10),
+            max_retries: None,
+        }
+    }
+
+    /// Sets the longest delay in between two attempts.
+    pub fn max_delay(mut self, max_delay: Duration) -> Backoff {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets how often a failed operation is retried, on top of the first
+    /// attempt. By default, it is retried until the deadline is hit.
+    pub fn max_retries(mut self, max_retries: u32) -> Backoff {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Returns the delay before retry number `retry`, counting from one.
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Retry `op` with `backoff` until it succeeds, or until a deadline is hit.
+///
+/// `op` is called once per attempt, and each attempt runs until it resolves
+/// or the deadline is hit. See the [module documentation](self).
+pub fn retry_until<T, F, Fut, O, E>(target: T, backoff: Backoff, op: F) -> RetryUntil<F, Fut, E>
+where
+    T: Into<Deadline>,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<O, E>>,
+{
+    RetryUntil {
+        op,
+        backoff,
+        deadline: target.into(),
+        attempt: None,
+        sleep: None,
+        retries: 0,
+        last: None,
+    }
+}
+
+/// The error returned by [`retry_until`] when the operation didn't succeed.
+#[derive(Debug)]
+pub enum RetryError<E> {
+    /// The deadline was hit, or there wasn't enough time left for another
+    /// attempt.
+    TimedOut {
+        /// The timeout error of the deadline.
+        error: TimedOutError,
+        /// The error the last attempt failed with, if any attempt failed.
+        last: Option<E>,
+    },
+    /// The operation still failed after the maximum number of retries.
+    Failed(E),
+}
+
+impl<E> RetryError<E> {
+    /// Returns the error the last attempt failed with, if any attempt failed.
+    pub fn into_last(self) -> Option<E> {
+        match self {
+            RetryError::TimedOut { last, .. } => last,
+            RetryError::Failed(err) => Some(err),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryError::TimedOut { error, last: None } => error.fmt(f),
+            RetryError::TimedOut {
+                error,
+                last: Some(last),
+            } => write!(f, "{}, last attempt failed: {}", error, last),
+            RetryError::Failed(err) => write!(f, "retries exhausted: {}", err),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RetryError::TimedOut { error, last: None } => Some(error),
+            RetryError::TimedOut { last: Some(last), .. } => Some(last),
+            RetryError::Failed(err) => Some(err),
+        }
+    }
+}
+
+pin_project! {
+    /// Retry an operation until it succeeds, or until a deadline is hit.
+    ///
+    /// This future is returned by [`retry_until`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct RetryUntil<F, Fut, E> {
+        op: F,
+        backoff: Backoff,
+        #[pin]
+        deadline: Deadline,
+        #[pin]
+        attempt: Option<Fut>,
+        #[pin]
+        sleep: Option<Deadline>,
+        retries: u32,
+        last: Option<E>,
+    }
+}
+
+impl<F, Fut, O, E> Future for RetryUntil<F, Fut, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<O, E>>,
+{
+    type Output = Result<O, RetryError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+                return Poll::Ready(Err(RetryError::TimedOut {
+                    error: this.deadline.timed_out(),
+                    last: this.last.take(),
+                }));
+            }
+            if let Some(sleep) = this.sleep.as_mut().as_pin_mut() {
+                ready!(sleep.poll(cx));
+                this.sleep.set(None);
+            }
+            if this.attempt.is_none() {
+                this.attempt.set(Some((this.op)()));
+            }
+
+            let attempt = this.attempt.as_mut().as_pin_mut().unwrap();
+            let err = match ready!(attempt.poll(cx)) {
+                Ok(output) => return Poll::Ready(Ok(output)),
+                Err(err) => err,
+            };
+            this.attempt.set(None);
+
+            if this.backoff.max_retries == Some(*this.retries) {
+                return Poll::Ready(Err(RetryError::Failed(err)));
+            }
+            *this.retries += 1;
+            let delay = this.backoff.delay(*this.retries);
+            // Don't sleep past the deadline only to give up then.
+            if this.deadline.remaining().is_some_and(|left| left < delay) {
+                return Poll::Ready(Err(RetryError::TimedOut {
+                    error: this.deadline.timed_out(),
+                    last: Some(err),
+                }));
+            }
+            *this.last = Some(err);
+            this.sleep.set(Some(Deadline::after(delay)));
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use pin_project_lite::pin_project;
+
+use crate::utils::Mutex;
+use crate::{StopSource, StopToken};
+
+type Task<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Run `body` in a scope whose spawned futures stop along with `token`.
+///
+/// Futures spawned through [`Scope::spawn`] are handed a child token of
+/// `token`, and are driven by the returned future alongside `body`, without
+/// an executor. Once `body` completes, the scope's tokens are cancelled and
+/// the returned future waits for the spawned futures to finish before
+/// resolving with the output of `body`. If the returned future is dropped
+/// instead, the spawned futures are dropped with it.
+///
+/// ```
+/// use stop_token::prelude::*;
+/// use stop_token::StopSource;
+///
+/// # async_std::task::block_on(async {
+/// let source = StopSource::new();
+/// let finished = stop_token::scope(source.token(), |scope| async move {
+///     for _ in 0..3 {
+///         scope.spawn(|token| async move {
+///             // Runs until the scope is left.
+///             let _ = std::future::pending::<()>().timeout_at(token).await;
+///         });
+///     }
+///     "done"
+/// })
+/// .await;
+/// assert_eq!(finished, "done");
+/// # });
+/// ```
+pub fn scope<'a, F, Fut>(token: StopToken, body: F) -> Scoped<'a, Fut>
+where
+    F: FnOnce(Scope<'a>) -> Fut,
+    Fut: Future,
+{
+    let source = StopSource::new();
+    source.attach_to(&token);
+    let spawned = Arc::new(Mutex::new(Vec::new()));
+    let body = body(Scope {
+        token: source.token(),
+        spawned: Arc::downgrade(&spawned),
+    });
+    Scoped {
+        body,
+        output: None,
+        source,
+        spawned,
+        running: Vec::new(),
+    }
+}
+
+/// A handle to spawn futures into a [`scope`].
+///
+/// The handle can be cloned and moved into spawned futures, so that they can
+/// spawn futures of their own.
+#[derive(Clone)]
+pub struct Scope<'a> {
+    token: StopToken,
+    spawned: Weak<Mutex<Vec<Task<'a>>>>,
+}
+
+impl fmt::Debug for Scope<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope").field("token", &self.token).finish()
+    }
+}
+
+impl<'a> Scope<'a> {
+    /// Produces a new `StopToken`, which stops once the scope is left or its
+    /// parent token stops.
+    pub fn token(&self) -> StopToken {
+        self.token.clone()
+    }
+
+    /// Spawns a future into the scope, handing it a token of the scope.
+    ///
+    /// If the scope has already been left, the future is dropped without
+    /// being run.
+    pub fn spawn<F, Fut>(&self, f: F)
+    where
+        F: FnOnce(StopToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'a,
+    {
+        if let Some(spawned) = self.spawned.upgrade() {
+            let task: Task<'a> = Box::pin(f(self.token()));
+            spawned.lock().push(task);
+        }
+    }
+}
+
+pin_project! {
+    /// Run a future in a scope, then stop and wait for its spawned futures.
+    ///
+    /// This future is returned by [`scope`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    pub struct Scoped<'a, Fut: Future> {
+        #[pin]
+        body: Fut,
+        output: Option<Fut::Output>,
+        source: StopSource,
+        spawned: Arc<Mutex<Vec<Task<'a>>>>,
+        running: Vec<Task<'a>>,
+    }
+}
+
+impl<Fut: Future> fmt::Debug for Scoped<'_, Fut> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scoped")
+            .field("source", &self.source)
+            .field("running", &self.running.len())
+            .field("finished", &self.output.is_some())
+            .finish()
+    }
+}
+
+impl<Fut: Future> Future for Scoped<'_, Fut> {
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if this.output.is_none() {
+            if let Poll::Ready(output) = this.body.poll(cx) {
+                *this.output = Some(output);
+                this.source.cancel();
+            }
+        }
+
+        // Spawned futures may spawn more futures while they are polled, so
+        // keep going until no new ones show up.
+        loop {
+            this.running.append(&mut this.spawned.lock());
+            this.running
+                .retain_mut(|task| task.as_mut().poll(cx).is_pending());
+            if this.spawned.lock().is_empty() {
+                break;
+            }
+        }
+
+        if this.running.is_empty() {
+            if let Some(output) = this.output.take() {
+                return Poll::Ready(output);
+            }
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,207 @@
+//! Carry deadlines across process boundaries with `serde`.
+
+use core::fmt;
+use std::time::{Duration, SystemTime};
+
+use serde_core::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_core::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::Deadline;
+
+/// How much time is left before a deadline is hit, in a form which can be
+/// sent to another process.
+///
+/// A `Deadline` is tied to the timer it counts down with, so it can't be
+/// serialized itself. A `Budget` records the time left instead, along with
+/// the wall-clock time the deadline expires at, so that a service can embed
+/// it in an RPC payload and the receiving end can turn it back into an
+/// equivalent `Deadline`.
+///
+/// When converted back into a `Deadline`, the deadline is hit once the
+/// remaining time has elapsed, or at the wall-clock expiry, whichever comes
+/// first. The wall-clock expiry accounts for the time the budget spent in
+/// transit, as long as both clocks agree; drop it with
+/// [`Budget::without_expiry`] when they don't.
+///
+/// Budgets are serialized as a struct with a `remaining` duration and an
+/// optional `expires_at` system time, in the formats `serde` uses for
+/// `Duration` and `SystemTime`.
+///
+/// ```
+/// use std::time::Duration;
+/// use stop_token::{Budget, Deadline};
+///
+/// let budget = Budget::of(&Deadline::elapsed()).unwrap();
+/// assert_eq!(budget.remaining(), Duration::ZERO);
+/// assert!(budget.expires_at().is_some());
+///
+/// assert_eq!(Budget::of(&Deadline::never()), None);
+/// ```
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Budget {
+    remaining: Duration,
+    expires_at: Option<SystemTime>,
+}
+
+impl Budget {
+    /// Creates a budget of `remaining`, without a wall-clock expiry.
+    pub fn new(remaining: Duration) -> Budget {
+        Budget {
+            remaining,
+            expires_at: None,
+        }
+    }
+
+    /// Records the time left before `deadline` is hit, and the wall-clock
+    /// time it is hit at.
+    ///
+    /// Returns `None` when the deadline can't tell how much time is left, see
+    /// [`Deadline::remaining`].
+    pub fn of(deadline: &Deadline) -> Option<Budget> {
+        let remaining = deadline.remaining()?;
+        Some(Budget {
+            remaining,
+            expires_at: crate::utils::system_now().checked_add(remaining),
+        })
+    }
+
+    /// Returns how much time was left when this budget was recorded.
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+
+    /// Returns the wall-clock time the deadline expires at, if it was
+    /// recorded.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    /// Drops the wall-clock expiry, for peers whose clocks can't be trusted to
+    /// agree.
+    pub fn without_expiry(self) -> Budget {
+        Budget::new(self.remaining)
+    }
+}
+
+cfg_duration_backend! {
+    /// A deadline which is hit once the budget's remaining time has elapsed,
+    /// or at its wall-clock expiry, whichever comes first.
+    impl From<Budget> for Deadline {
+        fn from(budget: Budget) -> Deadline {
+            let remaining = match budget.expires_at {
+                Some(time) => time
+                    .duration_since(crate::utils::system_now())
+                    .unwrap_or_default()
+                    .min(budget.remaining),
+                None => budget.remaining,
+            };
+            Deadline::after(remaining)
+        }
+    }
+}
+
+const FIELDS: &[&str] = &["remaining", "expires_at"];
+
+impl Serialize for Budget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Budget", 2)?;
+        state.serialize_field("remaining", &self.remaining)?;
+        state.serialize_field("expires_at", &self.expires_at)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Budget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Budget, D::Error> {
+        deserializer.deserialize_struct("Budget", FIELDS, BudgetVisitor)
+    }
+}
+
+struct BudgetVisitor;
+
+impl<'de> Visitor<'de> for BudgetVisitor {
+    type Value = Budget;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("struct Budget")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Budget, A::Error> {
+        let remaining = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let expires_at = seq.next_element()?.unwrap_or(None);
+        Ok(Budget {
+            remaining,
+            expires_at,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Budget, A::Error> {
+        let mut remaining = None;
+        let mut expires_at = None;
+        while let Some(field) = map.next_key()? {
+            match field {
+                Field::Remaining if remaining.is_some() => {
+                    return Err(de::Error::duplicate_field("remaining"))
+                }
+                Field::Remaining => remaining = Some(map.next_value()?),
+                Field::ExpiresAt if expires_at.is_some() => {
+                    return Err(de::Error::duplicate_field("expires_at"))
+                }
+                Field::ExpiresAt => expires_at = Some(map.next_value()?),
+                Field::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Budget {
+            remaining: remaining.ok_or_else(|| de::Error::missing_field("remaining"))?,
+            expires_at: expires_at.flatten(),
+        })
+    }
+}
+
+/// The fields of a serialized `Budget`. Unknown fields are skipped, so that
+/// newer peers can add some.
+enum Field {
+    Remaining,
+    ExpiresAt,
+    Other,
+}
+
+impl<'de> Deserialize<'de> for Field {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Field, D::Error> {
+        deserializer.deserialize_identifier(FieldVisitor)
+    }
+}
+
+struct FieldVisitor;
+
+impl<'de> Visitor<'de> for FieldVisitor {
+    type Value = Field;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a field identifier")
+    }
+
+    fn visit_u64<E: de::Error>(self, index: u64) -> Result<Field, E> {
+        Ok(match index {
+            0 => Field::Remaining,
+            1 => Field::ExpiresAt,
+            _ => Field::Other,
+        })
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Field, E> {
+        self.visit_bytes(name.as_bytes())
+    }
+
+    fn visit_bytes<E: de::Error>(self, name: &[u8]) -> Result<Field, E> {
+        Ok(match name {
+            b"remaining" => Field::Remaining,
+            b"expires_at" => Field::ExpiresAt,
+            _ => Field::Other,
+        })
+    }
+}
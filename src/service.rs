@@ -0,0 +1,159 @@
+//! A common interface for long-running components.
+//!
+//! A [`Stoppable`] service runs until the token it is handed stops, and then
+//! winds down. [`Services`] drives several of them side by side, stopping
+//! all of them once the shutdown token stops or any one of them exits.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::convert::Infallible;
+//! use stop_token::service::{Services, Stoppable};
+//! use stop_token::{StopSource, StopToken};
+//!
+//! struct Heartbeat;
+//!
+//! impl Stoppable for Heartbeat {
+//!     type Error = Infallible;
+//!
+//!     async fn run(self, token: StopToken) -> Result<(), Infallible> {
+//!         token.await;
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # async_std::task::block_on(async {
+//! let shutdown = StopSource::new();
+//! let services = Services::<Infallible>::new(shutdown.token())
+//!     .with(Heartbeat)
+//!     .with(Heartbeat)
+//!     .run();
+//!
+//! drop(shutdown);
+//! assert!(services.await.is_ok());
+//! # });
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::{StopSource, StopToken};
+
+type Service<E> = Pin<Box<dyn Future<Output = Result<(), E>> + Send>>;
+
+/// A long-running component which stops once its token stops.
+///
+/// Implementations can use `async fn run(self, token: StopToken)`.
+pub trait Stoppable {
+    /// The error the service fails with.
+    type Error;
+
+    /// Runs the service until `token` stops, or until it exits on its own.
+    fn run(self, token: StopToken) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// A set of services which run side by side and stop together.
+///
+/// Every service is handed a token which stops once the shutdown token
+/// passed to [`Services::new`] stops, or once any of the services exits,
+/// whether it succeeded or failed.
+pub struct Services<E> {
+    source: StopSource,
+    services: Vec<Service<E>>,
+}
+
+impl<E> fmt::Debug for Services<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Services")
+            .field("source", &self.source)
+            .field("services", &self.services.len())
+            .finish()
+    }
+}
+
+impl<E: 'static> Services<E> {
+    /// Creates an empty set of services, which stops once `shutdown` stops.
+    pub fn new(shutdown: StopToken) -> Services<E> {
+        let source = StopSource::new();
+        source.attach_to(&shutdown);
+        Services {
+            source,
+            services: Vec::new(),
+        }
+    }
+
+    /// Adds a service to the set.
+    pub fn with<S>(mut self, service: S) -> Services<E>
+    where
+        S: Stoppable + 'static,
+        S::Error: Into<E>,
+    {
+        let future = service.run(self.source.token());
+        self.services
+            .push(Box::pin(async move { future.await.map_err(Into::into) }));
+        self
+    }
+
+    /// Runs the services until all of them have exited.
+    ///
+    /// Resolves to the first error a service failed with, if any.
+    pub fn run(self) -> RunServices<E> {
+        RunServices {
+            source: self.source,
+            services: self.services,
+            error: None,
+        }
+    }
+}
+
+/// A future which drives a set of services.
+///
+/// This future is returned by [`Services::run`].
+#[must_use = "Futures do nothing unless polled or .awaited"]
+pub struct RunServices<E> {
+    source: StopSource,
+    services: Vec<Service<E>>,
+    error: Option<E>,
+}
+
+impl<E> fmt::Debug for RunServices<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RunServices")
+            .field("source", &self.source)
+            .field("running", &self.services.len())
+            .field("failed", &self.error.is_some())
+            .finish()
+    }
+}
+
+// The services are boxed, and the error is never pinned.
+impl<E> Unpin for RunServices<E> {}
+
+impl<E> Future for RunServices<E> {
+    type Output = Result<(), E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let (source, error) = (&this.source, &mut this.error);
+        this.services
+            .retain_mut(|service| match service.as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    if let Err(err) = result {
+                        error.get_or_insert(err);
+                    }
+                    // One service exiting stops the others.
+                    source.cancel();
+                    false
+                }
+                Poll::Pending => true,
+            });
+        if this.services.is_empty() {
+            return Poll::Ready(this.error.take().map_or(Ok(()), Err));
+        }
+        Poll::Pending
+    }
+}
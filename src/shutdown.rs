@@ -0,0 +1,237 @@
+//! Graceful shutdown in ordered phases.
+//!
+//! Services usually need to shut down in steps: stop accepting new work,
+//! then drain the work in flight, then flush buffers and close connections.
+//! A [`Shutdown`] coordinator holds one `StopSource` per named phase.
+//! Subsystems register under a phase to get a token, and acknowledge it once
+//! they have stopped. [`Shutdown::run`] then cancels the phases one after the
+//! other, waiting for each to be acknowledged before moving on to the next.
+//!
+//! # Examples
+//!
+//! ```
+//! use async_std::task;
+//! use stop_token::shutdown::Shutdown;
+//! use stop_token::StopToken;
+//!
+//! # task::block_on(async {
+//! let shutdown = Shutdown::new().phase("accept").phase("drain").phase("flush");
+//!
+//! for phase in ["accept", "drain", "flush"] {
+//!     let token = shutdown.register(phase);
+//!     task::spawn(async move {
+//!         token.clone().await;
+//!         // .. stop this subsystem ..
+//!         token.acknowledge();
+//!     });
+//! }
+//!
+//! let report = shutdown.run(StopToken::never()).await;
+//! assert!(report.is_complete());
+//! # });
+//! ```
+
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use crate::{Deadline, StopSource, StopToken, WaitAcknowledged};
+
+/// Coordinates a shutdown in named phases.
+///
+/// See the [module documentation](self) for an overview.
+#[derive(Default)]
+pub struct Shutdown {
+    phases: Vec<Phase>,
+}
+
+struct Phase {
+    name: Cow<'static, str>,
+    source: StopSource,
+    registered: AtomicUsize,
+    timeout: Option<Box<dyn Fn() -> Deadline + Send + Sync>>,
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.phases.iter().map(|phase| &phase.source))
+            .finish()
+    }
+}
+
+impl Shutdown {
+    /// Creates a coordinator without any phases.
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Adds a phase, which runs after the phases added before it.
+    pub fn phase(mut self, name: impl Into<Cow<'static, str>>) -> Shutdown {
+        let name = name.into();
+        self.phases.push(Phase {
+            source: StopSource::named(name.clone()),
+            name,
+            registered: AtomicUsize::new(0),
+            timeout: None,
+        });
+        self
+    }
+
+    /// Adds a phase which moves on to the next phase at the deadline returned
+    /// by `timeout`, even if it hasn't been fully acknowledged.
+    ///
+    /// `timeout` is called when the phase starts, for example
+    /// `|| (Instant::now() + Duration::from_secs(5)).into()`.
+    pub fn phase_with_timeout<F>(self, name: impl Into<Cow<'static, str>>, timeout: F) -> Shutdown
+    where
+        F: Fn() -> Deadline + Send + Sync + 'static,
+    {
+        let mut shutdown = self.phase(name);
+        if let Some(phase) = shutdown.phases.last_mut() {
+            phase.timeout = Some(Box::new(timeout));
+        }
+        shutdown
+    }
+
+    /// Registers a subsystem under `phase`, returning the token it should
+    /// stop on.
+    ///
+    /// The phase waits for the token to be acknowledged with
+    /// [`StopToken::acknowledge`]. Subsystems registered once their phase has
+    /// started are cancelled right away, but aren't waited on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no phase is named `phase`.
+    pub fn register(&self, phase: &str) -> StopToken {
+        let phase = self
+            .phases
+            .iter()
+            .find(|p| p.name == phase)
+            .unwrap_or_else(|| panic!("no shutdown phase named `{}`", phase));
+        phase.registered.fetch_add(1, Ordering::AcqRel);
+        phase.source.token()
+    }
+
+    /// Cancels the phases in order, waiting for each of them to be
+    /// acknowledged before moving on.
+    ///
+    /// Once `deadline` is hit, all remaining phases are cancelled at once and
+    /// the returned future completes without waiting for them.
+    pub fn run<T: Into<Deadline>>(&self, deadline: T) -> Run<'_> {
+        Run {
+            shutdown: self,
+            deadline: deadline.into(),
+            current: None,
+            report: Report::default(),
+        }
+    }
+}
+
+/// A future which runs the phases of a [`Shutdown`].
+///
+/// This future is returned by [`Shutdown::run`].
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct Run<'a> {
+    shutdown: &'a Shutdown,
+    deadline: Deadline,
+    current: Option<Current<'a>>,
+    report: Report,
+}
+
+/// The phase a [`Run`] is waiting on.
+#[derive(Debug)]
+struct Current<'a> {
+    registered: usize,
+    wait: WaitAcknowledged<'a>,
+    timeout: Option<Deadline>,
+}
+
+impl Future for Run<'_> {
+    type Output = Report;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let phases = &this.shutdown.phases;
+        while let Some(phase) = phases.get(this.report.phases.len()) {
+            let current = this.current.get_or_insert_with(|| {
+                phase.source.cancel();
+                let registered = phase.registered.load(Ordering::Acquire);
+                Current {
+                    registered,
+                    wait: phase.source.wait_acknowledged(registered),
+                    timeout: phase.timeout.as_ref().map(|timeout| timeout()),
+                }
+            });
+            let acknowledged = Pin::new(&mut current.wait).poll(cx).is_ready();
+            let timed_out = !acknowledged
+                && current
+                    .timeout
+                    .as_mut()
+                    .is_some_and(|timeout| Pin::new(timeout).poll(cx).is_ready());
+            let expired = !acknowledged && Pin::new(&mut this.deadline).poll(cx).is_ready();
+            if !acknowledged && !timed_out && !expired {
+                return Poll::Pending;
+            }
+
+            let registered = current.registered;
+            this.current = None;
+            this.report.phases.push(PhaseReport::new(phase, registered));
+            if expired {
+                for phase in &phases[this.report.phases.len()..] {
+                    phase.source.cancel();
+                    let registered = phase.registered.load(Ordering::Acquire);
+                    this.report.phases.push(PhaseReport::new(phase, registered));
+                }
+            }
+        }
+        Poll::Ready(core::mem::take(&mut this.report))
+    }
+}
+
+/// The outcome of a [`Shutdown::run`] call.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The outcome of every phase, in order.
+    pub phases: Vec<PhaseReport>,
+}
+
+impl Report {
+    /// Returns `true` if every phase was fully acknowledged.
+    pub fn is_complete(&self) -> bool {
+        self.phases.iter().all(PhaseReport::is_complete)
+    }
+}
+
+/// The outcome of a single shutdown phase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhaseReport {
+    /// The name of the phase.
+    pub name: Cow<'static, str>,
+    /// The number of subsystems which were waited on.
+    pub registered: usize,
+    /// The number of subsystems which acknowledged before the phase ended.
+    pub acknowledged: usize,
+}
+
+impl PhaseReport {
+    fn new(phase: &Phase, registered: usize) -> PhaseReport {
+        PhaseReport {
+            name: phase.name.clone(),
+            registered,
+            acknowledged: phase.source.acknowledged(),
+        }
+    }
+
+    /// Returns `true` if every registered subsystem acknowledged.
+    pub fn is_complete(&self) -> bool {
+        self.acknowledged >= self.registered
+    }
+}
@@ -0,0 +1,177 @@
+//! Cancellation driven by Unix signals.
+//!
+//! Daemons usually want `SIGTERM` to trigger a graceful shutdown, while other
+//! signals such as `SIGHUP` ask for something different, like reloading the
+//! configuration. A [`SignalSource`] is cancelled once any of the signals it
+//! was created with is received, so each concern can get its own source.
+//!
+//! # Features
+//!
+//! This module is only available on Unix, when the `signal` feature is
+//! enabled.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use stop_token::signal::{SignalSource, SIGHUP, SIGINT, SIGTERM};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let shutdown = SignalSource::new([SIGTERM, SIGINT])?;
+//! let reload = SignalSource::new([SIGHUP])?;
+//! # async_std::task::block_on(async {
+//! // .. run until `shutdown.token()` stops, reload when `reload.token()` stops ..
+//! # shutdown.token().await;
+//! # });
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::io;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+use signal_hook::iterator::{Handle, Signals};
+
+use crate::{StopSource, StopToken};
+
+pub use signal_hook::consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2};
+
+/// A `StopSource` which is cancelled once the process receives a signal.
+///
+/// The signals are observed from a helper thread, which exits once the
+/// source is cancelled. Like a regular `StopSource`, dropping a
+/// `SignalSource` cancels its tokens.
+#[derive(Debug)]
+pub struct SignalSource {
+    token: StopToken,
+    handle: Handle,
+}
+
+impl SignalSource {
+    /// Creates a source which is cancelled once any of `signals` is received.
+    ///
+    /// Until then, the signals don't take their default action (for
+    /// `SIGTERM`, terminating the process). Once the source has been
+    /// cancelled or dropped, and no other `SignalSource` waits for them, they
+    /// take their default action again, so a second `SIGINT` still
+    /// terminates a process which got stuck shutting down.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a handler can't be installed for one of the signals, for
+    /// example because it is one of the signals which can't be caught, such
+    /// as `SIGKILL`.
+    pub fn new<I>(signals: I) -> io::Result<SignalSource>
+    where
+        I: IntoIterator,
+        I::Item: core::borrow::Borrow<c_int>,
+    {
+        let signals: Vec<c_int> = signals
+            .into_iter()
+            .map(|signal| *core::borrow::Borrow::borrow(&signal))
+            .collect();
+        // The default action has to be suppressed before the iterator's
+        // handler is installed: the actions run in the order they were
+        // registered, and the iterator's thread may let the default action
+        // back in while the signal is still being handled.
+        wait_for(&signals)?;
+        let mut iter = match Signals::new(&signals) {
+            Ok(iter) => iter,
+            Err(err) => {
+                stop_waiting(&signals);
+                return Err(err);
+            }
+        };
+        let handle = iter.handle();
+        let source = StopSource::new();
+        let token = source.token();
+        let waiting = signals.clone();
+        let spawned = thread::Builder::new()
+            .name("stop-token-signal".into())
+            .spawn(move || {
+                // Ends on the first signal, or once the handle is closed.
+                let _ = iter.forever().next();
+                drop(source);
+                stop_waiting(&waiting);
+            });
+        if let Err(err) = spawned {
+            stop_waiting(&signals);
+            return Err(err);
+        }
+        Ok(SignalSource { token, handle })
+    }
+
+    /// Produces a new `StopToken`, associated with this source.
+    pub fn token(&self) -> StopToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for SignalSource {
+    fn drop(&mut self) {
+        self.handle.close();
+    }
+}
+
+/// How many sources wait for a signal, and the flag which makes the signal
+/// take its default action while none does.
+///
+/// `signal-hook` never restores the default action of a signal once it has
+/// handled it, so it is emulated instead.
+struct Disposition {
+    waiting: usize,
+    default: Arc<AtomicBool>,
+}
+
+fn dispositions() -> std::sync::MutexGuard<'static, HashMap<c_int, Disposition>> {
+    static DISPOSITIONS: OnceLock<Mutex<HashMap<c_int, Disposition>>> = OnceLock::new();
+    DISPOSITIONS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+}
+
+/// Suppresses the default action of `signals` until `stop_waiting` is
+/// called for them.
+fn wait_for(signals: &[c_int]) -> io::Result<()> {
+    let mut dispositions = dispositions();
+    for (i, &signal) in signals.iter().enumerate() {
+        let disposition = match dispositions.entry(signal) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let default = Arc::new(AtomicBool::new(true));
+                let registered =
+                    signal_hook::flag::register_conditional_default(signal, default.clone());
+                if let Err(err) = registered {
+                    drop(dispositions);
+                    stop_waiting(&signals[..i]);
+                    return Err(err);
+                }
+                entry.insert(Disposition {
+                    waiting: 0,
+                    default,
+                })
+            }
+        };
+        disposition.waiting += 1;
+        disposition.default.store(false, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// Lets `signals` take their default action again, unless another source
+/// waits for them.
+fn stop_waiting(signals: &[c_int]) {
+    let mut dispositions = dispositions();
+    for signal in signals {
+        if let Some(disposition) = dispositions.get_mut(signal) {
+            disposition.waiting -= 1;
+            if disposition.waiting == 0 {
+                disposition.default.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
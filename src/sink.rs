@@ -0,0 +1,171 @@
+//! Extension methods and types for the `Sink` trait.
+//!
+//! The `future` and `stream` combinators stop the read side of a connection
+//! between messages. [`SinkExt::until`] does the same for the write side: once
+//! the deadline is hit, the sink stops accepting new items, but the items it
+//! has already accepted are still flushed and the sink is closed, so the peer
+//! never sees half a message.
+//!
+//! [`SinkExt`] isn't part of the prelude, since types which are both a
+//! `Stream` and a `Sink`, such as framed connections, would then have two
+//! `until` methods.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io;
+//! use std::pin::Pin;
+//! use std::task::{Context, Poll};
+//! use futures_sink::Sink;
+//! use stop_token::sink::SinkExt as _;
+//! use stop_token::StopSource;
+//!
+//! /// A sink which writes lines to a buffer.
+//! #[derive(Default)]
+//! struct Lines(Vec<String>);
+//!
+//! impl Sink<String> for Lines {
+//!     type Error = io::Error;
+//!
+//!     fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+//!         Poll::Ready(Ok(()))
+//!     }
+//!
+//!     fn start_send(mut self: Pin<&mut Self>, line: String) -> io::Result<()> {
+//!         self.0.push(line);
+//!         Ok(())
+//!     }
+//!
+//!     fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+//!         Poll::Ready(Ok(()))
+//!     }
+//!
+//!     fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+//!         Poll::Ready(Ok(()))
+//!     }
+//! }
+//!
+//! # async_std::task::block_on(async {
+//! let source = StopSource::new();
+//! let mut lines = Box::pin(Lines::default().until(source.token()));
+//! drop(source);
+//!
+//! let ready = std::future::poll_fn(|cx| lines.as_mut().poll_ready(cx)).await;
+//! assert_eq!(ready.unwrap_err().kind(), io::ErrorKind::TimedOut);
+//! # });
+//! ```
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures_core::ready;
+use futures_sink::Sink;
+use pin_project_lite::pin_project;
+
+use crate::{Deadline, TimedOutError};
+
+/// Extend the `Sink` trait with the `until` method.
+pub trait SinkExt<Item>: Sink<Item> {
+    /// Applies the deadline to the sink, such that the resulting sink accepts
+    /// no more items once the deadline is hit.
+    ///
+    /// Once the deadline is hit, `poll_ready` closes the underlying sink,
+    /// flushing the items it has already accepted, and then fails with the
+    /// timeout error. The sink's error type must be able to carry it, which
+    /// is the case for `std::io::Error`.
+    fn until<T>(self, target: T) -> Until<Self>
+    where
+        Self: Sized,
+        Self::Error: From<TimedOutError>,
+        T: Into<Deadline>,
+    {
+        Until {
+            sink: self,
+            deadline: target.into(),
+            stopped: false,
+            closed: false,
+        }
+    }
+}
+
+impl<Si: Sink<Item>, Item> SinkExt<Item> for Si {}
+
+pin_project! {
+    /// A sink which stops accepting items once a deadline is hit.
+    ///
+    /// This sink is returned by [`SinkExt::until`].
+    #[must_use = "Sinks do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct Until<Si> {
+        #[pin]
+        sink: Si,
+        #[pin]
+        deadline: Deadline,
+        stopped: bool,
+        closed: bool,
+    }
+}
+
+impl<Si> Until<Si> {
+    /// Returns the deadline this sink runs until.
+    pub fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    /// Returns `true` if the deadline has been hit, and the sink no longer
+    /// accepts items.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Unwraps this sink, returning the underlying sink.
+    pub fn into_inner(self) -> Si {
+        self.sink
+    }
+}
+
+impl<Si, Item> Sink<Item> for Until<Si>
+where
+    Si: Sink<Item>,
+    Si::Error: From<TimedOutError>,
+{
+    type Error = Si::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut this = self.project();
+        if !*this.stopped && this.deadline.as_mut().poll(cx).is_ready() {
+            *this.stopped = true;
+        }
+        if !*this.stopped {
+            return this.sink.poll_ready(cx);
+        }
+        if !*this.closed {
+            ready!(this.sink.poll_close(cx))?;
+            *this.closed = true;
+        }
+        Poll::Ready(Err(this.deadline.timed_out().into()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        self.project().sink.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        if *this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        this.sink.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        if *this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(this.sink.poll_close(cx))?;
+        *this.closed = true;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -1,13 +1,17 @@
-use core::future::Future;
+use alloc::borrow::Cow;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+use core::future::{Future, IntoFuture};
 use core::pin::Pin;
-use core::task::{Context, Poll};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
 
-use async_channel::{bounded, Receiver, Sender};
-use futures_core::stream::Stream;
-
-use crate::Deadline;
-
-enum Never {}
+use crate::utils::{Mutex, WakerList};
+use crate::{Deadline, TimedOutError};
+#[cfg(feature = "oneshot")]
+use futures_channel::oneshot;
 
 /// `StopSource` produces `StopToken` and cancels all of its tokens on drop.
 ///
@@ -19,61 +23,1419 @@ enum Never {}
 /// schedule_some_work(token);
 /// drop(source); // At this point, scheduled work notices that it is canceled.
 /// ```
-#[derive(Debug)]
 pub struct StopSource {
-    /// Solely for `Drop`.
-    _chan: Sender<Never>,
-    stop_token: StopToken,
+    inner: Arc<Inner>,
+    propagation: Propagation,
 }
 
 /// `StopToken` is a future which completes when the associated `StopSource` is dropped.
-#[derive(Debug, Clone)]
 pub struct StopToken {
-    chan: Receiver<Never>,
+    inner: Arc<Inner>,
+    /// The key this token's waker is registered under.
+    key: Option<usize>,
+    /// The version of `inner` that `parents` were copied from.
+    version: Option<usize>,
+    parents: Vec<StopToken>,
 }
 
-impl Default for StopSource {
-    fn default() -> StopSource {
-        let (sender, receiver) = bounded::<Never>(1);
+/// The state shared between a source and its tokens.
+#[derive(Debug, Default)]
+struct Inner {
+    /// A name to tell sources apart when debugging.
+    name: Option<Cow<'static, str>>,
+    /// The generation of the source these tokens belong to.
+    source_generation: u64,
+    mode: Mode,
+    cancelled: AtomicBool,
+    /// Tokens waiting for cancellation, or for a change of parents.
+    wakers: Mutex<WakerList>,
+    tokens: AtomicUsize,
+    /// Bumped whenever `parents` changes, so tokens can skip the lock.
+    version: AtomicUsize,
+    parents: Mutex<Vec<StopToken>>,
+    /// Sources and combinators which were attached to this one.
+    children: Mutex<Vec<Weak<Inner>>>,
+    acknowledged: AtomicUsize,
+    acknowledged_wakers: Mutex<WakerList>,
+    #[cfg(feature = "test-util")]
+    wake_count: AtomicUsize,
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    created: crate::utils::Created,
+    /// A future which cancels the source once it completes.
+    #[cfg(feature = "oneshot")]
+    external: Mutex<Option<External>>,
+    /// Bumped whenever the external future is woken, so that a wake which
+    /// comes in while it is being polled isn't lost.
+    #[cfg(feature = "oneshot")]
+    external_wakes: AtomicUsize,
+    /// Receivers handed out by `StopSource::oneshot`.
+    #[cfg(feature = "oneshot")]
+    senders: Mutex<Vec<oneshot::Sender<()>>>,
+    /// Set up by the first call to `StopSource::trigger`.
+    #[cfg(feature = "std")]
+    trigger: std::sync::OnceLock<Arc<TriggerState>>,
+}
 
-        StopSource {
-            _chan: sender,
-            stop_token: StopToken { chan: receiver },
+#[cfg(feature = "oneshot")]
+struct External(Pin<alloc::boxed::Box<dyn Future<Output = ()> + Send>>);
+
+#[cfg(feature = "oneshot")]
+impl core::fmt::Debug for External {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("External").finish()
+    }
+}
+
+/// Drives the external future of a source, cancelling the source once it
+/// completes.
+///
+/// This keeps `is_stopped` and flags up to date without any token being
+/// polled.
+#[cfg(feature = "oneshot")]
+struct ExternalWaker(Weak<Inner>);
+
+#[cfg(feature = "oneshot")]
+impl alloc::task::Wake for ExternalWaker {
+    fn wake(self: Arc<Self>) {
+        if let Some(inner) = self.0.upgrade() {
+            inner.external_wakes.fetch_add(1, Ordering::AcqRel);
+            if inner.poll_external() {
+                inner.cancel();
+            }
         }
     }
 }
 
+/// How a token combines the cancellation of its parents.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Stop once any parent has stopped.
+    #[default]
+    Any,
+    /// Stop once every parent has stopped.
+    All,
+}
+
+/// The order in which a `StopSource` wakes the tasks of the sources attached
+/// below it when it is cancelled.
+///
+/// See [`StopSource::with_propagation`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// Wake every waiting task at once. Attached sources observe the
+    /// cancellation when their tokens are next polled.
+    #[default]
+    Simultaneous,
+    /// Cancel attached sources up front, and wake their tasks level by level,
+    /// starting with the deepest level, before the tasks of this source.
+    ChildrenFirst,
+    /// Cancel attached sources up front, and wake their tasks one subtree at a
+    /// time, each subtree from the bottom up, before the tasks of this source.
+    DepthFirst,
+}
+
+impl Default for StopSource {
+    fn default() -> StopSource {
+        StopSource::with_generation(0, None)
+    }
+}
+
 impl StopSource {
     /// Creates a new `StopSource`.
     pub fn new() -> StopSource {
         StopSource::default()
     }
 
+    /// Creates a new `StopSource` with a name.
+    ///
+    /// The name is shown in the `Debug` output of the source and its tokens,
+    /// which helps to tell sources apart when debugging a hung shutdown.
+    ///
+    /// ```
+    /// use stop_token::StopSource;
+    ///
+    /// let source = StopSource::named("http-listener");
+    /// let token = source.token();
+    /// assert_eq!(token.name(), Some("http-listener"));
+    /// assert!(format!("{:?}", token).contains("http-listener"));
+    /// ```
+    pub fn named(name: impl Into<Cow<'static, str>>) -> StopSource {
+        StopSource::with_generation(0, Some(name.into()))
+    }
+
+    /// Returns the name of this source, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name.as_deref()
+    }
+
+    fn with_generation(generation: u64, name: Option<Cow<'static, str>>) -> StopSource {
+        let inner = Inner {
+            name,
+            source_generation: generation,
+            ..Inner::default()
+        };
+
+        StopSource {
+            inner: Arc::new(inner),
+            propagation: Propagation::default(),
+        }
+    }
+
+    /// Sets the order in which cancellation reaches the sources attached
+    /// below this one, directly or through other sources.
+    ///
+    /// This lets leaf tasks observe cancellation before their supervisors tear
+    /// down resources the leaves still depend on. Sources are only reached
+    /// through [`StopSource::attach_to`] and [`StopToken::or`]; tokens
+    /// combined with [`StopToken::all`] observe the cancellation when polled.
+    ///
+    /// ```
+    /// use stop_token::{Propagation, StopSource};
+    ///
+    /// let supervisor = StopSource::new().with_propagation(Propagation::ChildrenFirst);
+    /// let worker = StopSource::new();
+    /// worker.attach_to(&supervisor.token());
+    ///
+    /// supervisor.cancel();
+    /// assert!(worker.is_cancelled());
+    /// ```
+    pub fn with_propagation(mut self, propagation: Propagation) -> StopSource {
+        self.propagation = propagation;
+        self
+    }
+
+    /// Cancels all tokens of this source without dropping it.
+    ///
+    /// Tokens produced after this call are cancelled too, until the source is
+    /// re-armed with [`StopSource::reset`].
+    pub fn cancel(&self) {
+        self.inner.cancel_with(self.propagation);
+    }
+
+    /// Returns a guard which cancels this source when it is dropped.
+    ///
+    /// Unlike the source itself, the guard can be handed to the code whose
+    /// exit should cancel sibling work, and it also cancels the source when
+    /// that code returns early or panics. Call [`CancelGuard::disarm`] to
+    /// drop the guard without cancelling.
+    ///
+    /// ```
+    /// use stop_token::StopSource;
+    ///
+    /// let source = StopSource::new();
+    /// let result: Result<(), &str> = (|| {
+    ///     let _guard = source.cancel_on_drop();
+    ///     Err("early return")?;
+    ///     Ok(())
+    /// })();
+    ///
+    /// assert!(result.is_err());
+    /// assert!(source.is_cancelled());
+    /// ```
+    ///
+    /// The guard belongs to the current generation of the source: after a
+    /// [`StopSource::reset`], dropping it has no effect.
+    pub fn cancel_on_drop(&self) -> CancelGuard {
+        CancelGuard {
+            inner: Some(self.inner.clone()),
+            propagation: self.propagation,
+        }
+    }
+
+    /// Returns `true` if this source has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+
+    /// Re-arms this source, starting a new generation.
+    ///
+    /// Tokens issued before the reset are cancelled (if they weren't already)
+    /// and stay that way; tokens issued after it observe only the new
+    /// generation. The source is also detached from its parent, if any.
+    pub fn reset(&mut self) {
+        self.cancel();
+        let name = self.inner.name.clone();
+        *self = StopSource::with_generation(self.generation() + 1, name)
+            .with_propagation(self.propagation);
+    }
+
+    /// Returns the generation of this source.
+    ///
+    /// The generation starts at zero and is incremented by every call to
+    /// [`StopSource::reset`].
+    pub fn generation(&self) -> u64 {
+        self.inner.source_generation
+    }
+
     /// Produces a new `StopToken`, associated with this source.
     ///
     /// Once the source is destroyed, `StopToken` future completes.
     pub fn token(&self) -> StopToken {
-        self.stop_token.clone()
+        StopToken::new(self.inner.clone())
+    }
+
+    /// Returns a handle which can cancel this source from any context.
+    ///
+    /// [`StopTrigger::fire`] neither locks nor allocates, so it can be called
+    /// from OS signal handlers or FFI callbacks. The source is then cancelled
+    /// from a helper thread, in the order set with
+    /// [`StopSource::with_propagation`]. The thread is spawned by the first
+    /// call to this method, and exits once the source is cancelled or
+    /// dropped.
+    ///
+    /// A trigger belongs to the current generation of the source: after a
+    /// [`StopSource::reset`], previously created triggers have no effect.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the helper thread can't be spawned.
+    #[cfg(feature = "std")]
+    pub fn trigger(&self) -> StopTrigger {
+        let state = self.inner.trigger.get_or_init(|| {
+            let inner = Arc::downgrade(&self.inner);
+            let propagation = self.propagation;
+            let fired = Arc::new(AtomicBool::new(false));
+            let thread = std::thread::Builder::new()
+                .name("stop-token-trigger".into())
+                .spawn({
+                    let fired = fired.clone();
+                    move || loop {
+                        let inner = match inner.upgrade() {
+                            Some(inner) if !inner.is_cancelled() => inner,
+                            _ => break,
+                        };
+                        if fired.load(Ordering::Acquire) {
+                            inner.cancel_with(propagation);
+                            break;
+                        }
+                        drop(inner);
+                        std::thread::park();
+                    }
+                })
+                .expect("failed to spawn the trigger thread")
+                .thread()
+                .clone();
+            Arc::new(TriggerState { fired, thread })
+        });
+        // The source may have been cancelled before the thread could be
+        // unparked by it, so have the thread check again.
+        state.thread.unpark();
+        StopTrigger {
+            inner: self.inner.clone(),
+            propagation: self.propagation,
+            state: state.clone(),
+        }
+    }
+
+    /// Returns the number of live tokens produced by this source.
+    ///
+    /// Every clone of a token counts, including the copies held by child
+    /// sources attached to one of this source's tokens, and by flags made
+    /// with [`StopToken::as_flag`].
+    pub fn token_count(&self) -> usize {
+        self.inner.tokens.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many times a waiting task has been woken because this
+    /// source was cancelled.
+    ///
+    /// Each waiting task is woken exactly once per cancellation, and tokens
+    /// never wake their task while the source hasn't been cancelled. This
+    /// counter lets tests assert that.
+    #[cfg(feature = "test-util")]
+    pub fn wake_count(&self) -> usize {
+        self.inner.wake_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns a `futures` oneshot receiver which completes once this source
+    /// is cancelled or dropped.
+    ///
+    /// This lets legacy code which waits on a oneshot channel for shutdown be
+    /// driven by a `StopSource`, without rewriting it to take a token.
+    ///
+    /// ```
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// let shutdown = source.oneshot();
+    ///
+    /// source.cancel();
+    /// assert_eq!(shutdown.await, Ok(()));
+    /// # });
+    /// ```
+    #[cfg(feature = "oneshot")]
+    pub fn oneshot(&self) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.inner.senders.lock().push(sender);
+        // Cancellation may have raced with the push.
+        if self.is_cancelled() {
+            self.inner.send_oneshots();
+        }
+        receiver
+    }
+
+    /// Returns how many tokens have been acknowledged with
+    /// [`StopToken::acknowledge`] in the current generation.
+    pub fn acknowledged(&self) -> usize {
+        self.inner.acknowledged.load(Ordering::Acquire)
+    }
+
+    /// Waits until at least `n` tokens have been acknowledged.
+    ///
+    /// This lets the source await confirmation that every participant has
+    /// finished its cleanup after being cancelled:
+    ///
+    /// ```
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// let token = source.token();
+    /// let worker = async_std::task::spawn(async move {
+    ///     token.clone().await;
+    ///     // .. clean up ..
+    ///     token.acknowledge();
+    /// });
+    ///
+    /// source.cancel();
+    /// source.wait_acknowledged(1).await;
+    /// # worker.await;
+    /// # });
+    /// ```
+    pub fn wait_acknowledged(&self, n: usize) -> WaitAcknowledged<'_> {
+        WaitAcknowledged {
+            inner: &self.inner,
+            n,
+            key: None,
+        }
+    }
+
+    /// Attaches this source to a parent token.
+    ///
+    /// Once attached, the tokens of this source also complete when `parent`
+    /// completes. Tokens that were handed out before the call pick up the new
+    /// parent too. Attaching replaces any previously attached parent.
+    ///
+    /// When a token observes that its parent has completed, the source is
+    /// cancelled for good: detaching afterwards does not revive it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is one of this source's own tokens. Longer cycles are
+    /// not detected and must be avoided by the caller.
+    pub fn attach_to(&self, parent: &StopToken) {
+        assert!(
+            !Arc::ptr_eq(&self.inner, &parent.inner),
+            "a `StopSource` cannot be attached to its own token"
+        );
+        parent.inner.add_child(&self.inner);
+        self.inner.set_parents(alloc::vec![parent.clone()]);
+    }
+
+    /// Detaches this source from its parent token, if any.
+    ///
+    /// Afterwards, the tokens of this source only complete when the source
+    /// itself is dropped.
+    pub fn detach(&self) {
+        self.inner.set_parents(Vec::new());
+    }
+}
+
+impl fmt::Debug for StopSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StopSource")
+            .field("name", &self.name())
+            .field("generation", &self.generation())
+            .field("cancelled", &self.is_cancelled())
+            .field("tokens", &self.token_count())
+            .finish()
+    }
+}
+
+impl Drop for StopSource {
+    fn drop(&mut self) {
+        self.inner.cancel_with(self.propagation);
+    }
+}
+
+/// A guard which cancels a `StopSource` when it is dropped.
+///
+/// This type is returned by [`StopSource::cancel_on_drop`].
+#[must_use = "Dropping the guard immediately cancels the source"]
+#[derive(Debug)]
+pub struct CancelGuard {
+    inner: Option<Arc<Inner>>,
+    propagation: Propagation,
+}
+
+impl CancelGuard {
+    /// Drops the guard without cancelling the source.
+    pub fn disarm(mut self) {
+        self.inner = None;
     }
 }
 
-impl Into<Deadline> for StopToken {
-    fn into(self) -> Deadline {
-        Deadline {
-            kind: crate::deadline::DeadlineKind::StopToken { t: self },
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(inner) = &self.inner {
+            inner.cancel_with(self.propagation);
         }
     }
 }
 
+/// A handle which cancels a `StopSource` from any context.
+///
+/// This type is returned by [`StopSource::trigger`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct StopTrigger {
+    inner: Arc<Inner>,
+    propagation: Propagation,
+    state: Arc<TriggerState>,
+}
+
+/// The state shared between the triggers of a source and their helper
+/// thread.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct TriggerState {
+    fired: Arc<AtomicBool>,
+    /// The helper thread which cancels the source once fired.
+    thread: std::thread::Thread,
+}
+
+#[cfg(feature = "std")]
+impl StopTrigger {
+    /// Cancels the source this trigger was created from.
+    ///
+    /// This only stores a flag and unparks the helper thread, without locking
+    /// or allocating, so it is safe to call from a signal handler. The source
+    /// is cancelled shortly after, once the helper thread runs.
+    ///
+    /// Firing a trigger whose source has already been cancelled does nothing.
+    pub fn fire(&self) {
+        self.state.fired.store(true, Ordering::Release);
+        self.state.thread.unpark();
+    }
+
+    /// Cancels the source this trigger was created from on the current
+    /// thread, and wakes its waiting tasks before returning.
+    ///
+    /// This takes the source's locks and runs the wakers of the waiting
+    /// tasks, so unlike [`StopTrigger::fire`], it must not be called from a
+    /// signal handler.
+    pub fn cancel(&self) {
+        self.inner.cancel_with(self.propagation);
+    }
+}
+
+impl Inner {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Checks whether this source or its parents have been cancelled, without
+    /// registering a waker.
+    fn is_stopped(&self) -> bool {
+        if self.is_cancelled() {
+            return true;
+        }
+        let parents = self.parents.lock();
+        match self.mode {
+            Mode::Any => parents.iter().any(|parent| parent.inner.is_stopped()),
+            Mode::All => parents.iter().all(|parent| parent.inner.is_stopped()),
+        }
+    }
+
+    /// Sets the cancelled flag, returning `true` if this call is the one
+    /// which set it.
+    ///
+    /// Only that call goes on to report and wake, so each cancellation is
+    /// reported once however many paths race to cancel.
+    ///
+    /// The trigger thread, if any, is unparked so that it can exit.
+    fn set_cancelled(&self) -> bool {
+        let set = self
+            .cancelled
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        #[cfg(feature = "std")]
+        if let (true, Some(trigger)) = (set, self.trigger.get()) {
+            trigger.thread.unpark();
+        }
+        set
+    }
+
+    fn cancel(&self) {
+        if self.set_cancelled() {
+            self.wake_cancelled();
+        }
+    }
+
+    /// Cancels this source and the sources attached below it, in the given
+    /// order.
+    fn cancel_with(&self, propagation: Propagation) {
+        if propagation == Propagation::Simultaneous {
+            return self.cancel();
+        }
+        if !self.set_cancelled() {
+            return;
+        }
+
+        let mut order = Vec::new();
+        self.collect_children(1, &mut order);
+        if propagation == Propagation::ChildrenFirst {
+            // The sort is stable, so each level keeps the depth-first order.
+            order.sort_by_key(|&(depth, _)| Reverse(depth));
+        }
+
+        // Set every flag before waking anyone, so that no task observes its
+        // supervisor stopping before its own source.
+        let order: Vec<_> = order
+            .into_iter()
+            .filter(|(_, child)| child.set_cancelled())
+            .collect();
+        let mut woken = Vec::new();
+        for (_, child) in &order {
+            child.wake_once(&mut woken);
+        }
+        self.wake_once(&mut woken);
+    }
+
+    /// Collects the sources attached below this one, each subtree from the
+    /// bottom up, along with their depth.
+    fn collect_children(&self, depth: usize, order: &mut Vec<(usize, Arc<Inner>)>) {
+        let children: Vec<_> = self
+            .children
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        for child in children {
+            // A child waiting on all of its parents doesn't stop with this one
+            // alone, and a child may have been detached since.
+            if child.mode == Mode::All || !child.is_attached_to(self) {
+                continue;
+            }
+            child.collect_children(depth + 1, order);
+            order.push((depth + 1, child));
+        }
+    }
+
+    fn is_attached_to(&self, parent: &Inner) -> bool {
+        self.parents
+            .lock()
+            .iter()
+            .any(|token| core::ptr::eq(&*token.inner, parent))
+    }
+
+    fn add_child(&self, child: &Arc<Inner>) {
+        let child = Arc::downgrade(child);
+        let mut children = self.children.lock();
+        children.retain(|c| c.strong_count() > 0 && !c.ptr_eq(&child));
+        children.push(child);
+    }
+
+    /// Wakes every waiting token after cancellation, skipping the tasks in
+    /// `woken`.
+    ///
+    /// Tasks waiting on an attached source have also registered with this
+    /// one, so this keeps them from being woken twice.
+    fn wake_once(&self, woken: &mut Vec<Waker>) {
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        self.report_cancelled();
+        #[cfg(feature = "oneshot")]
+        self.send_oneshots();
+        let start = woken.len();
+        let wakers = self.wakers.lock().take_all();
+        for waker in wakers {
+            if !woken.iter().any(|w| w.will_wake(&waker)) {
+                woken.push(waker);
+            }
+        }
+        // Count before waking, so that a woken task sees its own wake.
+        #[cfg(feature = "test-util")]
+        self.wake_count
+            .fetch_add(woken.len() - start, Ordering::Relaxed);
+        for waker in &woken[start..] {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Makes the cancellation sticky, without waking anyone.
+    fn mark_cancelled(&self) {
+        if self.set_cancelled() {
+            #[cfg(any(feature = "tracing", feature = "metrics"))]
+            self.report_cancelled();
+        }
+        #[cfg(feature = "oneshot")]
+        self.send_oneshots();
+    }
+
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    fn report_cancelled(&self) {
+        let elapsed = self.created.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            name = self.name.as_deref(),
+            generation = self.source_generation,
+            elapsed = ?elapsed,
+            "stop source cancelled"
+        );
+        #[cfg(feature = "metrics")]
+        {
+            let labels: Vec<_> = self
+                .name
+                .iter()
+                .map(|name| metrics::Label::new("source", name.clone()))
+                .collect();
+            metrics::counter!("stop_token_cancellations_total", labels.clone()).increment(1);
+            if let Some(elapsed) = elapsed {
+                metrics::histogram!("stop_token_time_to_cancel_seconds", labels).record(elapsed);
+            }
+        }
+    }
+
+    /// Wakes every waiting token after cancellation.
+    fn wake_cancelled(&self) {
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        self.report_cancelled();
+        #[cfg(feature = "oneshot")]
+        self.send_oneshots();
+        let wakers = self.wakers.lock().take_all();
+        // Count before waking, so that a woken task sees its own wake.
+        #[cfg(feature = "test-util")]
+        self.wake_count.fetch_add(wakers.len(), Ordering::Relaxed);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Wakes every waiting token.
+    fn wake_all(&self) {
+        let wakers = self.wakers.lock().take_all();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    #[cfg(feature = "oneshot")]
+    fn send_oneshots(&self) {
+        for sender in self.senders.lock().drain(..) {
+            let _ = sender.send(());
+        }
+    }
+
+    /// Polls the external future, returning `true` if this call completed
+    /// it.
+    ///
+    /// The future is taken out of the lock while it is polled, so that one
+    /// which wakes itself right away doesn't deadlock. Calls made meanwhile
+    /// find no future and return `false`, and the call polling it polls
+    /// again if it was woken in between.
+    #[cfg(feature = "oneshot")]
+    fn poll_external(self: &Arc<Self>) -> bool {
+        let waker = Waker::from(Arc::new(ExternalWaker(Arc::downgrade(self))));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            let mut future = match self.external.lock().take() {
+                Some(future) => future,
+                None => return false,
+            };
+            let wakes = self.external_wakes.load(Ordering::Acquire);
+            if future.0.as_mut().poll(&mut cx).is_ready() {
+                return true;
+            }
+            *self.external.lock() = Some(future);
+            if self.external_wakes.load(Ordering::Acquire) == wakes {
+                return false;
+            }
+        }
+    }
+
+    fn set_parents(&self, parents: Vec<StopToken>) {
+        let mut state = self.parents.lock();
+        *state = parents;
+        self.version.fetch_add(1, Ordering::AcqRel);
+        drop(state);
+        self.wake_all();
+    }
+}
+
+impl StopToken {
+    /// Creates a token observing `inner`, counting it as a live token.
+    fn new(inner: Arc<Inner>) -> StopToken {
+        inner.tokens.fetch_add(1, Ordering::Relaxed);
+        StopToken {
+            inner,
+            key: None,
+            version: None,
+            parents: Vec::new(),
+        }
+    }
+
+    /// Creates a token which isn't owned by a `StopSource`, and instead stops
+    /// based on `parents`.
+    fn from_parents(parents: Vec<StopToken>, mode: Mode) -> StopToken {
+        let inner = Arc::new(Inner {
+            mode,
+            parents: Mutex::new(parents),
+            ..Inner::default()
+        });
+        for parent in inner.parents.lock().iter() {
+            parent.inner.add_child(&inner);
+        }
+        StopToken::new(inner)
+    }
+
+    /// Creates a token which stops once `receiver` completes, either because
+    /// a value was sent or because the sender was dropped.
+    ///
+    /// This lets legacy code which signals shutdown through a `futures`
+    /// oneshot channel drive code which takes a token. The token stops as
+    /// soon as the receiver completes, without having to be polled, so
+    /// [`StopToken::is_stopped`] and flags observe it right away.
+    ///
+    /// ```
+    /// use futures_channel::oneshot;
+    /// use stop_token::StopToken;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let (shutdown, receiver) = oneshot::channel::<()>();
+    /// let token = StopToken::from_oneshot(receiver);
+    ///
+    /// shutdown.send(()).unwrap();
+    /// assert!(token.is_stopped());
+    /// token.await;
+    /// # });
+    /// ```
+    #[cfg(feature = "oneshot")]
+    pub fn from_oneshot<T: Send + 'static>(receiver: oneshot::Receiver<T>) -> StopToken {
+        let future = async move {
+            let _ = receiver.await;
+        };
+        let inner = Arc::new(Inner {
+            external: Mutex::new(Some(External(alloc::boxed::Box::pin(future)))),
+            ..Inner::default()
+        });
+        // Register the waker which drives the future from now on.
+        if inner.poll_external() {
+            inner.cancel();
+        }
+        StopToken::new(inner)
+    }
+
+    /// Creates a token which never stops.
+    ///
+    /// This is useful for APIs which take a `StopToken`, when the caller has
+    /// no need for cancellation.
+    pub fn never() -> StopToken {
+        StopToken::from_parents(Vec::new(), Mode::Any)
+    }
+
+    /// Creates a token which has already stopped.
+    ///
+    /// This is useful in tests, and for paths which should fail fast.
+    pub fn already_cancelled() -> StopToken {
+        let token = StopToken::never();
+        token.inner.cancel();
+        token
+    }
+
+    /// Combines two tokens into one which stops once either of them stops.
+    ///
+    /// This is useful to merge a global shutdown token with a more local one,
+    /// such as a per-connection token:
+    ///
+    /// ```
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let shutdown = StopSource::new();
+    /// let connection = StopSource::new();
+    /// let token = shutdown.token().or(connection.token());
+    ///
+    /// drop(connection);
+    /// token.await;
+    /// # });
+    /// ```
+    pub fn or(self, other: StopToken) -> StopToken {
+        StopToken::from_parents(alloc::vec![self, other], Mode::Any)
+    }
+
+    /// Combines several tokens into one which stops once all of them have
+    /// stopped.
+    ///
+    /// This can gate final teardown on several independent subsystems having
+    /// each signalled stop. If `tokens` is empty, the resulting token is
+    /// stopped immediately.
+    ///
+    /// ```
+    /// use stop_token::{StopSource, StopToken};
+    ///
+    /// # async_std::task::block_on(async {
+    /// let http = StopSource::new();
+    /// let db = StopSource::new();
+    /// let token = StopToken::all([http.token(), db.token()]);
+    ///
+    /// drop(http);
+    /// drop(db);
+    /// token.await;
+    /// # });
+    /// ```
+    pub fn all<I>(tokens: I) -> StopToken
+    where
+        I: IntoIterator<Item = StopToken>,
+    {
+        StopToken::from_parents(tokens.into_iter().collect(), Mode::All)
+    }
+
+    /// Polls whether this token has been stopped.
+    ///
+    /// This is the same as polling the token as a future, but doesn't require
+    /// pinning. Hand-written `Future` and `Stream` implementations can use it
+    /// to check for cancellation without storing the token as a pinned
+    /// sub-future.
+    ///
+    /// Once this has returned `Poll::Ready`, subsequent calls keep returning
+    /// `Poll::Ready`.
+    pub fn poll_stopped(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.inner.is_cancelled() {
+            return self.stopped();
+        }
+
+        // Register before checking again, so that a `cancel` or `attach_to`
+        // racing with us is never missed.
+        self.inner.wakers.lock().register(&mut self.key, cx.waker());
+        if self.inner.is_cancelled() {
+            return self.stopped();
+        }
+
+        #[cfg(feature = "oneshot")]
+        if self.inner.poll_external() {
+            let stopped = self.stopped();
+            self.inner.cancel();
+            return stopped;
+        }
+
+        let version = self.inner.version.load(Ordering::Acquire);
+        if self.version != Some(version) {
+            self.parents = self.inner.parents.lock().clone();
+            self.version = Some(version);
+        }
+
+        let stopped = match self.inner.mode {
+            Mode::Any => self
+                .parents
+                .iter_mut()
+                .any(|parent| parent.poll_stopped(cx).is_ready()),
+            Mode::All => {
+                // Cancellation is sticky, so parents that have stopped can be
+                // forgotten.
+                self.parents
+                    .retain_mut(|parent| parent.poll_stopped(cx).is_pending());
+                self.parents.is_empty()
+            }
+        };
+        if stopped {
+            // Make the cancellation sticky for every token of this source.
+            // Tokens which are waiting have registered with the parents as
+            // well, and were woken by them, so they aren't woken again.
+            self.inner.mark_cancelled();
+            return self.stopped();
+        }
+        Poll::Pending
+    }
+
+    /// Returns `true` if this token has stopped.
+    ///
+    /// Unlike polling the token, this doesn't register for a wakeup, so it
+    /// suits synchronous code which checks for cancellation between steps.
+    pub fn is_stopped(&self) -> bool {
+        self.inner.is_stopped()
+    }
+
+    /// Returns a flag which is set once this token stops.
+    ///
+    /// The flag is a cheap, `Send + Sync` handle which can be checked without
+    /// an async context, so it can be handed to blocking code, FFI callbacks
+    /// or tight loops which can't await. It is set by whichever thread stops
+    /// the token, so no task is needed to keep it up to date, and checking it
+    /// is a single atomic load.
+    ///
+    /// ```
+    /// use stop_token::StopSource;
+    ///
+    /// let source = StopSource::new();
+    /// let flag = source.token().as_flag();
+    /// let worker = std::thread::spawn(move || {
+    ///     let mut rounds = 0u64;
+    ///     while !flag.is_set() {
+    ///         rounds += 1;
+    ///     }
+    ///     rounds
+    /// });
+    ///
+    /// drop(source);
+    /// worker.join().unwrap();
+    /// ```
+    pub fn as_flag(&self) -> StopFlag {
+        let state = Arc::new_cyclic(|state: &Weak<FlagState>| FlagState {
+            set: AtomicBool::new(false),
+            token: Mutex::new(Some(self.clone())),
+            waker: Waker::from(Arc::new(FlagWaker(state.clone()))),
+            wakes: AtomicUsize::new(0),
+        });
+        // Register with the token, which wakes the flag from now on.
+        state.poll();
+        StopFlag { state }
+    }
+
+    /// Runs `future` until it resolves, or until this token stops.
+    ///
+    /// This is `future.timeout_at(token.clone())`, written token first.
+    ///
+    /// ```
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// let token = source.token();
+    /// assert_eq!(token.race(async { 1 }).await, Ok(1));
+    ///
+    /// drop(source);
+    /// assert!(token.race(std::future::pending::<()>()).await.is_err());
+    /// # });
+    /// ```
+    pub fn race<F: Future>(&self, future: F) -> crate::future::TimeoutAt<F> {
+        use crate::future::FutureExt;
+        future.timeout_at(self.clone())
+    }
+
+    /// Runs `future` until it completes, or until this token stops.
+    ///
+    /// This is the entry point for running a whole subsystem until shutdown.
+    /// Tasks the subsystem starts can be tied to the same token through
+    /// [`scope`](crate::scope) or a [`TaskTracker`](crate::TaskTracker), so
+    /// they wind down along with it.
+    ///
+    /// ```
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let shutdown = StopSource::new();
+    /// let token = shutdown.token();
+    /// let server = async_std::task::spawn(token.clone().run_until(async move {
+    ///     stop_token::scope(token, |scope| async move {
+    ///         scope.spawn(|token| async move {
+    ///             // .. accept connections until shutdown ..
+    ///             token.await;
+    ///         });
+    ///         std::future::pending::<()>().await
+    ///     })
+    ///     .await
+    /// }));
+    ///
+    /// drop(shutdown);
+    /// assert!(server.await.is_err());
+    /// # });
+    /// ```
+    pub fn run_until<F: Future>(self, future: F) -> crate::future::TimeoutAt<F> {
+        use crate::future::FutureExt;
+        future.timeout_at(self)
+    }
+
+    /// Yields to the executor once, and then fails if this token has stopped.
+    ///
+    /// This gives CPU-heavy loops a cooperative cancellation point in one
+    /// line. A token which has already stopped fails right away, without
+    /// yielding.
+    ///
+    /// ```
+    /// use stop_token::{StopSource, TimedOutError};
+    ///
+    /// async fn sum(token: &stop_token::StopToken) -> Result<u64, TimedOutError> {
+    ///     let mut sum = 0;
+    ///     for i in 0..1_000u64 {
+    ///         if i % 100 == 0 {
+    ///             token.checkpoint().await?;
+    ///         }
+    ///         sum += i;
+    ///     }
+    ///     Ok(sum)
+    /// }
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// assert_eq!(sum(&source.token()).await, Ok(499_500));
+    ///
+    /// let token = source.token();
+    /// drop(source);
+    /// assert!(sum(&token).await.is_err());
+    /// # });
+    /// ```
+    pub fn checkpoint(&self) -> Checkpoint<'_> {
+        Checkpoint {
+            token: self,
+            yielded: false,
+        }
+    }
+
+    /// Yields to the executor if this token has stopped, or once `budget`
+    /// runs out, and resolves to whether the token has stopped.
+    ///
+    /// Unlike [`StopToken::checkpoint`], this doesn't fail, and it only yields
+    /// when needed, so hot loops can call it on every iteration at little
+    /// cost. The budget belongs to the caller, so each loop or task counts
+    /// its own iterations; it doesn't track how long the task has run, and
+    /// it isn't tied to the executor's own cooperative budget, such as
+    /// tokio's.
+    ///
+    /// ```
+    /// use stop_token::{StopSource, YieldBudget};
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// let token = source.token();
+    /// let mut budget = YieldBudget::new();
+    /// let mut processed = 0;
+    /// for _ in 0..1_000 {
+    ///     if token.yield_now_if_needed(&mut budget).await {
+    ///         break;
+    ///     }
+    ///     processed += 1;
+    /// }
+    /// assert_eq!(processed, 1_000);
+    /// # });
+    /// ```
+    pub fn yield_now_if_needed<'a>(&'a self, budget: &'a mut YieldBudget) -> YieldNowIfNeeded<'a> {
+        YieldNowIfNeeded {
+            token: self,
+            budget,
+            yielded: false,
+        }
+    }
+
+    /// Drops the waker registration once this token has stopped, so that the
+    /// task isn't woken again.
+    fn stopped(&mut self) -> Poll<()> {
+        if let Some(key) = self.key.take() {
+            self.inner.wakers.lock().remove(key);
+        }
+        // Dropping the copies of the parents deregisters this task from all
+        // of them, so parents which stop later don't wake it again.
+        self.parents.clear();
+        Poll::Ready(())
+    }
+
+    /// Acknowledges that the work guarded by this token has finished.
+    ///
+    /// This is usually called after the token has completed and cleanup has
+    /// run. See [`StopSource::wait_acknowledged`].
+    pub fn acknowledge(self) {
+        self.inner.acknowledged.fetch_add(1, Ordering::AcqRel);
+        let wakers = self.inner.acknowledged_wakers.lock().take_all();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Returns the name of the source this token was produced by, if it has
+    /// one.
+    ///
+    /// See [`StopSource::named`].
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name.as_deref()
+    }
+
+    /// Returns the generation of the source this token was produced by.
+    ///
+    /// See [`StopSource::generation`].
+    pub fn generation(&self) -> u64 {
+        self.inner.source_generation
+    }
+}
+
+/// The default token never stops.
+///
+/// This lets structs which carry an optional cancellation token derive
+/// `Default`.
+impl Default for StopToken {
+    fn default() -> StopToken {
+        StopToken::never()
+    }
+}
+
+impl fmt::Debug for StopToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StopToken")
+            .field("name", &self.name())
+            .field("generation", &self.generation())
+            .field("cancelled", &self.inner.is_cancelled())
+            .finish()
+    }
+}
+
+impl Clone for StopToken {
+    fn clone(&self) -> Self {
+        StopToken::new(self.inner.clone())
+    }
+}
+
+impl Drop for StopToken {
+    fn drop(&mut self) {
+        self.inner.tokens.fetch_sub(1, Ordering::Relaxed);
+        if let Some(key) = self.key {
+            self.inner.wakers.lock().remove(key);
+        }
+    }
+}
+
+impl From<StopToken> for Deadline {
+    fn from(t: StopToken) -> Deadline {
+        Deadline::from_kind(crate::deadline::DeadlineKind::StopToken { t })
+    }
+}
+
 impl Future for StopToken {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let chan = Pin::new(&mut self.chan);
-        match Stream::poll_next(chan, cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Some(never)) => match never {},
-            Poll::Ready(None) => Poll::Ready(()),
+        self.poll_stopped(cx)
+    }
+}
+
+/// Awaiting a borrowed token waits on a clone of it.
+impl IntoFuture for &StopToken {
+    type Output = ();
+    type IntoFuture = StopToken;
+
+    fn into_future(self) -> StopToken {
+        self.clone()
+    }
+}
+
+/// Awaiting a borrowed source waits until it is cancelled, without dropping
+/// it.
+///
+/// ```
+/// use stop_token::StopSource;
+///
+/// # async_std::task::block_on(async {
+/// let source = StopSource::new();
+/// source.cancel();
+/// (&source).await;
+/// # });
+/// ```
+impl IntoFuture for &StopSource {
+    type Output = ();
+    type IntoFuture = StopToken;
+
+    fn into_future(self) -> StopToken {
+        self.token()
+    }
+}
+
+/// A flag which is set once a token stops.
+///
+/// This flag is returned by [`StopToken::as_flag`].
+#[derive(Debug, Clone)]
+pub struct StopFlag {
+    state: Arc<FlagState>,
+}
+
+impl StopFlag {
+    /// Returns `true` once the token this flag was made from has stopped.
+    pub fn is_set(&self) -> bool {
+        self.state.set.load(Ordering::Acquire)
+    }
+}
+
+/// The state shared by the clones of a `StopFlag`.
+#[derive(Debug)]
+struct FlagState {
+    set: AtomicBool,
+    /// The token which sets the flag, until it stops.
+    token: Mutex<Option<StopToken>>,
+    /// Polls the token whenever it is woken.
+    waker: Waker,
+    /// Bumped on every wake, so that a wake which comes in while the token
+    /// is being polled isn't lost.
+    wakes: AtomicUsize,
+}
+
+impl FlagState {
+    /// Polls the token, setting the flag once it has stopped.
+    ///
+    /// The token is taken out of the lock while it is polled, the way
+    /// `Inner::poll_external` does it.
+    fn poll(&self) {
+        let mut cx = Context::from_waker(&self.waker);
+        loop {
+            let mut token = match self.token.lock().take() {
+                Some(token) => token,
+                None => return,
+            };
+            let wakes = self.wakes.load(Ordering::Acquire);
+            if token.poll_stopped(&mut cx).is_ready() {
+                self.set.store(true, Ordering::Release);
+                return;
+            }
+            *self.token.lock() = Some(token);
+            if self.wakes.load(Ordering::Acquire) == wakes {
+                return;
+            }
+        }
+    }
+}
+
+/// Wakes a `StopFlag`, without keeping it alive: dropping the last clone of a
+/// flag drops its token, which deregisters this waker.
+struct FlagWaker(Weak<FlagState>);
+
+impl alloc::task::Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        if let Some(state) = self.0.upgrade() {
+            state.wakes.fetch_add(1, Ordering::AcqRel);
+            state.poll();
+        }
+    }
+}
+
+/// A cooperative cancellation point.
+///
+/// This future is returned by [`StopToken::checkpoint`].
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct Checkpoint<'a> {
+    token: &'a StopToken,
+    yielded: bool,
+}
+
+impl Future for Checkpoint<'_> {
+    type Output = Result<(), TimedOutError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.token.is_stopped() {
+            return Poll::Ready(Err(Deadline::from(self.token.clone()).timed_out()));
+        }
+        if !self.yielded {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// How many calls to [`StopToken::yield_now_if_needed`] may go by without
+/// yielding.
+///
+/// Each loop or task keeps its own budget, so one busy loop doesn't make
+/// another one yield.
+#[derive(Debug, Clone)]
+pub struct YieldBudget {
+    every: u32,
+    left: u32,
+}
+
+impl YieldBudget {
+    /// Creates a budget which yields on every 128th call.
+    pub fn new() -> YieldBudget {
+        YieldBudget::every(128)
+    }
+
+    /// Creates a budget which yields on every `n`th call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn every(n: u32) -> YieldBudget {
+        assert!(n > 0, "a yield budget must allow at least one call");
+        YieldBudget { every: n, left: n }
+    }
+
+    /// Counts one call, and returns whether it should yield.
+    fn spend(&mut self) -> bool {
+        self.left -= 1;
+        if self.left == 0 {
+            self.left = self.every;
+            return true;
+        }
+        false
+    }
+}
+
+impl Default for YieldBudget {
+    fn default() -> Self {
+        YieldBudget::new()
+    }
+}
+
+/// A cancellation point which only yields when needed.
+///
+/// This future is returned by [`StopToken::yield_now_if_needed`].
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct YieldNowIfNeeded<'a> {
+    token: &'a StopToken,
+    budget: &'a mut YieldBudget,
+    yielded: bool,
+}
+
+impl Future for YieldNowIfNeeded<'_> {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let stopped = self.token.is_stopped();
+        if !self.yielded {
+            let due = self.budget.spend();
+            if stopped || due {
+                self.yielded = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(stopped)
+    }
+}
+
+/// A future which completes once enough tokens have been acknowledged.
+///
+/// This future is returned by [`StopSource::wait_acknowledged`].
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct WaitAcknowledged<'a> {
+    inner: &'a Inner,
+    n: usize,
+    key: Option<usize>,
+}
+
+impl Future for WaitAcknowledged<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.inner.acknowledged.load(Ordering::Acquire) >= this.n {
+            return Poll::Ready(());
+        }
+        this.inner
+            .acknowledged_wakers
+            .lock()
+            .register(&mut this.key, cx.waker());
+        if this.inner.acknowledged.load(Ordering::Acquire) >= this.n {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitAcknowledged<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.inner.acknowledged_wakers.lock().remove(key);
         }
     }
 }
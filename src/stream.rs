@@ -1,12 +1,15 @@
 //! Extension methods and types for the `Stream` trait.
 
 use crate::{deadline::TimedOutError, Deadline};
+use alloc::vec::Vec;
 use core::future::Future;
 use core::pin::Pin;
 
-use futures_core::Stream;
+use core::task::{Context, Poll};
+use futures_core::stream::FusedStream;
+use futures_core::{ready, Stream};
+use futures_sink::Sink;
 use pin_project_lite::pin_project;
-use std::task::{Context, Poll};
 
 /// Extend the `Stream` trait with the `until` method.
 pub trait StreamExt: Stream {
@@ -20,6 +23,270 @@ pub trait StreamExt: Stream {
         TimeoutAt {
             stream: self,
             deadline: target.into(),
+            policy: Policy::default(),
+            draining: false,
+            done: false,
+        }
+    }
+
+    /// Applies the token to the `stream`, such that the resulting stream
+    /// produces no more items once the token becomes cancelled.
+    ///
+    /// This is another name for [`StreamExt::timeout_at`].
+    fn until<T>(self, target: T) -> TimeoutAt<Self>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+    {
+        self.timeout_at(target)
+    }
+
+    /// Collects items from the stream until it is exhausted, or until a
+    /// deadline is hit.
+    ///
+    /// Resolves to the collected items together with the remainder of the
+    /// stream, so that work which was cut off can be persisted or re-queued.
+    fn partition_until<T>(self, target: T) -> PartitionUntil<Self>
+    where
+        Self: Sized + Unpin,
+        T: Into<Deadline>,
+    {
+        PartitionUntil {
+            stream: Some(self),
+            deadline: target.into(),
+            completed: Vec::new(),
+        }
+    }
+
+    /// Forwards all items of the stream into `sink` until the stream is
+    /// exhausted or a deadline is hit.
+    ///
+    /// Unlike `StreamExt::forward` from `futures`, this stops pulling new
+    /// items from the stream once the deadline fires. Any item that was
+    /// already pulled is still sent, and the sink is flushed before the
+    /// future resolves, so the last item accepted is always delivered in
+    /// full. This is the relay loop of a chat server, which must hang up
+    /// between messages:
+    ///
+    /// ```
+    /// use async_std::stream;
+    /// use stop_token::stream::{EndReason, StreamExt};
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let shutdown = StopSource::new();
+    /// let messages = stream::from_iter(vec!["hello", "world"]);
+    /// let mut peer = Vec::new();
+    ///
+    /// let relayed = messages
+    ///     .forward_until(&mut peer, shutdown.token())
+    ///     .await
+    ///     .unwrap();
+    /// assert_eq!(relayed.count, 2);
+    /// assert_eq!(relayed.reason, EndReason::Exhausted);
+    /// assert_eq!(peer, ["hello", "world"]);
+    /// # });
+    /// ```
+    fn forward_until<Si, T>(self, sink: Si, target: T) -> ForwardUntil<Self, Si, Self::Item>
+    where
+        Self: Sized,
+        Si: Sink<Self::Item>,
+        T: Into<Deadline>,
+    {
+        ForwardUntil {
+            stream: self,
+            sink,
+            deadline: target.into(),
+            buffered: None,
+            count: 0,
+            end: None,
+        }
+    }
+
+    /// Ends the stream once `changed` completes.
+    ///
+    /// `changed` can be any future representing "the world changed", such as
+    /// a configuration generation being bumped. It is only consulted while
+    /// waiting for the next item, so the stream always ends at an item
+    /// boundary. Unlike [`StreamExt::timeout_at`], the stream simply ends
+    /// instead of yielding an error.
+    ///
+    /// ```
+    /// use async_std::prelude::*;
+    /// use async_std::stream;
+    /// use stop_token::stream::StreamExt as _;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let (sender, receiver) = async_channel::bounded::<()>(1);
+    /// let changed = Box::pin(async move {
+    ///     let _ = receiver.recv().await;
+    /// });
+    ///
+    /// let mut work = stream::repeat(1).until_change(changed);
+    /// assert_eq!(work.next().await, Some(1));
+    /// sender.send(()).await.unwrap();
+    /// assert_eq!(work.next().await, None);
+    /// # });
+    /// ```
+    fn until_change<F>(self, changed: F) -> UntilChange<Self, F>
+    where
+        Self: Sized,
+        F: Future,
+    {
+        UntilChange {
+            stream: self,
+            changed,
+            done: false,
+        }
+    }
+
+    /// Ends the stream once a deadline is hit.
+    ///
+    /// Unlike [`StreamExt::timeout_at`], the stream keeps its item type and
+    /// simply ends instead of yielding an error, so `while let Some(item)`
+    /// loops stop on their own. Like the default [`Policy`], the deadline is
+    /// checked before each item is taken.
+    ///
+    /// ```
+    /// use async_std::prelude::*;
+    /// use async_std::stream;
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// let mut work = stream::repeat(1).until_done(source.token());
+    ///
+    /// assert_eq!(work.next().await, Some(1));
+    /// drop(source);
+    /// assert_eq!(work.next().await, None);
+    /// # });
+    /// ```
+    fn until_done<T>(self, target: T) -> UntilDone<Self>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+    {
+        UntilDone {
+            stream: self,
+            deadline: target.into(),
+            reason: None,
+        }
+    }
+
+    /// Ends the stream once a deadline is hit, yielding one last item made by
+    /// `last` first.
+    ///
+    /// This suits protocols which need to send a closing frame, such as a
+    /// `Goodbye` message, at an item boundary before hanging up. `last` isn't
+    /// called if the stream ends on its own first.
+    ///
+    /// ```
+    /// use async_std::prelude::*;
+    /// use async_std::stream;
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = StopSource::new();
+    /// let mut frames = stream::repeat("message").until_with_final(source.token(), || "goodbye");
+    ///
+    /// assert_eq!(frames.next().await, Some("message"));
+    /// drop(source);
+    /// assert_eq!(frames.next().await, Some("goodbye"));
+    /// assert_eq!(frames.next().await, None);
+    /// # });
+    /// ```
+    fn until_with_final<T, F>(self, target: T, last: F) -> UntilWithFinal<Self, F>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+        F: FnOnce() -> Self::Item,
+    {
+        UntilWithFinal {
+            stream: self,
+            deadline: target.into(),
+            last: Some(last),
+            done: false,
+        }
+    }
+
+    /// Applies an idle timeout to the stream, which restarts every time an
+    /// item arrives.
+    ///
+    /// A timeout error is yielded only when the gap between two items exceeds
+    /// `timeout`, unlike [`StreamExt::timeout_at`], which bounds the stream as
+    /// a whole. The timer restarts after the error as well, so the stream
+    /// only ends once the underlying stream does.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use async_std::prelude::*;
+    /// use stop_token::prelude::*;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let (_sender, receiver) = async_channel::bounded::<u8>(1);
+    /// let mut reads = receiver.timeout_between_items(Duration::from_millis(10));
+    /// assert!(reads.next().await.unwrap().is_err());
+    /// # }
+    /// ```
+    #[cfg(any(
+        feature = "async-io",
+        feature = "futures-timer",
+        feature = "tokio",
+        all(target_arch = "wasm32", feature = "wasm"),
+    ))]
+    fn timeout_between_items(self, timeout: core::time::Duration) -> TimeoutBetweenItems<Self>
+    where
+        Self: Sized,
+    {
+        TimeoutBetweenItems {
+            stream: self,
+            deadline: Deadline::after(timeout),
+            timeout,
+            done: false,
+        }
+    }
+
+    /// Applies a soft and a hard deadline to the stream.
+    ///
+    /// Once `soft` is hit, the item the stream is producing is still waited
+    /// for, but no new item is started. Once `hard` is hit, the stream bails
+    /// out right away, even in the middle of an item. Either way, the stream
+    /// yields a timeout error and then ends.
+    ///
+    /// This pairs with the tokens of a [`GracefulSource`](crate::GracefulSource):
+    ///
+    /// ```
+    /// use async_std::prelude::*;
+    /// use async_std::stream;
+    /// use stop_token::prelude::*;
+    /// use stop_token::GracefulSource;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let source = GracefulSource::new();
+    /// let token = source.token();
+    /// let mut work = stream::repeat(1).timeout_at_graceful(token.soft(), token.hard());
+    ///
+    /// assert_eq!(work.next().await, Some(Ok(1)));
+    /// source.cancel_soft();
+    /// assert!(work.next().await.unwrap().is_err());
+    /// assert!(work.next().await.is_none());
+    /// # });
+    /// ```
+    fn timeout_at_graceful<T, U>(self, soft: T, hard: U) -> TimeoutAtGraceful<Self>
+    where
+        Self: Sized,
+        T: Into<Deadline>,
+        U: Into<Deadline>,
+    {
+        TimeoutAtGraceful {
+            stream: self,
+            soft: soft.into(),
+            hard: hard.into(),
+            in_flight: false,
+            done: false,
         }
     }
 }
@@ -27,9 +294,9 @@ pub trait StreamExt: Stream {
 impl<S: Stream> StreamExt for S {}
 
 pin_project! {
-    /// Run a future until it resolves, or until a deadline is hit.
+    /// Run a stream until it completes, or until a deadline is hit.
     ///
-    /// This method is returned by [`FutureExt::deadline`].
+    /// This stream is returned by [`StreamExt::timeout_at`].
     #[must_use = "Futures do nothing unless polled or .awaited"]
     #[derive(Debug)]
     pub struct TimeoutAt<S> {
@@ -37,10 +304,97 @@ pin_project! {
         stream: S,
         #[pin]
         deadline: Deadline,
+        policy: Policy,
+        draining: bool,
+        done: bool,
     }
 }
 
+/// What a [`TimeoutAt`] stream does when the deadline and an item are ready
+/// at the same time.
+///
+/// See [`TimeoutAt::with_policy`].
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Policy {
+    /// Yield the item. The deadline is only consulted while the stream has no
+    /// item ready, so a stream which always has an item ready never times
+    /// out.
+    PreferItem,
+    /// Check the deadline before polling the stream, so no further items are
+    /// taken once it has been hit. Items which haven't been taken stay in the
+    /// stream, see [`TimeoutAt::into_inner`].
+    #[default]
+    PreferDeadline,
+    /// Like `PreferDeadline`, but also discard an item if the deadline was hit
+    /// while the stream was producing it, and end the stream after the
+    /// timeout error. Nothing is emitted past the cutoff, at the cost of
+    /// losing that item.
+    Strict,
+    /// Like `PreferDeadline`, but once the deadline has been hit, keep taking
+    /// the items the stream has ready without waiting, such as messages
+    /// already sitting in a channel. The timeout error is yielded once the
+    /// stream has no item ready, and then the stream ends.
+    Drain,
+}
+
 impl<S> TimeoutAt<S> {
+    /// Sets what this stream does when the deadline and an item are ready at
+    /// the same time.
+    ///
+    /// ```
+    /// use async_std::prelude::*;
+    /// use async_std::stream;
+    /// use stop_token::prelude::*;
+    /// use stop_token::stream::Policy;
+    /// use stop_token::StopToken;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let mut work = stream::once(1)
+    ///     .timeout_at(StopToken::already_cancelled())
+    ///     .with_policy(Policy::Strict);
+    /// assert!(work.next().await.unwrap().is_err());
+    /// assert!(work.next().await.is_none());
+    /// # });
+    /// ```
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the deadline this stream runs until.
+    ///
+    /// This lets code inspect the budget left, see [`Deadline::remaining`].
+    pub fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    /// Replaces the deadline this stream runs until, keeping the state of
+    /// the underlying stream.
+    ///
+    /// This lets a long-lived stream have its timeout extended, for example
+    /// whenever a keepalive arrives. A stream which has already ended stays
+    /// ended.
+    ///
+    /// ```
+    /// use async_std::prelude::*;
+    /// use async_std::stream;
+    /// use stop_token::prelude::*;
+    /// use stop_token::StopToken;
+    ///
+    /// # async_std::task::block_on(async {
+    /// let mut work = Box::pin(stream::repeat(1).timeout_at(StopToken::already_cancelled()));
+    /// assert!(work.next().await.unwrap().is_err());
+    ///
+    /// work.as_mut().set_deadline(StopToken::never());
+    /// assert_eq!(work.next().await, Some(Ok(1)));
+    /// # });
+    /// ```
+    pub fn set_deadline<T: Into<Deadline>>(self: Pin<&mut Self>, target: T) {
+        let mut this = self.project();
+        this.deadline.set(target.into());
+        *this.draining = false;
+    }
+
     /// Unwraps this `Stop` stream, returning the underlying stream.
     pub fn into_inner(self) -> S {
         self.stream
@@ -53,11 +407,821 @@ where
 {
     type Item = Result<S::Item, TimedOutError>;
 
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if *this.policy == Policy::Drain {
+            if !*this.draining && this.deadline.as_mut().poll(cx).is_ready() {
+                *this.draining = true;
+            }
+            if *this.draining {
+                return match this.stream.poll_next(cx) {
+                    Poll::Ready(Some(item)) => Poll::Ready(Some(Ok(item))),
+                    Poll::Ready(None) => {
+                        *this.done = true;
+                        Poll::Ready(None)
+                    }
+                    Poll::Pending => {
+                        *this.done = true;
+                        Poll::Ready(Some(Err(this.deadline.timed_out())))
+                    }
+                };
+            }
+        }
+        if *this.policy == Policy::PreferItem {
+            if let Poll::Ready(item) = this.stream.as_mut().poll_next(cx) {
+                *this.done = item.is_none();
+                return Poll::Ready(item.map(Ok));
+            }
+        }
+        if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+            *this.done = *this.policy == Policy::Strict;
+            return Poll::Ready(Some(Err(this.deadline.timed_out())));
+        }
+        if *this.policy == Policy::PreferItem {
+            return Poll::Pending;
+        }
+
+        let item = ready!(this.stream.poll_next(cx));
+        if item.is_none() {
+            *this.done = true;
+        }
+        if *this.policy == Policy::Strict && item.is_some() {
+            // The deadline may have been hit while the item was produced.
+            if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+                *this.done = true;
+                return Poll::Ready(Some(Err(this.deadline.timed_out())));
+            }
+        }
+        Poll::Ready(item.map(Ok))
+    }
+}
+
+impl<S: Stream> FusedStream for TimeoutAt<S> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+pin_project! {
+    /// Stop a stream between items on a soft deadline, and right away on a
+    /// hard deadline.
+    ///
+    /// This stream is returned by [`StreamExt::timeout_at_graceful`].
+    #[must_use = "Streams do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct TimeoutAtGraceful<S> {
+        #[pin]
+        stream: S,
+        #[pin]
+        soft: Deadline,
+        #[pin]
+        hard: Deadline,
+        in_flight: bool,
+        done: bool,
+    }
+}
+
+impl<S: Stream> Stream for TimeoutAtGraceful<S> {
+    type Item = Result<S::Item, TimedOutError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(()) = this.hard.as_mut().poll(cx) {
+            *this.done = true;
+            return Poll::Ready(Some(Err(this.hard.timed_out())));
+        }
+        if !*this.in_flight {
+            if let Poll::Ready(()) = this.soft.as_mut().poll(cx) {
+                *this.done = true;
+                return Poll::Ready(Some(Err(this.soft.timed_out())));
+            }
+        }
+
+        match this.stream.poll_next(cx) {
+            Poll::Ready(item) => {
+                *this.in_flight = false;
+                *this.done = item.is_none();
+                Poll::Ready(item.map(Ok))
+            }
+            Poll::Pending => {
+                *this.in_flight = true;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S: Stream> FusedStream for TimeoutAtGraceful<S> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+pin_project! {
+    /// A stream which yields one last item and ends once a deadline is hit.
+    ///
+    /// This stream is returned by [`StreamExt::until_with_final`].
+    #[must_use = "Streams do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct UntilWithFinal<S, F> {
+        #[pin]
+        stream: S,
+        #[pin]
+        deadline: Deadline,
+        last: Option<F>,
+        done: bool,
+    }
+}
+
+impl<S, F> Stream for UntilWithFinal<S, F>
+where
+    S: Stream,
+    F: FnOnce() -> S::Item,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+            *this.done = true;
+            return Poll::Ready(this.last.take().map(|last| last()));
+        }
+        let item = ready!(this.stream.poll_next(cx));
+        *this.done = item.is_none();
+        Poll::Ready(item)
+    }
+}
+
+impl<S, F> FusedStream for UntilWithFinal<S, F>
+where
+    S: Stream,
+    F: FnOnce() -> S::Item,
+{
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+cfg_duration_backend! {
+    pin_project! {
+        /// A stream which times out when the gap between two items is too long.
+        ///
+        /// This stream is returned by [`StreamExt::timeout_between_items`].
+        #[must_use = "Streams do nothing unless polled"]
+        #[derive(Debug)]
+        pub struct TimeoutBetweenItems<S> {
+            #[pin]
+            stream: S,
+            #[pin]
+            deadline: Deadline,
+            timeout: core::time::Duration,
+            done: bool,
+        }
+    }
+
+    impl<S> TimeoutBetweenItems<S> {
+        /// Unwraps this stream, returning the underlying stream.
+        pub fn into_inner(self) -> S {
+            self.stream
+        }
+    }
+
+    impl<S: Stream> Stream for TimeoutBetweenItems<S> {
+        type Item = Result<S::Item, TimedOutError>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let mut this = self.project();
+            if *this.done {
+                return Poll::Ready(None);
+            }
+            if let Poll::Ready(item) = this.stream.poll_next(cx) {
+                *this.done = item.is_none();
+                this.deadline.set(Deadline::after(*this.timeout));
+                return Poll::Ready(item.map(Ok));
+            }
+            ready!(this.deadline.as_mut().poll(cx));
+            let err = this.deadline.timed_out();
+            this.deadline.set(Deadline::after(*this.timeout));
+            Poll::Ready(Some(Err(err)))
+        }
+    }
+
+    impl<S: Stream> FusedStream for TimeoutBetweenItems<S> {
+        fn is_terminated(&self) -> bool {
+            self.done
+        }
+    }
+}
+
+cfg_duration_backend! {
+    /// Produce a tick every `period` until a deadline is hit.
+    ///
+    /// Each tick yields the time elapsed since the stream was created, which
+    /// unlike an `Instant` can also be read in the browser. The first tick
+    /// fires one period from now, and ticks which are missed because the
+    /// stream wasn't polled in time are skipped, see
+    /// [`RecurringDeadline::every`]. Once the deadline is hit, the stream
+    /// ends, so a maintenance loop bounded by shutdown is just a `while let`:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use async_std::prelude::*;
+    /// use stop_token::stream::interval_until;
+    /// use stop_token::StopSource;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let shutdown = StopSource::new();
+    /// let mut ticks = interval_until(Duration::from_millis(10), shutdown.token());
+    ///
+    /// let mut chores = 0;
+    /// while ticks.next().await.is_some() {
+    ///     // .. evict expired cache entries ..
+    ///     chores += 1;
+    ///     if chores == 3 {
+    ///         shutdown.cancel();
+    ///     }
+    /// }
+    /// assert_eq!(chores, 3);
+    /// # }
+    /// ```
+    ///
+    /// This function is only available when a backend which can count down
+    /// a `Duration` is enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    ///
+    /// [`RecurringDeadline::every`]: crate::RecurringDeadline::every
+    pub fn interval_until<T>(period: core::time::Duration, target: T) -> IntervalUntil
+    where
+        T: Into<Deadline>,
+    {
+        let mut recurring = crate::RecurringDeadline::every(period);
+        IntervalUntil {
+            tick: recurring.next_deadline(),
+            recurring,
+            started: crate::utils::Timestamp::now(),
+            deadline: target.into(),
+            done: false,
+        }
+    }
+
+    pin_project! {
+        /// A stream of ticks which ends once a deadline is hit.
+        ///
+        /// This stream is returned by [`interval_until`].
+        #[must_use = "Streams do nothing unless polled"]
+        #[derive(Debug)]
+        pub struct IntervalUntil {
+            recurring: crate::RecurringDeadline,
+            started: crate::utils::Timestamp,
+            #[pin]
+            tick: Deadline,
+            #[pin]
+            deadline: Deadline,
+            done: bool,
+        }
+    }
+
+    impl Stream for IntervalUntil {
+        type Item = core::time::Duration;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let mut this = self.project();
+            if *this.done {
+                return Poll::Ready(None);
+            }
+            if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+                *this.done = true;
+                return Poll::Ready(None);
+            }
+            ready!(this.tick.as_mut().poll(cx));
+            this.tick.set(this.recurring.next_deadline());
+            Poll::Ready(Some(this.started.elapsed()))
+        }
+    }
+
+    impl FusedStream for IntervalUntil {
+        fn is_terminated(&self) -> bool {
+            self.done
+        }
+    }
+}
+
+/// Wait for the next item of `stream`, biased towards the deadline.
+///
+/// The deadline is checked before the stream is polled, so once it has been
+/// hit no further items are taken. Because the deadline is only consulted while
+/// waiting for an item, work done with an item in between two calls is never
+/// preempted. This is the ordering a `futures::select_biased!` loop needs to
+/// get right by hand:
+///
+/// ```
+/// use async_std::stream;
+/// use stop_token::stream::next_biased;
+/// use stop_token::{Deadline, StopSource};
+///
+/// # async_std::task::block_on(async {
+/// let source = StopSource::new();
+/// let mut deadline: Deadline = source.token().into();
+/// let mut work = stream::from_iter(0..10);
+///
+/// while let Ok(Some(item)) = next_biased(&mut work, &mut deadline).await {
+///     // Processing an item always runs to completion.
+///     if item == 3 {
+///         source.cancel();
+///     }
+/// }
+/// # });
+/// ```
+///
+/// Resolves to `Ok(None)` when the stream is exhausted, and to
+/// `Err(TimedOutError)` once the deadline has been hit.
+pub fn next_biased<'a, S>(stream: &'a mut S, deadline: &'a mut Deadline) -> NextBiased<'a, S>
+where
+    S: Stream + Unpin + ?Sized,
+{
+    NextBiased { stream, deadline }
+}
+
+/// Wait for the next item of a stream, biased towards the deadline.
+///
+/// This future is returned by [`next_biased`].
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct NextBiased<'a, S: ?Sized> {
+    stream: &'a mut S,
+    deadline: &'a mut Deadline,
+}
+
+impl<S> Future for NextBiased<'_, S>
+where
+    S: Stream + Unpin + ?Sized,
+{
+    type Output = Result<Option<S::Item>, TimedOutError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Poll::Ready(()) = Pin::new(&mut *this.deadline).poll(cx) {
+            return Poll::Ready(Err(this.deadline.timed_out()));
+        }
+        Pin::new(&mut *this.stream).poll_next(cx).map(Ok)
+    }
+}
+
+/// The reason a cancellable stream operation ended.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EndReason {
+    /// The stream produced all of its items.
+    Exhausted,
+    /// The deadline was hit before the stream was exhausted.
+    TimedOut,
+}
+
+/// The outcome of a [`StreamExt::forward_until`] call.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Forwarded {
+    /// The number of items sent into the sink.
+    pub count: usize,
+    /// Why forwarding ended.
+    pub reason: EndReason,
+}
+
+pin_project! {
+    /// Forward a stream into a sink until the stream ends, or until a deadline is hit.
+    ///
+    /// This future is returned by [`StreamExt::forward_until`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct ForwardUntil<S, Si, Item> {
+        #[pin]
+        stream: S,
+        #[pin]
+        sink: Si,
+        #[pin]
+        deadline: Deadline,
+        buffered: Option<Item>,
+        count: usize,
+        end: Option<EndReason>,
+    }
+}
+
+impl<S, Si> Future for ForwardUntil<S, Si, S::Item>
+where
+    S: Stream,
+    Si: Sink<S::Item>,
+{
+    type Output = Result<Forwarded, Si::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            if this.buffered.is_some() {
+                ready!(this.sink.as_mut().poll_ready(cx))?;
+                let item = this.buffered.take().unwrap();
+                this.sink.as_mut().start_send(item)?;
+                *this.count += 1;
+            }
+
+            if let Some(reason) = *this.end {
+                ready!(this.sink.as_mut().poll_flush(cx))?;
+                return Poll::Ready(Ok(Forwarded {
+                    count: *this.count,
+                    reason,
+                }));
+            }
+
+            if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+                *this.end = Some(EndReason::TimedOut);
+                continue;
+            }
+
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => *this.buffered = Some(item),
+                Poll::Ready(None) => *this.end = Some(EndReason::Exhausted),
+                Poll::Pending => {
+                    ready!(this.sink.as_mut().poll_flush(cx))?;
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+/// The outcome of a [`StreamExt::partition_until`] call.
+#[derive(Debug)]
+pub struct Partitioned<S: Stream> {
+    /// The items that were taken from the stream before it ended.
+    pub completed: Vec<S::Item>,
+    /// The unconsumed remainder of the stream.
+    pub remainder: S,
+    /// Why collecting ended.
+    pub reason: EndReason,
+}
+
+pin_project! {
+    /// Collect items from a stream until it ends, or until a deadline is hit.
+    ///
+    /// This future is returned by [`StreamExt::partition_until`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct PartitionUntil<S: Stream> {
+        stream: Option<S>,
+        #[pin]
+        deadline: Deadline,
+        completed: Vec<S::Item>,
+    }
+}
+
+impl<S> Future for PartitionUntil<S>
+where
+    S: Stream + Unpin,
+{
+    type Output = Partitioned<S>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let stream = this
+            .stream
+            .as_mut()
+            .expect("`PartitionUntil` polled after completion");
+        let reason = loop {
+            if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+                break EndReason::TimedOut;
+            }
+            match ready!(Pin::new(&mut *stream).poll_next(cx)) {
+                Some(item) => this.completed.push(item),
+                None => break EndReason::Exhausted,
+            }
+        };
+        Poll::Ready(Partitioned {
+            completed: core::mem::take(this.completed),
+            remainder: this.stream.take().unwrap(),
+            reason,
+        })
+    }
+}
+
+pin_project! {
+    /// A stream which ends once a change has been signalled.
+    ///
+    /// This stream is returned by [`StreamExt::until_change`].
+    #[must_use = "Streams do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct UntilChange<S, F> {
+        #[pin]
+        stream: S,
+        #[pin]
+        changed: F,
+        done: bool,
+    }
+}
+
+impl<S, F> UntilChange<S, F> {
+    /// Unwraps this stream, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, F> Stream for UntilChange<S, F>
+where
+    S: Stream,
+    F: Future,
+{
+    type Item = S::Item;
+
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.project();
-        if let Poll::Ready(()) = this.deadline.poll(cx) {
-            return Poll::Ready(Some(Err(TimedOutError::new())));
+        if *this.done {
+            return Poll::Ready(None);
+        }
+        if this.changed.poll(cx).is_ready() {
+            *this.done = true;
+            return Poll::Ready(None);
+        }
+        this.stream.poll_next(cx)
+    }
+}
+
+pin_project! {
+    /// A stream which ends once a deadline is hit.
+    ///
+    /// This stream is returned by [`StreamExt::until_done`].
+    #[must_use = "Streams do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct UntilDone<S> {
+        #[pin]
+        stream: S,
+        #[pin]
+        deadline: Deadline,
+        reason: Option<EndReason>,
+    }
+}
+
+impl<S> UntilDone<S> {
+    /// Returns why the stream ended, or `None` if it hasn't ended yet.
+    pub fn end_reason(&self) -> Option<EndReason> {
+        self.reason
+    }
+
+    /// Unwraps this stream, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Stream> Stream for UntilDone<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if this.reason.is_some() {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(()) = this.deadline.as_mut().poll(cx) {
+            *this.reason = Some(EndReason::TimedOut);
+            return Poll::Ready(None);
+        }
+        let item = ready!(this.stream.poll_next(cx));
+        if item.is_none() {
+            *this.reason = Some(EndReason::Exhausted);
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl<S: Stream> FusedStream for UntilDone<S> {
+    fn is_terminated(&self) -> bool {
+        self.reason.is_some()
+    }
+}
+
+/// Merge two streams until both are exhausted, or until a deadline is hit.
+///
+/// Items are taken from both streams as they become ready, alternating which
+/// one is polled first. Once the merged stream has ended,
+/// [`SelectUntil::end_reason`] tells whether both inputs were exhausted or
+/// the deadline was hit.
+///
+/// ```
+/// use async_std::prelude::*;
+/// use async_std::stream;
+/// use stop_token::stream::{select_until, EndReason};
+/// use stop_token::StopToken;
+///
+/// # async_std::task::block_on(async {
+/// let mut merged = select_until(stream::once(1), stream::once(2), StopToken::never());
+/// let mut sum = 0;
+/// while let Some(item) = merged.next().await {
+///     sum += item;
+/// }
+/// assert_eq!(sum, 3);
+/// assert_eq!(merged.end_reason(), Some(EndReason::Exhausted));
+/// # });
+/// ```
+pub fn select_until<A, B, T>(a: A, b: B, target: T) -> SelectUntil<A, B>
+where
+    A: Stream,
+    B: Stream<Item = A::Item>,
+    T: Into<Deadline>,
+{
+    SelectUntil {
+        a,
+        b,
+        deadline: target.into(),
+        a_done: false,
+        b_done: false,
+        a_first: false,
+        end: None,
+    }
+}
+
+pin_project! {
+    /// A stream which merges two streams until a deadline is hit.
+    ///
+    /// This stream is returned by [`select_until`].
+    #[must_use = "Streams do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct SelectUntil<A, B> {
+        #[pin]
+        a: A,
+        #[pin]
+        b: B,
+        #[pin]
+        deadline: Deadline,
+        a_done: bool,
+        b_done: bool,
+        a_first: bool,
+        end: Option<EndReason>,
+    }
+}
+
+impl<A, B> SelectUntil<A, B> {
+    /// Returns why the merged stream ended, or `None` if it hasn't ended yet.
+    pub fn end_reason(&self) -> Option<EndReason> {
+        self.end
+    }
+}
+
+impl<A, B> Stream for SelectUntil<A, B>
+where
+    A: Stream,
+    B: Stream<Item = A::Item>,
+{
+    type Item = A::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if this.end.is_some() {
+            return Poll::Ready(None);
+        }
+        if this.deadline.as_mut().poll(cx).is_ready() {
+            *this.end = Some(EndReason::TimedOut);
+            return Poll::Ready(None);
+        }
+
+        *this.a_first = !*this.a_first;
+        for poll_a in [*this.a_first, !*this.a_first] {
+            let (done, poll) = match poll_a {
+                true if !*this.a_done => (&mut *this.a_done, this.a.as_mut().poll_next(cx)),
+                false if !*this.b_done => (&mut *this.b_done, this.b.as_mut().poll_next(cx)),
+                _ => continue,
+            };
+            match poll {
+                Poll::Ready(Some(item)) => return Poll::Ready(Some(item)),
+                Poll::Ready(None) => *done = true,
+                Poll::Pending => {}
+            }
+        }
+
+        if *this.a_done && *this.b_done {
+            *this.end = Some(EndReason::Exhausted);
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+/// Fetch pages until there are no more, or until a deadline is hit.
+///
+/// `fetch` is called with the cursor of the page to fetch, and resolves to the
+/// items of that page along with the cursor of the next page, if any. Items
+/// are accumulated across pages. If the deadline is hit while a page is being
+/// fetched, that fetch is dropped and its cursor is returned, so the caller
+/// can resume from it later.
+///
+/// ```
+/// use stop_token::stream::{paginate_until, EndReason};
+/// use stop_token::StopToken;
+///
+/// # async_std::task::block_on(async {
+/// let pages = paginate_until(0, StopToken::never(), |page| async move {
+///     let next = if page < 2 { Some(page + 1) } else { None };
+///     (vec![page * 10, page * 10 + 1], next)
+/// })
+/// .await;
+/// assert_eq!(pages.items, vec![0, 1, 10, 11, 20, 21]);
+/// assert_eq!(pages.cursor, None);
+/// assert_eq!(pages.reason, EndReason::Exhausted);
+/// # });
+/// ```
+pub fn paginate_until<C, T, F, Fut, D>(
+    initial: C,
+    target: D,
+    fetch: F,
+) -> PaginateUntil<C, T, F, Fut>
+where
+    C: Clone,
+    F: FnMut(C) -> Fut,
+    Fut: Future<Output = (Vec<T>, Option<C>)>,
+    D: Into<Deadline>,
+{
+    PaginateUntil {
+        fetch,
+        pending: None,
+        cursor: Some(initial),
+        items: Vec::new(),
+        deadline: target.into(),
+    }
+}
+
+/// The outcome of a [`paginate_until`] call.
+#[derive(Debug)]
+pub struct Paginated<C, T> {
+    /// The items of every page that was fetched.
+    pub items: Vec<T>,
+    /// The cursor to resume from, or `None` if every page was fetched.
+    pub cursor: Option<C>,
+    /// Why fetching ended.
+    pub reason: EndReason,
+}
+
+pin_project! {
+    /// Fetch pages until there are no more, or until a deadline is hit.
+    ///
+    /// This future is returned by [`paginate_until`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct PaginateUntil<C, T, F, Fut> {
+        fetch: F,
+        #[pin]
+        pending: Option<Fut>,
+        cursor: Option<C>,
+        items: Vec<T>,
+        #[pin]
+        deadline: Deadline,
+    }
+}
+
+impl<C, T, F, Fut> Future for PaginateUntil<C, T, F, Fut>
+where
+    C: Clone,
+    F: FnMut(C) -> Fut,
+    Fut: Future<Output = (Vec<T>, Option<C>)>,
+{
+    type Output = Paginated<C, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            if this.deadline.as_mut().poll(cx).is_ready() {
+                this.pending.set(None);
+                return Poll::Ready(Paginated {
+                    items: core::mem::take(this.items),
+                    cursor: this.cursor.take(),
+                    reason: EndReason::TimedOut,
+                });
+            }
+
+            if this.pending.is_none() {
+                match this.cursor {
+                    Some(cursor) => this.pending.set(Some((this.fetch)(cursor.clone()))),
+                    None => {
+                        return Poll::Ready(Paginated {
+                            items: core::mem::take(this.items),
+                            cursor: None,
+                            reason: EndReason::Exhausted,
+                        })
+                    }
+                }
+            }
+
+            let (page, next) = ready!(this.pending.as_mut().as_pin_mut().unwrap().poll(cx));
+            this.pending.set(None);
+            this.items.extend(page);
+            *this.cursor = next;
         }
-        this.stream.poll_next(cx).map(|el| el.map(|el| Ok(el)))
     }
 }
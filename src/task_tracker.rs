@@ -0,0 +1,209 @@
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use pin_project_lite::pin_project;
+
+use crate::utils::{Mutex, WakerList};
+use crate::{StopSource, StopToken};
+
+/// Hands out tokens to tasks, and keeps count of the tasks which are still
+/// running.
+///
+/// Cancelling tokens only asks tasks to stop; a `TaskTracker` lets whoever
+/// handles shutdown also wait until every task has actually exited. Futures
+/// are tracked with [`TaskTracker::track`] before being spawned, and
+/// [`TaskTracker::wait`] completes once the tracker is closed and all tracked
+/// futures have completed or been dropped.
+///
+/// ```
+/// use async_std::task;
+/// use stop_token::TaskTracker;
+///
+/// # task::block_on(async {
+/// let tracker = TaskTracker::new();
+/// for _ in 0..3 {
+///     let token = tracker.token();
+///     task::spawn(tracker.track(async move {
+///         token.await;
+///         // .. clean up ..
+///     }));
+/// }
+///
+/// tracker.cancel();
+/// tracker.close();
+/// tracker.wait().await;
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TaskTracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    source: StopSource,
+    live: AtomicUsize,
+    closed: AtomicBool,
+    wakers: Mutex<WakerList>,
+}
+
+impl Inner {
+    fn is_drained(&self) -> bool {
+        self.closed.load(Ordering::Acquire) && self.live.load(Ordering::Acquire) == 0
+    }
+
+    fn notify(&self) {
+        if self.is_drained() {
+            let wakers = self.wakers.lock().take_all();
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl TaskTracker {
+    /// Creates a new, open `TaskTracker`.
+    pub fn new() -> TaskTracker {
+        TaskTracker::default()
+    }
+
+    /// Produces a new `StopToken`, which stops once the tracker is cancelled.
+    pub fn token(&self) -> StopToken {
+        self.inner.source.token()
+    }
+
+    /// Cancels the tracker's tokens once `parent` stops.
+    ///
+    /// See [`StopSource::attach_to`].
+    pub fn attach_to(&self, parent: &StopToken) {
+        self.inner.source.attach_to(parent);
+    }
+
+    /// Tracks `future` until it completes or is dropped.
+    ///
+    /// Futures can still be tracked after the tracker has been closed.
+    pub fn track<Fut: Future>(&self, future: Fut) -> Tracked<Fut> {
+        self.inner.live.fetch_add(1, Ordering::AcqRel);
+        Tracked {
+            future,
+            inner: Some(self.inner.clone()),
+        }
+    }
+
+    /// Cancels the tracker's tokens.
+    pub fn cancel(&self) {
+        self.inner.source.cancel();
+    }
+
+    /// Closes the tracker, so that [`TaskTracker::wait`] can complete once no
+    /// tracked futures are left.
+    pub fn close(&self) {
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.notify();
+    }
+
+    /// Returns `true` if the tracker has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of tracked futures which haven't completed yet.
+    pub fn len(&self) -> usize {
+        self.inner.live.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if no tracked futures are running.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits until the tracker is closed and every tracked future has
+    /// completed or been dropped.
+    pub fn wait(&self) -> WaitTracked<'_> {
+        WaitTracked {
+            inner: &self.inner,
+            key: None,
+        }
+    }
+}
+
+pin_project! {
+    /// A future which is counted by a [`TaskTracker`] until it completes or
+    /// is dropped.
+    ///
+    /// This future is returned by [`TaskTracker::track`].
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct Tracked<Fut> {
+        #[pin]
+        future: Fut,
+        inner: Option<Arc<Inner>>,
+    }
+
+    impl<Fut> PinnedDrop for Tracked<Fut> {
+        fn drop(this: Pin<&mut Self>) {
+            if let Some(inner) = this.project().inner.take() {
+                untrack(&inner);
+            }
+        }
+    }
+}
+
+fn untrack(inner: &Inner) {
+    inner.live.fetch_sub(1, Ordering::AcqRel);
+    inner.notify();
+}
+
+impl<Fut: Future> Future for Tracked<Fut> {
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = match this.future.poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        if let Some(inner) = this.inner.take() {
+            untrack(&inner);
+        }
+        Poll::Ready(output)
+    }
+}
+
+/// A future which completes once a [`TaskTracker`] has been drained.
+///
+/// This future is returned by [`TaskTracker::wait`].
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct WaitTracked<'a> {
+    inner: &'a Inner,
+    key: Option<usize>,
+}
+
+impl Future for WaitTracked<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.inner.is_drained() {
+            return Poll::Ready(());
+        }
+        this.inner.wakers.lock().register(&mut this.key, cx.waker());
+        if this.inner.is_drained() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitTracked<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.inner.wakers.lock().remove(key);
+        }
+    }
+}
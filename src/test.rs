@@ -0,0 +1,247 @@
+//! Utilities for testing code which uses deadlines.
+//!
+//! # Features
+//!
+//! This module is only available when the `test-util` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use stop_token::clock::Clock;
+//! use stop_token::prelude::*;
+//! use stop_token::test::MockClock;
+//!
+//! # async_std::task::block_on(async {
+//! let clock = MockClock::new();
+//! let work = async_std::task::spawn(
+//!     std::future::pending::<()>().timeout_at(clock.after(Duration::from_secs(60))),
+//! );
+//!
+//! // No need to wait a minute.
+//! clock.advance(Duration::from_secs(60));
+//! assert!(work.await.is_err());
+//! # });
+//! ```
+//!
+//! Code which creates its deadlines from an `Instant` or a `Duration` can be
+//! run against a mock clock too, with [`MockClock::set_default`].
+
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use crate::clock::Clock;
+use crate::utils::{Mutex, WakerList};
+use crate::Deadline;
+
+/// A clock which only moves when told to.
+///
+/// Its instants are the time elapsed since the clock was created, which
+/// starts out at zero. Clones share the same time, so a test can hand a
+/// clone to the code under test and advance the time from the outside.
+/// Code which takes a [`Clock`], or the deadlines it creates, can then be
+/// tested without sleeping, and so can code which creates its deadlines from
+/// an `Instant` or a `Duration`, see [`MockClock::set_default`].
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "std"), derive(Default))]
+struct Inner {
+    now: Mutex<Duration>,
+    wakers: Mutex<WakerList>,
+    /// The real time the clock was created at, which its time zero stands
+    /// for.
+    #[cfg(feature = "std")]
+    started: crate::utils::Timestamp,
+}
+
+#[cfg(feature = "std")]
+impl Default for Inner {
+    fn default() -> Self {
+        Inner {
+            now: Mutex::default(),
+            wakers: Mutex::default(),
+            #[cfg(feature = "std")]
+            started: crate::utils::Timestamp::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// The clock set with `MockClock::set_default` on this thread, if any.
+    static DEFAULT: core::cell::RefCell<Option<MockClock>> = const { core::cell::RefCell::new(None) };
+}
+
+/// Returns the clock set with [`MockClock::set_default`] on this thread, if
+/// any.
+#[cfg(feature = "std")]
+pub(crate) fn default_clock() -> Option<MockClock> {
+    DEFAULT.with(|clock| clock.borrow().clone())
+}
+
+/// Creates a deadline on the default clock of this thread, if one is set,
+/// which is hit once the clock has moved forward by `remaining`.
+#[cfg(any(
+    feature = "async-io",
+    feature = "futures-timer",
+    feature = "tokio",
+    all(target_arch = "wasm32", feature = "wasm"),
+))]
+pub(crate) fn mocked(remaining: impl FnOnce() -> Duration) -> Option<Deadline> {
+    default_clock().map(|clock| clock.after(remaining()))
+}
+
+impl MockClock {
+    /// Creates a new clock, at time zero.
+    pub fn new() -> MockClock {
+        MockClock::default()
+    }
+
+    /// Moves the clock forward by `duration`, hitting the deadlines which
+    /// expire by then.
+    pub fn advance(&self, duration: Duration) {
+        *self.inner.now.lock() += duration;
+        let wakers = self.inner.wakers.lock().take_all();
+        wakers.into_iter().for_each(|waker| waker.wake());
+    }
+
+    /// Creates a deadline which is hit once the clock has moved forward by
+    /// `duration`.
+    pub fn after(&self, duration: Duration) -> Deadline {
+        self.deadline(self.now() + duration)
+    }
+
+    /// Makes the built-in timer backends follow this clock on the current
+    /// thread, until the returned guard is dropped.
+    ///
+    /// Meanwhile, deadlines created on this thread from an `Instant` or a
+    /// `Duration`, through the `async-io`, `futures-timer`, `tokio` or `wasm`
+    /// backends, are deadlines on this clock: they are hit once the clock
+    /// has moved forward by the time that was left when they were created.
+    /// Idle and recurring deadlines follow this clock as well.
+    ///
+    /// Only deadlines created on this thread are affected, so create them on
+    /// the test's own thread, for example with `block_on`, rather than in
+    /// spawned tasks.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use stop_token::test::MockClock;
+    /// use stop_token::Deadline;
+    ///
+    /// # #[cfg(not(feature = "async-io"))]
+    /// # fn main() {}
+    /// # #[cfg(feature = "async-io")]
+    /// # fn main() {
+    /// let clock = MockClock::new();
+    /// let _guard = clock.set_default();
+    ///
+    /// let deadline = Deadline::from(Duration::from_secs(60));
+    /// clock.advance(Duration::from_secs(45));
+    /// assert_eq!(deadline.remaining(), Some(Duration::from_secs(15)));
+    /// # }
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn set_default(&self) -> DefaultGuard {
+        let previous = DEFAULT.with(|clock| clock.replace(Some(self.clone())));
+        DefaultGuard {
+            previous,
+            _not_send: core::marker::PhantomData,
+        }
+    }
+
+    /// Returns the real time which corresponds to the current time of this
+    /// clock.
+    #[cfg(feature = "std")]
+    pub(crate) fn timestamp(&self) -> crate::utils::Timestamp {
+        self.inner.started + self.now()
+    }
+}
+
+/// A guard which keeps a [`MockClock`] the default on the current thread.
+///
+/// This type is returned by [`MockClock::set_default`]. Dropping it restores
+/// the previous default, if any.
+#[cfg(feature = "std")]
+#[must_use = "Dropping the guard immediately restores the previous default"]
+#[derive(Debug)]
+pub struct DefaultGuard {
+    previous: Option<MockClock>,
+    /// The guard belongs to the thread it was created on.
+    _not_send: core::marker::PhantomData<*const ()>,
+}
+
+#[cfg(feature = "std")]
+impl Drop for DefaultGuard {
+    fn drop(&mut self) {
+        DEFAULT.with(|clock| *clock.borrow_mut() = self.previous.take());
+    }
+}
+
+impl Clock for MockClock {
+    type Instant = Duration;
+    type Sleep = Sleep;
+
+    fn now(&self) -> Duration {
+        *self.inner.now.lock()
+    }
+
+    fn sleep_until(&self, deadline: Duration) -> Sleep {
+        Sleep {
+            inner: self.inner.clone(),
+            deadline,
+            key: None,
+        }
+    }
+
+    fn remaining(&self, deadline: Duration) -> Option<Duration> {
+        Some(deadline.saturating_sub(self.now()))
+    }
+
+    fn instant_after(&self, duration: Duration) -> Option<Duration> {
+        self.now().checked_add(duration)
+    }
+}
+
+/// A future which completes once a [`MockClock`] reaches an instant.
+///
+/// This future is returned by [`MockClock`]'s `sleep_until`.
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct Sleep {
+    inner: Arc<Inner>,
+    deadline: Duration,
+    key: Option<usize>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // Register first, so an `advance` racing with this poll isn't missed.
+        this.inner.wakers.lock().register(&mut this.key, cx.waker());
+        if *this.inner.now.lock() >= this.deadline {
+            if let Some(key) = this.key.take() {
+                this.inner.wakers.lock().remove(key);
+            }
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.inner.wakers.lock().remove(key);
+        }
+    }
+}
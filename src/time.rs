@@ -0,0 +1,24 @@
+cfg_duration_backend! {
+    use core::convert::TryInto;
+
+    use ::time::{OffsetDateTime, PrimitiveDateTime};
+
+    use crate::Deadline;
+
+    /// A deadline at a wall-clock time, at any UTC offset.
+    ///
+    /// Times in the past produce a deadline which is already hit.
+    impl From<OffsetDateTime> for Deadline {
+        fn from(time: OffsetDateTime) -> Deadline {
+            let remaining = time - OffsetDateTime::now_utc();
+            Deadline::after(remaining.try_into().unwrap_or_default())
+        }
+    }
+
+    /// A deadline at a wall-clock time, which is taken to be in UTC.
+    impl From<PrimitiveDateTime> for Deadline {
+        fn from(time: PrimitiveDateTime) -> Deadline {
+            time.assume_utc().into()
+        }
+    }
+}
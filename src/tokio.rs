@@ -10,50 +10,74 @@
 //!
 //! # Examples
 
-use std::future::{pending, Future, Pending};
+use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use tokio::time::{timeout_at, Instant as TokioInstant, Timeout};
+use tokio::time::{sleep_until, Instant as TokioInstant};
 
-/// A future that times out after a duration of time.
-#[must_use = "Futures do nothing unless polled or .awaited"]
-#[derive(Debug)]
-pub(crate) struct Deadline {
-    instant: TokioInstant,
-    delay: Pin<Box<Timeout<Pending<()>>>>,
-}
+/// The clock of the `tokio` timer backend.
+///
+/// See [`Clock`](crate::clock::Clock). It follows Tokio's notion of time, so
+/// deadlines respect `tokio::time::pause`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl crate::clock::Clock for TokioClock {
+    type Instant = TokioInstant;
+    type Sleep = Sleep;
+
+    fn now(&self) -> TokioInstant {
+        TokioInstant::now()
+    }
+
+    fn remaining(&self, deadline: TokioInstant) -> Option<std::time::Duration> {
+        Some(deadline.saturating_duration_since(TokioInstant::now()))
+    }
+
+    fn instant_after(&self, duration: std::time::Duration) -> Option<TokioInstant> {
+        TokioInstant::now().checked_add(duration)
+    }
 
-impl Clone for Deadline {
-    fn clone(&self) -> Self {
-        let instant = self.instant.clone();
-        Self {
-            instant,
-            delay: Box::pin(timeout_at(instant, pending())),
+    fn sleep_until(&self, deadline: TokioInstant) -> Sleep {
+        Sleep {
+            sleep: Box::pin(sleep_until(deadline)),
         }
     }
 }
 
-impl Future for Deadline {
+/// A future which completes at an instant.
+///
+/// This future is returned by [`TokioClock`]'s `sleep_until`.
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub struct Sleep {
+    sleep: Pin<Box<tokio::time::Sleep>>,
+}
+
+impl Future for Sleep {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match Pin::new(&mut self.delay).poll(cx) {
-            Poll::Ready(_) => Poll::Ready(()),
-            Poll::Pending => Poll::Pending,
-        }
+        self.sleep.as_mut().poll(cx)
     }
 }
 
-impl Into<crate::Deadline> for tokio::time::Instant {
-    fn into(self) -> crate::Deadline {
-        let instant = TokioInstant::from(self);
-        let deadline = Deadline {
-            instant,
-            delay: Box::pin(timeout_at(instant, pending())),
-        };
-
-        crate::Deadline {
-            kind: crate::deadline::DeadlineKind::Tokio { t: deadline },
+impl From<TokioInstant> for crate::Deadline {
+    fn from(instant: TokioInstant) -> crate::Deadline {
+        use crate::clock::Clock;
+        #[cfg(feature = "test-util")]
+        if let Some(deadline) =
+            crate::test::mocked(|| instant.saturating_duration_since(TokioInstant::now()))
+        {
+            return deadline;
         }
+        TokioClock.deadline(instant)
+    }
+}
+
+/// Lets code which used `tokio::time::timeout` keep its error handling.
+impl From<tokio::time::error::Elapsed> for crate::TimedOutError {
+    fn from(_: tokio::time::error::Elapsed) -> crate::TimedOutError {
+        crate::TimedOutError::opaque()
     }
 }
@@ -0,0 +1,289 @@
+//! Apply deadlines and a shutdown token to `tower` services.
+//!
+//! [`StopLayer`] wraps every call of a service in [`FutureExt::timeout_at`],
+//! with a deadline combining the shutdown token, an optional timeout per
+//! call, and an optional deadline carried by the request itself, such as one
+//! stored in the extensions of an `http::Request`.
+//!
+//! When the deadline is hit, the call resolves to the timeout error,
+//! converted into the service's error type with `From<TimedOutError>`. This
+//! is the case for `Box<dyn Error + Send + Sync>`, the error type most
+//! `tower` middleware uses, and for `std::io::Error`. Other errors are
+//! mapped by implementing `From<TimedOutError>` for them.
+//!
+//! # Features
+//!
+//! This module is only available when the `tower` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::future::{ready, Ready};
+//! use std::task::{Context, Poll};
+//! use stop_token::tower::StopLayer;
+//! use stop_token::StopSource;
+//! use tower_layer::Layer;
+//! use tower_service::Service;
+//!
+//! type BoxError = Box<dyn std::error::Error + Send + Sync>;
+//!
+//! struct Echo;
+//!
+//! impl Service<String> for Echo {
+//!     type Response = String;
+//!     type Error = BoxError;
+//!     type Future = Ready<Result<String, BoxError>>;
+//!
+//!     fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+//!         Poll::Ready(Ok(()))
+//!     }
+//!
+//!     fn call(&mut self, req: String) -> Self::Future {
+//!         ready(Ok(req))
+//!     }
+//! }
+//!
+//! # async_std::task::block_on(async {
+//! let shutdown = StopSource::new();
+//! let mut service = StopLayer::new(shutdown.token()).layer(Echo);
+//! assert_eq!(service.call("hello".into()).await.unwrap(), "hello");
+//!
+//! drop(shutdown);
+//! assert!(service.call("hello".into()).await.is_err());
+//! # });
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures_core::ready;
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::future::{FutureExt, TimeoutAt};
+use crate::{Deadline, TimedOutError};
+
+/// Reads the deadline a request carries, if any.
+///
+/// This is implemented for closures taking a reference to the request, see
+/// [`StopLayer::request_deadline`].
+pub trait RequestDeadline<Req> {
+    /// Returns the deadline `req` carries, if any.
+    fn deadline(&self, req: &Req) -> Option<Deadline>;
+}
+
+impl<Req, F> RequestDeadline<Req> for F
+where
+    F: Fn(&Req) -> Option<Deadline>,
+{
+    fn deadline(&self, req: &Req) -> Option<Deadline> {
+        self(req)
+    }
+}
+
+/// Requests carry no deadline of their own.
+///
+/// This is the default of [`StopLayer`] and [`StopService`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoRequestDeadline {
+    _private: (),
+}
+
+impl<Req> RequestDeadline<Req> for NoRequestDeadline {
+    fn deadline(&self, _: &Req) -> Option<Deadline> {
+        None
+    }
+}
+
+/// A layer which stops the calls of a service once a deadline is hit.
+///
+/// See the [module documentation](self).
+#[derive(Clone)]
+pub struct StopLayer<F = NoRequestDeadline> {
+    shutdown: Deadline,
+    #[cfg(any(
+        feature = "async-io",
+        feature = "futures-timer",
+        feature = "tokio",
+        all(target_arch = "wasm32", feature = "wasm"),
+    ))]
+    timeout: Option<std::time::Duration>,
+    request_deadline: F,
+}
+
+impl StopLayer {
+    /// Creates a layer which stops every call once `shutdown` is hit, usually
+    /// the token of the server's shutdown source.
+    pub fn new<T: Into<Deadline>>(shutdown: T) -> StopLayer {
+        StopLayer {
+            shutdown: shutdown.into(),
+            #[cfg(any(
+                feature = "async-io",
+                feature = "futures-timer",
+                feature = "tokio",
+                all(target_arch = "wasm32", feature = "wasm"),
+            ))]
+            timeout: None,
+            request_deadline: NoRequestDeadline::default(),
+        }
+    }
+}
+
+impl<F> StopLayer<F> {
+    /// Also stops every call once `timeout` has elapsed since it was made.
+    #[cfg(any(
+        feature = "async-io",
+        feature = "futures-timer",
+        feature = "tokio",
+        all(target_arch = "wasm32", feature = "wasm"),
+    ))]
+    pub fn timeout(mut self, timeout: std::time::Duration) -> StopLayer<F> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Also stops every call once the deadline `f` reads from its request is
+    /// hit.
+    ///
+    /// ```
+    /// use stop_token::tower::StopLayer;
+    /// use stop_token::{Deadline, StopToken};
+    ///
+    /// /// A request which may carry its caller's deadline.
+    /// struct Request {
+    ///     deadline: Option<Deadline>,
+    /// }
+    ///
+    /// let layer = StopLayer::new(StopToken::never())
+    ///     .request_deadline(|req: &Request| req.deadline.clone());
+    /// ```
+    pub fn request_deadline<G>(self, f: G) -> StopLayer<G> {
+        StopLayer {
+            shutdown: self.shutdown,
+            #[cfg(any(
+                feature = "async-io",
+                feature = "futures-timer",
+                feature = "tokio",
+                all(target_arch = "wasm32", feature = "wasm"),
+            ))]
+            timeout: self.timeout,
+            request_deadline: f,
+        }
+    }
+}
+
+impl<F> fmt::Debug for StopLayer<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StopLayer")
+            .field("shutdown", &self.shutdown)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, F: Clone> Layer<S> for StopLayer<F> {
+    type Service = StopService<S, F>;
+
+    fn layer(&self, inner: S) -> StopService<S, F> {
+        StopService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// A service which stops its calls once a deadline is hit.
+///
+/// This service is returned by [`StopLayer`].
+#[derive(Clone)]
+pub struct StopService<S, F = NoRequestDeadline> {
+    inner: S,
+    layer: StopLayer<F>,
+}
+
+impl<S, F> StopService<S, F> {
+    /// Returns a reference to the underlying service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwraps this service, returning the underlying service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: fmt::Debug, F> fmt::Debug for StopService<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StopService")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S, F, Req> Service<Req> for StopService<S, F>
+where
+    S: Service<Req>,
+    S::Error: From<TimedOutError>,
+    F: RequestDeadline<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> ResponseFuture<S::Future> {
+        let mut deadlines = Vec::new();
+        deadlines.extend(self.layer.request_deadline.deadline(&req));
+        #[cfg(any(
+            feature = "async-io",
+            feature = "futures-timer",
+            feature = "tokio",
+            all(target_arch = "wasm32", feature = "wasm"),
+        ))]
+        deadlines.extend(self.layer.timeout.map(Deadline::after));
+
+        let deadline = if deadlines.is_empty() {
+            self.layer.shutdown.clone()
+        } else {
+            deadlines.push(self.layer.shutdown.clone());
+            Deadline::earliest(deadlines)
+        };
+        ResponseFuture {
+            inner: self.inner.call(req).timeout_at(deadline),
+        }
+    }
+}
+
+pin_project! {
+    /// The response of a [`StopService`], which resolves to the timeout error
+    /// once the call's deadline is hit.
+    #[must_use = "Futures do nothing unless polled or .awaited"]
+    #[derive(Debug)]
+    pub struct ResponseFuture<Fut> {
+        #[pin]
+        inner: TimeoutAt<Fut>,
+    }
+}
+
+impl<Fut, T, E> Future for ResponseFuture<Fut>
+where
+    Fut: Future<Output = Result<T, E>>,
+    E: From<TimedOutError>,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match ready!(self.project().inner.poll(cx)) {
+            Ok(res) => Poll::Ready(res),
+            Err(err) => Poll::Ready(Err(err.into())),
+        }
+    }
+}
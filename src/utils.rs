@@ -0,0 +1,255 @@
+use alloc::vec::Vec;
+use core::task::Waker;
+
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use core::future::Future;
+#[cfg(feature = "std")]
+use core::pin::Pin;
+#[cfg(feature = "std")]
+use core::task::{Context, Poll};
+#[cfg(feature = "std")]
+use std::task::Wake;
+#[cfg(feature = "std")]
+use std::thread::{self, Thread};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+struct ThreadWaker(Thread);
+
+#[cfg(feature = "std")]
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drive a future to completion on the current thread.
+#[cfg(feature = "std")]
+pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = alloc::boxed::Box::pin(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Drive a future on the current thread for at most `timeout`, returning
+/// `None` if it didn't complete in time.
+#[cfg(feature = "std")]
+pub(crate) fn block_on_timeout<F: Future>(fut: F, timeout: Duration) -> Option<F::Output> {
+    let mut fut = alloc::boxed::Box::pin(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return Some(output);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        thread::park_timeout(deadline - now);
+    }
+}
+
+/// Poll two futures, resolving with `true` if the first one wins.
+#[cfg(feature = "std")]
+pub(crate) async fn first_wins<A, B>(mut a: A, mut b: B) -> bool
+where
+    A: Future + Unpin,
+    B: Future + Unpin,
+{
+    core::future::poll_fn(|cx| {
+        if Pin::new(&mut a).poll(cx).is_ready() {
+            return Poll::Ready(true);
+        }
+        Pin::new(&mut b).poll(cx).map(|_| false)
+    })
+    .await
+}
+
+/// A list of wakers, each registered under a stable key.
+///
+/// Keys are reused once removed, so the list only grows to the number of
+/// registrations that are alive at the same time.
+#[derive(Debug, Default)]
+pub(crate) struct WakerList {
+    wakers: Vec<Option<Waker>>,
+    free: Vec<usize>,
+}
+
+impl WakerList {
+    /// Registers `waker` under `key`, allocating a key if there is none yet.
+    pub(crate) fn register(&mut self, key: &mut Option<usize>, waker: &Waker) {
+        let key = *key.get_or_insert_with(|| match self.free.pop() {
+            Some(key) => key,
+            None => {
+                self.wakers.push(None);
+                self.wakers.len() - 1
+            }
+        });
+        match &mut self.wakers[key] {
+            Some(registered) if registered.will_wake(waker) => {}
+            slot => *slot = Some(waker.clone()),
+        }
+    }
+
+    /// Removes the registration under `key`, freeing the key.
+    pub(crate) fn remove(&mut self, key: usize) {
+        self.wakers[key] = None;
+        self.free.push(key);
+    }
+
+    /// Takes all registered wakers, leaving their keys allocated.
+    pub(crate) fn take_all(&mut self) -> Vec<Waker> {
+        self.wakers.iter_mut().filter_map(Option::take).collect()
+    }
+}
+
+/// A mutex which is backed by the standard library when it is available, and
+/// by a spin lock otherwise.
+///
+/// Critical sections in this crate are short and never block, so spinning is
+/// acceptable on targets without an OS.
+#[derive(Debug, Default)]
+pub(crate) struct Mutex<T>(
+    #[cfg(feature = "std")] std::sync::Mutex<T>,
+    #[cfg(not(feature = "std"))] spin::Mutex<T>,
+);
+
+impl<T> Mutex<T> {
+    pub(crate) fn new(value: T) -> Self {
+        #[cfg(feature = "std")]
+        return Mutex(std::sync::Mutex::new(value));
+        #[cfg(not(feature = "std"))]
+        return Mutex(spin::Mutex::new(value));
+    }
+
+    /// Locks the mutex. Poisoning is ignored, so both backends behave the
+    /// same.
+    #[cfg(feature = "std")]
+    pub(crate) fn lock(&self) -> std::sync::MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn lock(&self) -> spin::MutexGuard<'_, T> {
+        self.0.lock()
+    }
+}
+
+/// A point in time, to measure how much time has passed since.
+///
+/// `std::time::Instant::now` panics on `wasm32-unknown-unknown`, so with the
+/// `wasm` feature the time is read from `Date.now()` there instead.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) struct Timestamp(
+    #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))] std::time::Instant,
+    /// Milliseconds since the Unix epoch.
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    f64,
+);
+
+#[cfg(feature = "std")]
+impl Timestamp {
+    /// Returns the current time, which follows the mock clock set as the
+    /// default on this thread, if any.
+    pub(crate) fn now() -> Timestamp {
+        #[cfg(feature = "test-util")]
+        if let Some(clock) = crate::test::default_clock() {
+            return clock.timestamp();
+        }
+        #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+        return Timestamp(std::time::Instant::now());
+        #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+        return Timestamp(js_sys::Date::now());
+    }
+
+    /// Returns the time from `earlier` to `self`, or zero if `earlier` is
+    /// later.
+    pub(crate) fn saturating_duration_since(self, earlier: Timestamp) -> Duration {
+        #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+        return self.0.saturating_duration_since(earlier.0);
+        #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+        return Duration::from_secs_f64((self.0 - earlier.0).max(0.0) / 1000.0);
+    }
+
+    pub(crate) fn elapsed(self) -> Duration {
+        Timestamp::now().saturating_duration_since(self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::ops::Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, duration: Duration) -> Timestamp {
+        #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+        return Timestamp(self.0 + duration);
+        #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+        return Timestamp(self.0 + duration.as_secs_f64() * 1000.0);
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::ops::AddAssign<Duration> for Timestamp {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+/// Returns the current wall-clock time.
+///
+/// Like `Instant::now`, `SystemTime::now` panics on `wasm32-unknown-unknown`,
+/// so with the `wasm` feature the time is read from `Date.now()` there.
+#[cfg(any(
+    feature = "serde",
+    feature = "async-io",
+    feature = "futures-timer",
+    feature = "tokio",
+    all(target_arch = "wasm32", feature = "wasm"),
+))]
+pub(crate) fn system_now() -> std::time::SystemTime {
+    #[cfg(not(all(target_arch = "wasm32", feature = "wasm")))]
+    return std::time::SystemTime::now();
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    return std::time::UNIX_EPOCH + Duration::from_secs_f64(js_sys::Date::now() / 1000.0);
+}
+
+/// The time something was created at, to report how long it lived.
+///
+/// There is no clock to read on `wasm32` without the `wasm` feature, so the
+/// time isn't known there.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Created(Option<Timestamp>);
+
+#[cfg(feature = "std")]
+impl Default for Created {
+    fn default() -> Self {
+        #[cfg(not(all(target_arch = "wasm32", not(feature = "wasm"))))]
+        return Created(Some(Timestamp::now()));
+        #[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+        return Created(None);
+    }
+}
+
+#[cfg(feature = "std")]
+impl Created {
+    pub(crate) fn elapsed(&self) -> Option<Duration> {
+        self.0.map(Timestamp::elapsed)
+    }
+}
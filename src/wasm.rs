@@ -0,0 +1,79 @@
+//! Create deadlines in the browser.
+//!
+//! Neither the `async-io` nor the `tokio` timers work on
+//! `wasm32-unknown-unknown`. This module implements `Into<Deadline>` for
+//! `Duration` on top of `setTimeout`, through `gloo-timers`.
+//!
+//! # Features
+//!
+//! This module is only available on `wasm32` targets, when the `wasm` feature
+//! is enabled. The deadlines it creates must stay on the thread they were
+//! created on.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use std::time::Duration;
+//! use stop_token::prelude::*;
+//!
+//! async fn fetch_with_timeout() {
+//!     let result = fetch().timeout_at(Duration::from_secs(5)).await;
+//! }
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use gloo_timers::future::TimeoutFuture;
+use send_wrapper::SendWrapper;
+
+/// A future that times out after a duration of time.
+#[must_use = "Futures do nothing unless polled or .awaited"]
+#[derive(Debug)]
+pub(crate) struct Deadline {
+    /// When the deadline is hit, in milliseconds since the Unix epoch.
+    target: f64,
+    delay: SendWrapper<TimeoutFuture>,
+}
+
+impl Deadline {
+    pub(crate) fn at(target: f64) -> Self {
+        let remaining = (target - js_sys::Date::now()).max(0.0);
+        Deadline {
+            target,
+            delay: SendWrapper::new(TimeoutFuture::new(remaining.ceil() as u32)),
+        }
+    }
+
+    pub(crate) fn remaining(&self) -> Duration {
+        Duration::from_secs_f64((self.target - js_sys::Date::now()).max(0.0) / 1000.0)
+    }
+}
+
+impl Clone for Deadline {
+    fn clone(&self) -> Self {
+        Deadline::at(self.target)
+    }
+}
+
+impl Future for Deadline {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.delay).poll(cx)
+    }
+}
+
+#[cfg(not(any(feature = "async-io", feature = "futures-timer")))]
+impl From<Duration> for crate::Deadline {
+    fn from(duration: Duration) -> crate::Deadline {
+        #[cfg(feature = "test-util")]
+        if let Some(deadline) = crate::test::mocked(|| duration) {
+            return deadline;
+        }
+        let deadline = Deadline::at(js_sys::Date::now() + duration.as_millis() as f64);
+        crate::Deadline::from_kind(crate::deadline::DeadlineKind::Wasm { t: deadline })
+    }
+}
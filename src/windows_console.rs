@@ -0,0 +1,99 @@
+//! Graceful shutdown for Windows console applications.
+//!
+//! Closing the console window, logging off, or shutting down the system sends
+//! a console control event to the process. Once such an event has been
+//! handled, Windows gives the process a few seconds before terminating it. A
+//! [`ConsoleStopSource`] turns these events into a cancellation, so the same
+//! `timeout_at` loops used by Unix daemons can drain their work in that time.
+//!
+//! Services are stopped through the service control manager instead; see
+//! `stop_token::windows_service::ServiceStopSource`. Both can be combined with
+//! [`StopToken::or`].
+//!
+//! # Features
+//!
+//! This module is only available on Windows, when the `windows-console`
+//! feature is enabled. The events are observed through `tokio`, so the source
+//! must be created from within a Tokio runtime.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use stop_token::prelude::*;
+//! use stop_token::windows_console::ConsoleStopSource;
+//!
+//! #[tokio::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let console = ConsoleStopSource::new()?;
+//!     let mut work = incoming_work().timeout_at(console.token());
+//!     while let Some(Ok(job)) = work.next().await {
+//!         job.await;
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use std::future::poll_fn;
+use std::io;
+use std::task::Poll;
+
+use ::tokio::signal::windows::{ctrl_close, ctrl_logoff, ctrl_shutdown};
+use ::tokio::task::JoinHandle;
+
+use crate::{StopSource, StopToken};
+
+/// A `StopSource` which is cancelled by console control events.
+///
+/// The source is cancelled when the process receives `CTRL_CLOSE_EVENT`,
+/// `CTRL_LOGOFF_EVENT`, or `CTRL_SHUTDOWN_EVENT`. Like a regular
+/// `StopSource`, dropping a `ConsoleStopSource` cancels its tokens.
+#[derive(Debug)]
+pub struct ConsoleStopSource {
+    token: StopToken,
+    task: JoinHandle<()>,
+}
+
+impl ConsoleStopSource {
+    /// Starts listening for console control events.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the console control handler can't be installed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    pub fn new() -> io::Result<ConsoleStopSource> {
+        let mut close = ctrl_close()?;
+        let mut logoff = ctrl_logoff()?;
+        let mut shutdown = ctrl_shutdown()?;
+        let source = StopSource::new();
+        let token = source.token();
+        let task = ::tokio::spawn(async move {
+            poll_fn(|cx| {
+                if close.poll_recv(cx).is_ready()
+                    || logoff.poll_recv(cx).is_ready()
+                    || shutdown.poll_recv(cx).is_ready()
+                {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+            drop(source);
+        });
+        Ok(ConsoleStopSource { token, task })
+    }
+
+    /// Produces a new `StopToken`, associated with this source.
+    pub fn token(&self) -> StopToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for ConsoleStopSource {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
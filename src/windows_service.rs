@@ -0,0 +1,146 @@
+//! Graceful shutdown for Windows services.
+//!
+//! # Features
+//!
+//! This module is only available on Windows, when the `windows-service`
+//! feature is enabled.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use std::time::Duration;
+//! use stop_token::prelude::*;
+//! use stop_token::windows_service::ServiceStopSource;
+//! use windows_service::service::ServiceExitCode;
+//!
+//! fn run_service() -> windows_service::Result<()> {
+//!     let service = ServiceStopSource::register("my-service")?;
+//!     service.set_running()?;
+//!
+//!     async_std::task::block_on(async {
+//!         let mut work = incoming_work().timeout_at(service.token());
+//!         while let Some(Ok(job)) = work.next().await {
+//!             // Tell the SCM we're still making progress while draining.
+//!             service.report_stop_pending(Duration::from_secs(10))?;
+//!             job.await;
+//!         }
+//!         Ok::<_, windows_service::Error>(())
+//!     })?;
+//!
+//!     service.set_stopped(ServiceExitCode::NO_ERROR)
+//! }
+//! ```
+
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use ::windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+
+use crate::{StopSource, StopToken};
+
+/// A `StopSource` which is cancelled by the service control manager.
+///
+/// The source is cancelled when the service receives a `STOP` or `SHUTDOWN`
+/// control event.
+#[derive(Debug)]
+pub struct ServiceStopSource {
+    source: Arc<StopSource>,
+    status: ServiceStatusHandle,
+    checkpoint: AtomicU32,
+}
+
+impl ServiceStopSource {
+    /// Registers the control handler for the service named `service_name`.
+    ///
+    /// This must be called from the service's main function, see
+    /// `windows_service::service_control_handler::register`.
+    pub fn register(service_name: impl AsRef<OsStr>) -> ::windows_service::Result<Self> {
+        let source = Arc::new(StopSource::new());
+        let status = service_control_handler::register(service_name, {
+            let source = source.clone();
+            move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    source.cancel();
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            }
+        })?;
+        Ok(ServiceStopSource {
+            source,
+            status,
+            checkpoint: AtomicU32::new(0),
+        })
+    }
+
+    /// Produces a new `StopToken`, which completes once the service is asked
+    /// to stop.
+    pub fn token(&self) -> StopToken {
+        self.source.token()
+    }
+
+    /// Reports `SERVICE_RUNNING`, accepting the `STOP` and `SHUTDOWN` controls.
+    pub fn set_running(&self) -> ::windows_service::Result<()> {
+        self.set_status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            ServiceExitCode::NO_ERROR,
+            0,
+            Duration::default(),
+        )
+    }
+
+    /// Reports `SERVICE_STOP_PENDING`, advancing the checkpoint.
+    ///
+    /// Call this periodically while draining, with a `wait_hint` covering the
+    /// time until the next report. Otherwise the service control manager
+    /// assumes the service has hung.
+    pub fn report_stop_pending(&self, wait_hint: Duration) -> ::windows_service::Result<()> {
+        let checkpoint = self.checkpoint.fetch_add(1, Ordering::Relaxed) + 1;
+        self.set_status(
+            ServiceState::StopPending,
+            ServiceControlAccept::empty(),
+            ServiceExitCode::NO_ERROR,
+            checkpoint,
+            wait_hint,
+        )
+    }
+
+    /// Reports `SERVICE_STOPPED` with the given exit code.
+    pub fn set_stopped(&self, exit_code: ServiceExitCode) -> ::windows_service::Result<()> {
+        self.set_status(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            exit_code,
+            0,
+            Duration::default(),
+        )
+    }
+
+    fn set_status(
+        &self,
+        current_state: ServiceState,
+        controls_accepted: ServiceControlAccept,
+        exit_code: ServiceExitCode,
+        checkpoint: u32,
+        wait_hint: Duration,
+    ) -> ::windows_service::Result<()> {
+        self.status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code,
+            checkpoint,
+            wait_hint,
+            process_id: None,
+        })
+    }
+}
@@ -1,10 +1,12 @@
+#![cfg(not(target_arch = "wasm32"))]
+
 use std::time::Duration;
 
 use async_std::prelude::*;
 use stop_token::prelude::*;
 
 use async_channel::bounded;
-use async_std::task;
+use async_std::{stream, task};
 
 use stop_token::StopSource;
 
@@ -97,3 +99,2457 @@ async fn tokio_time() {
     sender.send(6).await.unwrap();
     assert_eq!(task.await.unwrap(), vec![1, 2, 3]);
 }
+
+#[test]
+fn forward_until() {
+    use stop_token::stream::EndReason;
+    use stop_token::StopToken;
+    task::block_on(async {
+        let source = StopSource::new();
+        let mut sink = Vec::new();
+        let res = stream::from_iter(vec![1, 2, 3])
+            .forward_until(&mut sink, source.token())
+            .await
+            .unwrap();
+        assert_eq!(res.count, 3);
+        assert_eq!(res.reason, EndReason::Exhausted);
+        assert_eq!(sink, vec![1, 2, 3]);
+
+        let token = source.token();
+        drop(source);
+        let mut sink = Vec::new();
+        let res = stream::repeat(1)
+            .forward_until(&mut sink, token)
+            .await
+            .unwrap();
+        assert_eq!(res.count, 0);
+        assert_eq!(res.reason, EndReason::TimedOut);
+
+        let mut sink = Vec::new();
+        let res = stream::once(1)
+            .forward_until(&mut sink, StopToken::never())
+            .await
+            .unwrap();
+        assert_eq!(res.count, 1);
+        assert_eq!(sink, vec![1]);
+    })
+}
+
+#[test]
+fn attach_detach() {
+    task::block_on(async {
+        let parent = StopSource::new();
+        let child = StopSource::new();
+        let token = child.token();
+
+        child.attach_to(&parent.token());
+        child.detach();
+        drop(parent);
+        let res = async_std::future::timeout(Duration::from_millis(50), token.clone()).await;
+        assert!(res.is_err());
+
+        let parent = StopSource::new();
+        let waiter = task::spawn(token);
+        task::sleep(Duration::from_millis(50)).await;
+        child.attach_to(&parent.token());
+        drop(parent);
+        waiter.await;
+    })
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn on_expire() {
+    use stop_token::Deadline;
+    task::block_on(async {
+        let (sender, receiver) = bounded::<&str>(2);
+
+        let source = StopSource::new();
+        let deadline: Deadline = source.token().into();
+        let guard = deadline.on_expire({
+            let sender = sender.clone();
+            move || sender.try_send("expired").unwrap()
+        });
+        drop(source);
+        assert_eq!(receiver.recv().await.unwrap(), "expired");
+        drop(guard);
+
+        let source = StopSource::new();
+        let deadline: Deadline = source.token().into();
+        drop(deadline.on_expire(move || sender.try_send("disarmed").unwrap()));
+        drop(source);
+        assert!(receiver.recv().await.is_err());
+    })
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn on_expire_runs_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use stop_token::Deadline;
+
+    task::block_on(async {
+        let (sender, receiver) = bounded::<()>(2);
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let mut source = StopSource::new();
+        let deadline: Deadline = source.token().into();
+        let guard = deadline.clone().on_expire({
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                sender.try_send(()).unwrap();
+            }
+        });
+
+        // Stopping the source again, or re-arming it, doesn't run it twice.
+        source.cancel();
+        source.cancel();
+        receiver.recv().await.unwrap();
+        source.reset();
+        drop(source);
+        drop(deadline);
+        assert!(receiver.recv().await.is_err());
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        drop(guard);
+    })
+}
+
+#[test]
+fn reset() {
+    task::block_on(async {
+        let mut source = StopSource::new();
+        assert_eq!(source.token_count(), 0);
+        let old = source.token();
+        assert_eq!(source.token_count(), 1);
+        source.cancel();
+        assert!(source.is_cancelled());
+        old.clone().await;
+
+        source.reset();
+        assert!(!source.is_cancelled());
+        assert_eq!(source.generation(), 1);
+        let new = source.token();
+        assert_eq!(source.token_count(), 1);
+        assert_eq!((old.generation(), new.generation()), (0, 1));
+        old.await;
+        let res = async_std::future::timeout(Duration::from_millis(50), new).await;
+        assert!(res.is_err());
+    })
+}
+
+#[test]
+fn named() {
+    use stop_token::StopToken;
+
+    // The name is shared with the tokens, and shows up in `Debug` output.
+    let source = StopSource::named("db");
+    let token = source.token();
+    assert_eq!(source.name(), Some("db"));
+    assert_eq!(token.name(), Some("db"));
+    assert!(format!("{:?}", source).contains(r#"name: Some("db")"#));
+    assert!(format!("{:?}", token).contains(r#"name: Some("db")"#));
+
+    // Resetting the source keeps its name.
+    let mut source = StopSource::named(String::from("worker"));
+    source.reset();
+    assert_eq!(source.token().name(), Some("worker"));
+
+    // Tokens which don't come from a single source have no name.
+    assert_eq!(StopSource::new().token().name(), None);
+    assert_eq!(source.token().or(StopToken::never()).name(), None);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing() {
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use stop_token::StopToken;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records the message of every event.
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl Messages {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    struct Message(Option<String>);
+
+    impl Visit for Message {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    impl Subscriber for Messages {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut message = Message(None);
+            event.record(&mut message);
+            self.0.lock().unwrap().extend(message.0);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let messages = Messages::default();
+    tracing::subscriber::with_default(messages.clone(), || {
+        task::block_on(async {
+            // Cancelling again, or dropping a cancelled source, isn't
+            // reported twice.
+            let source = StopSource::named("db");
+            source.cancel();
+            source.cancel();
+            drop(source);
+            assert_eq!(messages.take(), ["stop source cancelled"]);
+
+            drop(StopSource::new());
+            assert_eq!(messages.take(), ["stop source cancelled"]);
+
+            // A deadline which has already expired is reported once hit.
+            let deadline = StopToken::already_cancelled();
+            messages.take();
+            let res = std::future::pending::<()>().timeout_at(deadline).await;
+            assert!(res.is_err());
+            assert_eq!(messages.take(), ["deadline hit", "future timed out"]);
+        })
+    });
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics() {
+    use metrics::{
+        Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+        SharedString, Unit,
+    };
+    use std::sync::{Arc, Mutex};
+    use stop_token::{Deadline, StopToken};
+
+    /// Records the key of every counter increment and histogram sample.
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<String>>>);
+
+    impl Recorded {
+        fn push(&self, key: &Key) {
+            self.0.lock().unwrap().push(key.to_string());
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    struct Handle(Key, Recorded);
+
+    impl CounterFn for Handle {
+        fn increment(&self, value: u64) {
+            for _ in 0..value {
+                self.1.push(&self.0);
+            }
+        }
+        fn absolute(&self, _: u64) {}
+    }
+
+    impl HistogramFn for Handle {
+        fn record(&self, _: f64) {
+            self.1.push(&self.0);
+        }
+    }
+
+    impl Recorder for Recorded {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(Arc::new(Handle(key.clone(), self.clone())))
+        }
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(Arc::new(Handle(key.clone(), self.clone())))
+        }
+    }
+
+    let recorded = Recorded::default();
+    metrics::with_local_recorder(&recorded, || {
+        task::block_on(async {
+            // Cancelling again, or dropping a cancelled source, isn't
+            // counted twice.
+            let source = StopSource::named("db");
+            source.cancel();
+            source.cancel();
+            drop(source);
+            assert_eq!(
+                recorded.take(),
+                [
+                    "Key(stop_token_cancellations_total, [source = db])",
+                    "Key(stop_token_time_to_cancel_seconds, [source = db])",
+                ]
+            );
+
+            drop(StopSource::new());
+            assert_eq!(
+                recorded.take(),
+                [
+                    "Key(stop_token_cancellations_total)",
+                    "Key(stop_token_time_to_cancel_seconds)",
+                ]
+            );
+
+            // A deadline which has already expired is counted once hit.
+            let deadline = Deadline::from(StopToken::already_cancelled()).labelled("query");
+            recorded.take();
+            let res = std::future::pending::<()>().timeout_at(deadline).await;
+            assert!(res.is_err());
+            assert_eq!(
+                recorded.take(),
+                [
+                    "Key(stop_token_deadlines_hit_total, [deadline = query])",
+                    "Key(stop_token_time_to_deadline_seconds, [deadline = query])",
+                ]
+            );
+        })
+    });
+}
+
+#[test]
+fn poll_stopped() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Wake, Waker};
+
+    /// Counts how many times it was woken.
+    struct Wakes(AtomicUsize);
+
+    impl Wake for Wakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let wakes = Arc::new(Wakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    // A live token is pending, and registers the task.
+    let source = StopSource::new();
+    let mut token = source.token();
+    assert!(token.poll_stopped(&mut cx).is_pending());
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+
+    // Cancelling the source wakes the task once, and the token stays
+    // stopped.
+    source.cancel();
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+    assert!(token.poll_stopped(&mut cx).is_ready());
+    assert!(token.poll_stopped(&mut cx).is_ready());
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn partition_until() {
+    use stop_token::stream::EndReason;
+    task::block_on(async {
+        let (sender, receiver) = bounded::<i32>(10);
+        let source = StopSource::new();
+        let task = task::spawn({
+            let token = source.token();
+            async move { receiver.partition_until(token).await }
+        });
+        sender.send(1).await.unwrap();
+        sender.send(2).await.unwrap();
+        task::sleep(Duration::from_millis(100)).await;
+        drop(source);
+        sender.send(3).await.unwrap();
+
+        let mut partitioned = task.await;
+        assert_eq!(partitioned.completed, vec![1, 2]);
+        assert_eq!(partitioned.reason, EndReason::TimedOut);
+        assert_eq!(partitioned.remainder.next().await, Some(3));
+    })
+}
+
+#[test]
+fn select_until() {
+    use stop_token::stream::{select_until, EndReason};
+    task::block_on(async {
+        let (sender_a, receiver_a) = bounded::<i32>(10);
+        let (sender_b, receiver_b) = bounded::<i32>(10);
+        let source = StopSource::new();
+        let mut merged = select_until(receiver_a, receiver_b, source.token());
+
+        sender_a.send(1).await.unwrap();
+        sender_b.send(2).await.unwrap();
+        let mut items = vec![merged.next().await, merged.next().await];
+        items.sort();
+        assert_eq!(items, vec![Some(1), Some(2)]);
+        assert_eq!(merged.end_reason(), None);
+
+        drop(source);
+        assert_eq!(merged.next().await, None);
+        assert_eq!(merged.end_reason(), Some(EndReason::TimedOut));
+    })
+}
+
+#[test]
+fn combinators() {
+    use stop_token::StopToken;
+    task::block_on(async {
+        let a = StopSource::new();
+        let b = StopSource::new();
+        let any = a.token().or(b.token());
+        let all = StopToken::all(vec![a.token(), b.token()]);
+
+        drop(a);
+        any.await;
+        let res = async_std::future::timeout(Duration::from_millis(50), all.clone()).await;
+        assert!(res.is_err());
+
+        drop(b);
+        all.await;
+        StopToken::all(vec![]).await;
+
+        StopToken::already_cancelled().await;
+        let res = async_std::future::timeout(Duration::from_millis(50), StopToken::never()).await;
+        assert!(res.is_err());
+    })
+}
+
+#[test]
+fn wrap_all() {
+    use stop_token::future::wrap_all;
+    task::block_on(async {
+        let source = StopSource::new();
+        let futures = vec![
+            Box::pin(async { 1 }) as std::pin::Pin<Box<dyn Future<Output = i32> + Send>>,
+            Box::pin(std::future::pending()),
+            Box::pin(std::future::pending()),
+        ];
+        let mut wrapped = wrap_all(futures, source.token()).into_iter();
+        assert_eq!(wrapped.next().unwrap().await, Ok(1));
+
+        let pending: Vec<_> = wrapped.map(task::spawn).collect();
+        task::sleep(Duration::from_millis(50)).await;
+        drop(source);
+        for task in pending {
+            assert!(task.await.is_err());
+        }
+    })
+}
+
+#[test]
+fn trigger() {
+    task::block_on(async {
+        let source = StopSource::new();
+        let token = source.token();
+        let trigger = source.trigger();
+        std::thread::spawn(move || trigger.fire());
+        token.await;
+        assert!(source.is_cancelled());
+    })
+}
+
+#[test]
+fn trigger_cancel() {
+    let source = StopSource::new();
+    let trigger = source.trigger();
+    trigger.cancel();
+    // Unlike `fire`, the source is cancelled before `cancel` returns.
+    assert!(source.is_cancelled());
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn trigger_propagation() {
+    use stop_token::Propagation;
+
+    task::block_on(async {
+        let source = StopSource::new().with_propagation(Propagation::ChildrenFirst);
+        let worker = StopSource::new();
+        worker.attach_to(&source.token());
+        let trigger = source.trigger();
+        let waiter = task::spawn(source.token());
+        task::sleep(Duration::from_millis(50)).await;
+
+        trigger.fire();
+        waiter.await;
+        // The attached source was cancelled up front, not when next polled.
+        assert!(worker.is_cancelled());
+
+        // Later cancellations don't wake or report again.
+        trigger.fire();
+        trigger.cancel();
+        source.cancel();
+        assert_eq!(source.wake_count(), 1);
+    })
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn single_wake() {
+    task::block_on(async {
+        let parent = StopSource::new();
+        let source = StopSource::new();
+        source.attach_to(&parent.token());
+        let tasks: Vec<_> = (0..3).map(|_| task::spawn(source.token())).collect();
+        task::sleep(Duration::from_millis(50)).await;
+
+        source.cancel();
+        for task in tasks {
+            task.await;
+        }
+        assert_eq!(source.wake_count(), 3);
+        assert_eq!(parent.wake_count(), 0);
+
+        // Cancellation through a parent wakes each task once, too.
+        let source = StopSource::new();
+        source.attach_to(&parent.token());
+        let tasks: Vec<_> = (0..3).map(|_| task::spawn(source.token())).collect();
+        task::sleep(Duration::from_millis(50)).await;
+
+        parent.cancel();
+        for task in tasks {
+            task.await;
+        }
+        assert_eq!(parent.wake_count(), 3);
+        assert_eq!(source.wake_count(), 0);
+    })
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn or_deregisters() {
+    task::block_on(async {
+        let first = StopSource::new();
+        let second = StopSource::new();
+        let mut token = first.token().or(second.token());
+        let canceller = task::spawn(async move {
+            task::sleep(Duration::from_millis(50)).await;
+            first.cancel();
+            first
+        });
+
+        (&mut token).await;
+        let first = canceller.await;
+        assert_eq!(first.wake_count(), 1);
+
+        // The task which waited on both parents isn't woken by the second.
+        second.cancel();
+        assert_eq!(second.wake_count(), 0);
+        assert!(token.is_stopped());
+    })
+}
+
+#[test]
+fn paginate_until() {
+    use stop_token::stream::{paginate_until, EndReason};
+    task::block_on(async {
+        let source = StopSource::new();
+        let (sender, receiver) = bounded::<()>(1);
+        let pages = task::spawn(paginate_until(0, source.token(), move |page| {
+            let receiver = receiver.clone();
+            async move {
+                // The second page never arrives.
+                if page > 0 {
+                    receiver.recv().await.unwrap();
+                }
+                (vec![page], Some(page + 1))
+            }
+        }));
+        task::sleep(Duration::from_millis(100)).await;
+        drop(source);
+
+        let pages = pages.await;
+        assert_eq!(pages.items, vec![0]);
+        assert_eq!(pages.cursor, Some(1));
+        assert_eq!(pages.reason, EndReason::TimedOut);
+        drop(sender);
+    })
+}
+
+#[cfg(all(unix, feature = "signal"))]
+#[test]
+fn signal() {
+    use stop_token::signal::{SignalSource, SIGUSR1, SIGUSR2};
+    task::block_on(async {
+        let usr1 = SignalSource::new([SIGUSR1]).unwrap();
+        let usr2 = SignalSource::new([SIGUSR2]).unwrap();
+
+        signal_hook::low_level::raise(SIGUSR1).unwrap();
+        usr1.token().await;
+        let stopped = async_std::future::timeout(Duration::from_millis(100), usr2.token()).await;
+        assert!(stopped.is_err());
+    })
+}
+
+#[cfg(all(unix, feature = "signal"))]
+#[test]
+fn signal_default_restored() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{Command, Stdio};
+    use stop_token::signal::{SignalSource, SIGUSR2};
+
+    // A second signal terminates the process, so it is sent in a child.
+    if std::env::var_os("STOP_TOKEN_SIGNAL_CHILD").is_some() {
+        let source = SignalSource::new([SIGUSR2]).unwrap();
+        signal_hook::low_level::raise(SIGUSR2).unwrap();
+        task::block_on(source.token());
+        // The helper thread restores the default action after it cancels
+        // the source, so retry until the process is gone.
+        loop {
+            signal_hook::low_level::raise(SIGUSR2).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    let status = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "signal_default_restored"])
+        .env("STOP_TOKEN_SIGNAL_CHILD", "1")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(status.signal(), Some(SIGUSR2));
+}
+
+#[test]
+fn propagation() {
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Wake, Waker};
+    use stop_token::{Propagation, StopToken};
+
+    struct Record(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Wake for Record {
+        fn wake(self: Arc<Self>) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    fn wake_order(propagation: Propagation) -> Vec<&'static str> {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let root = StopSource::new().with_propagation(propagation);
+        let a = StopSource::new();
+        a.attach_to(&root.token());
+        let b = StopSource::new();
+        b.attach_to(&root.token());
+        let b1 = StopSource::new();
+        b1.attach_to(&b.token());
+
+        let mut tokens: Vec<(StopToken, Waker)> =
+            [("root", &root), ("a", &a), ("b", &b), ("b1", &b1)]
+                .iter()
+                .map(|&(name, source)| {
+                    let waker = Waker::from(Arc::new(Record(name, order.clone())));
+                    (source.token(), waker)
+                })
+                .collect();
+        for (token, waker) in &mut tokens {
+            assert!(token
+                .poll_stopped(&mut Context::from_waker(waker))
+                .is_pending());
+        }
+
+        root.cancel();
+        assert!(b1.is_cancelled());
+        let order = order.lock().unwrap().clone();
+        order
+    }
+
+    assert_eq!(
+        wake_order(Propagation::ChildrenFirst),
+        ["b1", "a", "b", "root"]
+    );
+    assert_eq!(
+        wake_order(Propagation::DepthFirst),
+        ["a", "b1", "b", "root"]
+    );
+}
+
+#[test]
+fn on_drop_incomplete() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let dropped = AtomicUsize::new(0);
+    task::block_on(async {
+        let completed = async { 1 }.on_drop_incomplete(|| {
+            dropped.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(completed.await, 1);
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+
+        let incomplete = std::future::pending::<()>().on_drop_incomplete(|| {
+            dropped.fetch_add(1, Ordering::Relaxed);
+        });
+        drop(incomplete);
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+    })
+}
+
+#[test]
+fn into_future() {
+    use std::future::IntoFuture;
+    use stop_token::Deadline;
+
+    async fn wait<F: IntoFuture<Output = ()>>(f: F) {
+        f.await
+    }
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let token = source.token();
+        let deadline = Deadline::from(source.token());
+        source.cancel();
+        wait(&source).await;
+        wait(&token).await;
+        wait(&deadline).await;
+    })
+}
+
+#[test]
+fn policy() {
+    use stop_token::stream::Policy;
+    use stop_token::StopToken;
+    task::block_on(async {
+        let token = StopToken::already_cancelled();
+        let mut work = stream::from_iter(vec![1, 2])
+            .timeout_at(token.clone())
+            .with_policy(Policy::PreferItem);
+        assert_eq!(work.next().await, Some(Ok(1)));
+        assert_eq!(work.next().await, Some(Ok(2)));
+        assert!(work.next().await.is_none());
+
+        let mut work = stream::from_iter(vec![1, 2]).timeout_at(token);
+        assert!(work.next().await.unwrap().is_err());
+        assert_eq!(work.into_inner().next().await, Some(1));
+    })
+}
+
+#[cfg(feature = "net")]
+#[test]
+fn serve() {
+    use stop_token::net::{serve, Served};
+    use stop_token::StopToken;
+    task::block_on(async {
+        let (sender, receiver) = bounded::<std::io::Result<u32>>(10);
+        let shutdown = StopSource::new();
+        let server = task::spawn(
+            serve(receiver, shutdown.token(), |id, token| async move {
+                // Connection 0 keeps running after shutdown.
+                if id == 0 {
+                    std::future::pending::<()>().await;
+                }
+                token.await;
+            })
+            .drain_timeout(|| StopToken::already_cancelled().into()),
+        );
+        sender.send(Ok(0)).await.unwrap();
+        sender.send(Ok(1)).await.unwrap();
+        sender
+            .send(Err(std::io::ErrorKind::Other.into()))
+            .await
+            .unwrap();
+        task::sleep(Duration::from_millis(100)).await;
+        drop(shutdown);
+
+        let served = server.await;
+        assert_eq!(
+            served,
+            Served {
+                accepted: 2,
+                accept_errors: 1,
+                idle: 0,
+                aborted: 1,
+            }
+        );
+    })
+}
+
+#[cfg(feature = "oneshot")]
+#[test]
+fn oneshot() {
+    use futures_channel::oneshot;
+    use stop_token::StopToken;
+    task::block_on(async {
+        // A legacy sender drives a token, which drives a legacy receiver.
+        let (sender, receiver) = oneshot::channel::<()>();
+        let parent = StopToken::from_oneshot(receiver);
+        let source = StopSource::new();
+        source.attach_to(&parent);
+        let legacy = source.oneshot();
+
+        let waiting = task::spawn(source.token());
+        task::sleep(Duration::from_millis(100)).await;
+        drop(sender);
+        waiting.await;
+        parent.await;
+        assert!(legacy.await.is_ok());
+
+        // Synchronous checks observe the receiver completing, without the
+        // token being polled.
+        let (sender, receiver) = oneshot::channel::<()>();
+        let token = StopToken::from_oneshot(receiver);
+        let flag = token.as_flag();
+        assert!(!token.is_stopped());
+        std::thread::spawn(move || sender.send(()).unwrap())
+            .join()
+            .unwrap();
+        assert!(token.is_stopped());
+        assert!(flag.is_set());
+        assert!(token.checkpoint().await.is_err());
+
+        // A receiver which completed already stops the token up front.
+        let (sender, receiver) = oneshot::channel::<()>();
+        drop(sender);
+        assert!(StopToken::from_oneshot(receiver).is_stopped());
+    })
+}
+
+#[test]
+fn cancel_on_drop() {
+    let source = StopSource::new();
+    let guard = source.cancel_on_drop();
+    guard.disarm();
+    assert!(!source.is_cancelled());
+
+    let guard = source.cancel_on_drop();
+    let worker = std::thread::spawn(move || {
+        let _guard = guard;
+        panic!("worker failed");
+    });
+    assert!(worker.join().is_err());
+    assert!(source.is_cancelled());
+}
+
+#[test]
+fn scope() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    task::block_on(async {
+        let stopped = AtomicUsize::new(0);
+        let source = StopSource::new();
+        let stopped = &stopped;
+        let output = stop_token::scope(source.token(), |scope| async move {
+            for _ in 0..2 {
+                let nested = scope.clone();
+                scope.spawn(move |token| async move {
+                    nested.spawn(|token| async move {
+                        token.await;
+                        stopped.fetch_add(1, Ordering::SeqCst);
+                    });
+                    token.await;
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            task::sleep(Duration::from_millis(10)).await;
+            assert_eq!(stopped.load(Ordering::SeqCst), 0);
+            12
+        })
+        .await;
+        assert_eq!(output, 12);
+        assert_eq!(stopped.load(Ordering::SeqCst), 4);
+
+        // Stopping the parent token stops the spawned futures as well.
+        let output = stop_token::scope(source.token(), |scope| async move {
+            let token = scope.token();
+            scope.spawn(|token| async move {
+                token.await;
+            });
+            drop(source);
+            token.await;
+        });
+        output.await;
+    })
+}
+
+#[test]
+fn task_tracker() {
+    use stop_token::{StopToken, TaskTracker};
+
+    task::block_on(async {
+        let tracker = TaskTracker::new();
+        let (sender, receiver) = bounded::<()>(1);
+        for _ in 0..3 {
+            let token = tracker.token();
+            let sender = sender.clone();
+            task::spawn(tracker.track(async move {
+                token.await;
+                task::sleep(Duration::from_millis(10)).await;
+                drop(sender);
+            }));
+        }
+        drop(sender);
+        assert_eq!(tracker.len(), 3);
+
+        // The tracker isn't closed yet, so waiting can't complete.
+        tracker.cancel();
+        let wait = tracker.wait().timeout_at(StopToken::already_cancelled());
+        assert!(wait.await.is_err());
+
+        tracker.close();
+        tracker.wait().await;
+        assert!(tracker.is_empty());
+        assert!(receiver.recv().await.is_err());
+
+        // Dropping a tracked future counts as it exiting.
+        let tracked = tracker.track(std::future::pending::<()>());
+        assert_eq!(tracker.len(), 1);
+        drop(tracked);
+        tracker.wait().await;
+    })
+}
+
+#[test]
+fn shutdown() {
+    use std::sync::{Arc, Mutex};
+    use stop_token::shutdown::Shutdown;
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let shutdown = Shutdown::new()
+            .phase("accept")
+            .phase_with_timeout("drain", || StopToken::already_cancelled().into())
+            .phase("flush");
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for phase in ["flush", "accept"] {
+            let token = shutdown.register(phase);
+            let order = order.clone();
+            task::spawn(async move {
+                token.clone().await;
+                task::sleep(Duration::from_millis(10)).await;
+                order.lock().unwrap().push(phase);
+                token.acknowledge();
+            });
+        }
+        // Never acknowledged, so the phase times out.
+        let _stuck = shutdown.register("drain");
+
+        let report = shutdown.run(StopToken::never()).await;
+        assert_eq!(*order.lock().unwrap(), ["accept", "flush"]);
+        let acknowledged: Vec<_> = report.phases.iter().map(|p| p.acknowledged).collect();
+        assert_eq!(acknowledged, [1, 0, 1]);
+        assert!(!report.is_complete());
+
+        // Hitting the overall deadline cancels the remaining phases at once.
+        let shutdown = Shutdown::new().phase("accept").phase("flush");
+        let accept = shutdown.register("accept");
+        let flush = shutdown.register("flush");
+        let report = shutdown.run(StopToken::already_cancelled()).await;
+        assert_eq!(report.phases.len(), 2);
+        accept.await;
+        flush.await;
+    })
+}
+
+#[test]
+fn graceful() {
+    use futures_core::Stream;
+    use std::future::poll_fn;
+    use std::task::Poll;
+    use stop_token::{GracefulSource, StopLevel};
+
+    task::block_on(async {
+        // A soft stop lets the item in flight finish.
+        let source = GracefulSource::new();
+        let token = source.token();
+        let (sender, receiver) = bounded::<i32>(10);
+        let mut work = Box::pin(receiver.timeout_at_graceful(token.soft(), token.hard()));
+        let pending = poll_fn(|cx| Poll::Ready(work.as_mut().poll_next(cx).is_pending())).await;
+        assert!(pending);
+        source.cancel_soft();
+        assert_eq!(token.level(), StopLevel::Soft);
+        sender.send(1).await.unwrap();
+        sender.send(2).await.unwrap();
+        assert_eq!(work.next().await, Some(Ok(1)));
+        assert!(work.next().await.unwrap().is_err());
+        assert_eq!(work.next().await, None);
+
+        // A hard stop bails out in the middle of an item.
+        let source = GracefulSource::new();
+        let token = source.token();
+        let (_sender, receiver) = bounded::<i32>(10);
+        let mut work = Box::pin(receiver.timeout_at_graceful(token.soft(), token.hard()));
+        let pending = poll_fn(|cx| Poll::Ready(work.as_mut().poll_next(cx).is_pending())).await;
+        assert!(pending);
+        source.cancel_hard();
+        assert_eq!(source.level(), StopLevel::Hard);
+        assert!(work.next().await.unwrap().is_err());
+        assert_eq!(work.next().await, None);
+    })
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn cancel_with_grace() {
+    use stop_token::{GracefulSource, StopLevel};
+
+    task::block_on(async {
+        let source = GracefulSource::new();
+        let token = source.token();
+        source.cancel_with_grace(Duration::from_millis(50));
+        assert_eq!(token.level(), StopLevel::Soft);
+        token.hard().await;
+        assert_eq!(token.level(), StopLevel::Hard);
+    })
+}
+
+#[test]
+fn services() {
+    use stop_token::service::{Services, Stoppable};
+    use stop_token::StopToken;
+
+    struct Worker;
+    struct Failing;
+
+    impl Stoppable for Worker {
+        type Error = String;
+
+        async fn run(self, token: StopToken) -> Result<(), String> {
+            token.await;
+            Ok(())
+        }
+    }
+
+    impl Stoppable for Failing {
+        type Error = &'static str;
+
+        async fn run(self, _token: StopToken) -> Result<(), &'static str> {
+            task::sleep(Duration::from_millis(10)).await;
+            Err("failed")
+        }
+    }
+
+    task::block_on(async {
+        // One service failing stops the others.
+        let result = Services::<String>::new(StopToken::never())
+            .with(Worker)
+            .with(Failing)
+            .with(Worker)
+            .run()
+            .await;
+        assert_eq!(result, Err("failed".to_string()));
+    })
+}
+
+#[test]
+fn from_future() {
+    use stop_token::Deadline;
+
+    task::block_on(async {
+        let (sender, receiver) = bounded::<()>(1);
+        let deadline = Deadline::from_future(async move {
+            let _ = receiver.recv().await;
+        });
+        let first = task::spawn(std::future::pending::<()>().timeout_at(deadline.clone()));
+        let second = task::spawn(std::future::pending::<()>().timeout_at(deadline));
+        drop(sender);
+        assert!(first.await.is_err());
+        assert!(second.await.is_err());
+    })
+}
+
+#[cfg(feature = "local")]
+#[test]
+fn from_local_future() {
+    use std::rc::Rc;
+    use stop_token::Deadline;
+
+    let (sender, receiver) = bounded::<()>(1);
+    let local = Rc::new(());
+    let deadline = Deadline::from_local_future(async move {
+        let _local = local;
+        let _ = receiver.recv().await;
+    });
+
+    // Only the thread which created the deadline can poll it.
+    let elsewhere = deadline.clone();
+    let res = std::thread::spawn(move || task::block_on(elsewhere)).join();
+    assert!(res.is_err());
+
+    task::block_on(async {
+        let work = std::future::pending::<()>().timeout_at(deadline.clone());
+        let res = async_std::future::timeout(Duration::from_millis(50), work).await;
+        assert!(res.is_err(), "the deadline was hit early");
+
+        drop(sender);
+        let res = std::future::pending::<()>().timeout_at(deadline).await;
+        assert!(res.is_err());
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn clock() {
+    use std::time::Instant;
+    use stop_token::async_io::AsyncIoClock;
+    use stop_token::clock::Clock;
+
+    task::block_on(async {
+        let clock = AsyncIoClock;
+        let deadline = clock.deadline(clock.now() + Duration::from_millis(10));
+        let started = Instant::now();
+        let first = std::future::pending::<()>().timeout_at(deadline.clone());
+        let second = std::future::pending::<()>().timeout_at(deadline);
+        assert!(first.await.is_err());
+        assert!(second.await.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(10));
+    })
+}
+
+#[cfg(feature = "futures-timer")]
+#[test]
+fn futures_timer() {
+    use stop_token::clock::Clock;
+    use stop_token::futures_timer::FuturesTimerClock;
+
+    task::block_on(async {
+        let clock = FuturesTimerClock;
+        let deadline = clock.deadline(clock.now() + Duration::from_millis(10));
+        assert!(std::future::pending::<()>()
+            .timeout_at(deadline)
+            .await
+            .is_err());
+
+        let work = std::future::pending::<()>().timeout_at(Duration::from_millis(10));
+        assert!(work.await.is_err());
+    })
+}
+
+#[cfg(feature = "embassy-time")]
+#[test]
+fn embassy_time() {
+    task::block_on(async {
+        let deadline = embassy_time::Duration::from_millis(10);
+        let work = std::future::pending::<()>().timeout_at(deadline);
+        assert!(work.await.is_err());
+    })
+}
+
+#[cfg(all(feature = "chrono", feature = "async-io"))]
+#[test]
+fn chrono() {
+    task::block_on(async {
+        let later = chrono::Utc::now() + chrono::Duration::milliseconds(10);
+        let work = std::future::pending::<()>().timeout_at(later.with_timezone(&chrono::Local));
+        assert!(work.await.is_err());
+
+        let past = chrono::Utc::now() - chrono::Duration::seconds(1);
+        assert!(std::future::pending::<()>().timeout_at(past).await.is_err());
+    })
+}
+
+#[cfg(all(feature = "time", feature = "async-io"))]
+#[test]
+fn time() {
+    task::block_on(async {
+        let later = time::OffsetDateTime::now_utc() + Duration::from_millis(10);
+        let work = std::future::pending::<()>().timeout_at(later);
+        assert!(work.await.is_err());
+
+        let past = time::OffsetDateTime::now_utc() - Duration::from_secs(1);
+        let past = time::PrimitiveDateTime::new(past.date(), past.time());
+        assert!(std::future::pending::<()>().timeout_at(past).await.is_err());
+    })
+}
+
+#[cfg(all(feature = "humantime", feature = "async-io"))]
+#[test]
+fn humantime() {
+    task::block_on(async {
+        let deadline: humantime::Duration = "10ms".parse().unwrap();
+        let work = std::future::pending::<()>().timeout_at(deadline);
+        assert!(work.await.is_err());
+
+        let deadline = stop_token::Deadline::parse("1h 30m").unwrap();
+        assert_eq!(async {}.timeout_at(deadline).await, Ok(()));
+        assert!(stop_token::Deadline::parse("1 fortnight").is_err());
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn system_time() {
+    use std::time::SystemTime;
+
+    task::block_on(async {
+        let later = SystemTime::now() + Duration::from_millis(10);
+        let work = std::future::pending::<()>().timeout_at(later);
+        assert!(work.await.is_err());
+
+        let past = SystemTime::now() - Duration::from_secs(1);
+        assert!(std::future::pending::<()>().timeout_at(past).await.is_err());
+    })
+}
+
+#[test]
+fn earliest() {
+    use stop_token::{Deadline, StopToken};
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let deadline = Deadline::earliest(vec![StopToken::never(), source.token()]);
+        let work = task::spawn(std::future::pending::<()>().timeout_at(deadline));
+        drop(source);
+        assert!(work.await.is_err());
+
+        let none = Deadline::earliest(Vec::<Deadline>::new());
+        assert_eq!(async { 1 }.timeout_at(none).await, Ok(1));
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn remaining() {
+    use std::time::Instant;
+    use stop_token::{Deadline, StopToken};
+
+    let work = std::future::pending::<()>().timeout_at(Duration::from_secs(60));
+    let remaining = work.deadline().remaining().unwrap();
+    assert!(remaining <= Duration::from_secs(60));
+    assert!(remaining > Duration::from_secs(59));
+    assert!(work.deadline().expires_at().unwrap() > Instant::now());
+
+    let deadline = Deadline::earliest(vec![
+        Deadline::from(Duration::from_secs(60)),
+        Deadline::from(Duration::from_secs(1)),
+        StopToken::never().into(),
+    ]);
+    assert!(deadline.remaining().unwrap() <= Duration::from_secs(1));
+
+    let past = Deadline::from(Instant::now() - Duration::from_secs(1));
+    assert_eq!(past.remaining(), Some(Duration::ZERO));
+    assert_eq!(Deadline::never().remaining(), None);
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn idle_deadline() {
+    use stop_token::IdleDeadline;
+
+    task::block_on(async {
+        let idle = IdleDeadline::new(Duration::from_millis(50));
+        let deadline = idle.deadline();
+        let work = task::spawn(std::future::pending::<()>().timeout_at(deadline));
+        for _ in 0..4 {
+            task::sleep(Duration::from_millis(20)).await;
+            idle.touch();
+        }
+        // 80ms have passed, longer than the idle timeout, yet no timeout.
+        let work = work.timeout_at(Duration::from_millis(20)).await;
+        assert!(work.is_err());
+
+        let work = std::future::pending::<()>().timeout_at(&idle);
+        assert!(work.await.is_err());
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn recurring_deadline() {
+    use std::time::Instant;
+    use stop_token::RecurringDeadline;
+
+    task::block_on(async {
+        let start = Instant::now();
+        let mut recurring = RecurringDeadline::every(Duration::from_millis(20));
+        for _ in 0..3 {
+            let work = std::future::pending::<()>().timeout_at(recurring.next_deadline());
+            assert!(work.await.is_err());
+        }
+        assert!(start.elapsed() >= Duration::from_millis(60));
+
+        // Occurrences missed while busy are skipped.
+        task::sleep(Duration::from_millis(50)).await;
+        let remaining = recurring.next_deadline().remaining().unwrap();
+        assert!(remaining <= Duration::from_millis(20));
+    })
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn mock_clock() {
+    use stop_token::clock::Clock;
+    use stop_token::test::MockClock;
+
+    task::block_on(async {
+        let clock = MockClock::new();
+        let deadline = clock.after(Duration::from_secs(10));
+        let mut work = task::spawn(std::future::pending::<()>().timeout_at(deadline.clone()));
+
+        clock.clone().advance(Duration::from_secs(4));
+        assert_eq!(clock.now(), Duration::from_secs(4));
+        assert_eq!(deadline.remaining(), Some(Duration::from_secs(6)));
+        let res = async_std::future::timeout(Duration::from_millis(20), &mut work).await;
+        assert!(res.is_err(), "the deadline was hit early");
+
+        clock.advance(Duration::from_secs(6));
+        assert!(work.await.is_err());
+    })
+}
+
+#[cfg(all(feature = "test-util", feature = "async-io"))]
+#[test]
+fn mock_clock_default() {
+    use std::time::Instant;
+    use stop_token::test::MockClock;
+    use stop_token::{Deadline, IdleDeadline};
+
+    let clock = MockClock::new();
+    task::block_on(async {
+        let _guard = clock.set_default();
+        let from_duration = Deadline::from(Duration::from_secs(60));
+        let from_instant = Deadline::from(Instant::now() + Duration::from_secs(60));
+        let idle = IdleDeadline::new(Duration::from_secs(30)).deadline();
+        let mut work = Box::pin(std::future::pending::<()>().timeout_at(from_duration.clone()));
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(from_duration.remaining(), Some(Duration::from_secs(1)));
+        assert!(from_instant.remaining().unwrap() <= Duration::from_secs(1));
+        let res = async_std::future::timeout(Duration::from_millis(20), &mut work).await;
+        assert!(res.is_err(), "the deadline was hit early");
+
+        clock.advance(Duration::from_secs(1));
+        let err = work.await.unwrap_err();
+        assert_eq!(err.elapsed(), Some(Duration::from_secs(60)));
+        from_instant.await;
+        idle.await;
+    });
+
+    // Once the guard is gone, deadlines follow the system time again.
+    let deadline = Deadline::from(Duration::from_secs(60));
+    clock.advance(Duration::from_secs(60));
+    assert!(deadline.remaining().unwrap() > Duration::from_secs(59));
+}
+
+#[cfg(all(feature = "chaos", feature = "test-util"))]
+#[test]
+fn chaos() {
+    use stop_token::chaos;
+    use stop_token::test::MockClock;
+
+    let clock = MockClock::new();
+    let minute = Duration::from_secs(60);
+
+    chaos::enable_for("chaos-never", 0.0);
+    for _ in 0..100 {
+        let deadline = clock.after(minute).labelled("chaos-never");
+        assert_eq!(deadline.remaining(), Some(minute));
+    }
+
+    chaos::enable_for("chaos-always", 1.0);
+    for _ in 0..100 {
+        let deadline = clock.after(minute).labelled("chaos-always");
+        assert!(deadline.remaining().unwrap() < minute);
+    }
+
+    let shortened = |seed| {
+        chaos::seed(seed);
+        clock.after(minute).labelled("chaos-always").remaining()
+    };
+    assert_eq!(shortened(7), shortened(7));
+    assert_ne!(shortened(7), shortened(8));
+
+    chaos::disable_for("chaos-never");
+    chaos::disable_for("chaos-always");
+}
+
+#[test]
+fn current_deadline() {
+    use stop_token::{current_deadline, Deadline};
+
+    task::block_on(async {
+        assert!(current_deadline().is_none());
+
+        let outer = StopSource::new();
+        let work = async {
+            let budget = current_deadline().unwrap();
+            assert_eq!(budget.label(), Some("inner"));
+            std::future::pending::<()>().timeout_at(budget).await
+        };
+        let work = async {
+            work.with_deadline(Deadline::never().labelled("inner"))
+                .await
+        }
+        .with_deadline(outer.token());
+        let work = task::spawn(work);
+        drop(outer);
+        assert!(work.await.is_err());
+        assert!(current_deadline().is_none());
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn grpc_timeout() {
+    use stop_token::grpc;
+
+    assert_eq!(grpc::encode_duration(Duration::from_millis(150)), "150000u");
+    assert_eq!(
+        grpc::encode_duration(Duration::from_secs(3600 * 24)),
+        "86400000m"
+    );
+    assert_eq!(
+        grpc::encode_duration(Duration::from_secs(u64::MAX)),
+        "99999999H"
+    );
+    assert_eq!(grpc::decode_duration("2M"), Ok(Duration::from_secs(120)));
+    for invalid in ["", "S", "123", "123456789S", "-1S", "10s", "1é"] {
+        assert!(grpc::decode_duration(invalid).is_err(), "{}", invalid);
+    }
+
+    let deadline = grpc::decode("30S").unwrap();
+    let header = grpc::encode(&deadline).unwrap();
+    let remaining = grpc::decode_duration(&header).unwrap();
+    assert!(remaining <= Duration::from_secs(30));
+    assert!(remaining > Duration::from_secs(29));
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn timed_out_error() {
+    use stop_token::{Deadline, StopToken};
+
+    task::block_on(async {
+        let deadline = Deadline::from(Duration::from_millis(20)).labelled("db-query");
+        let err = std::future::pending::<()>()
+            .timeout_at(deadline)
+            .await
+            .unwrap_err();
+        assert_eq!(err.label(), Some("db-query"));
+        assert!(err.elapsed().unwrap() >= Duration::from_millis(20));
+        assert!(err.budget().unwrap() <= Duration::from_millis(20));
+        assert!(err.to_string().starts_with("Future has timed out after "));
+        assert!(err.to_string().ends_with("waiting on db-query"));
+
+        let err = async {}
+            .timeout_at(StopToken::already_cancelled())
+            .await
+            .unwrap_err();
+        assert_eq!(err.budget(), None);
+        assert_eq!(err.to_string(), "Future has timed out");
+    })
+}
+
+#[cfg(all(feature = "tokio", feature = "async-std"))]
+#[test]
+fn runtime_timeout_errors() {
+    use stop_token::TimedOutError;
+
+    async fn fetch() -> Result<(), TimedOutError> {
+        async_std::future::timeout(Duration::from_millis(1), std::future::pending::<()>()).await?;
+        Ok(())
+    }
+
+    task::block_on(async {
+        assert_eq!(fetch().await.unwrap_err().label(), None);
+    });
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let elapsed = tokio::time::timeout(Duration::from_millis(1), std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        let err = TimedOutError::from(elapsed);
+        assert_eq!(err.to_string(), "Future has timed out");
+    });
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn timeout_kind() {
+    use stop_token::{Deadline, TimeoutKind};
+
+    task::block_on(async {
+        let shutdown = StopSource::new();
+        let deadline = Deadline::from(Duration::from_millis(10)).or_token(&shutdown.token());
+        let err = std::future::pending::<()>()
+            .timeout_at(deadline.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), TimeoutKind::Expired);
+
+        let futures = stop_token::future::wrap_all(
+            vec![std::future::pending::<()>()],
+            Deadline::from(Duration::from_secs(60)).or_token(&shutdown.token()),
+        );
+        drop(shutdown);
+        for work in futures {
+            assert_eq!(work.await.unwrap_err().kind(), TimeoutKind::Stopped);
+        }
+    })
+}
+
+#[test]
+fn map_timeout() {
+    use std::cell::Cell;
+    use stop_token::{Deadline, StopToken, TimedOutError, TimeoutKind};
+
+    #[derive(Debug, PartialEq)]
+    enum Error {
+        Timeout(&'static str),
+    }
+
+    task::block_on(async {
+        // The closure is handed the timeout error, and its output is
+        // returned in place of it.
+        let received = Cell::new(None);
+        let deadline = Deadline::from(StopToken::already_cancelled()).labelled("query");
+        let res = async { 1 }
+            .timeout_at(deadline)
+            .map_timeout(|err: TimedOutError| {
+                received.set(Some(err));
+                Error::Timeout("query failed")
+            })
+            .await;
+        assert_eq!(res, Err(Error::Timeout("query failed")));
+        let err = received.get().unwrap();
+        assert_eq!(err.kind(), TimeoutKind::Stopped);
+        assert_eq!(err.label(), Some("query"));
+
+        // The closure isn't called when the future completes.
+        let called = Cell::new(false);
+        let res = async { 1 }
+            .timeout_at(Deadline::never())
+            .map_timeout(|_| {
+                called.set(true);
+                Error::Timeout("query failed")
+            })
+            .await;
+        assert_eq!(res, Ok(1));
+        assert!(!called.get());
+    })
+}
+
+#[test]
+fn race() {
+    use std::future::{poll_fn, Future};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use stop_token::{StopToken, TimeoutKind};
+
+    /// Resolves to `1` when `ready`, and records being polled and dropped.
+    struct Probe {
+        ready: bool,
+        polled: Arc<AtomicBool>,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Probe {
+        fn new(ready: bool) -> Probe {
+            Probe {
+                ready,
+                polled: Arc::default(),
+                dropped: Arc::default(),
+            }
+        }
+    }
+
+    impl Future for Probe {
+        type Output = i32;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<i32> {
+            self.polled.store(true, Ordering::SeqCst);
+            if self.ready {
+                Poll::Ready(1)
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    task::block_on(async {
+        // When both sides are ready, the token wins, and the future is
+        // dropped without being polled.
+        let probe = Probe::new(true);
+        let (polled, dropped) = (probe.polled.clone(), probe.dropped.clone());
+        let err = StopToken::already_cancelled()
+            .race(probe)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), TimeoutKind::Stopped);
+        assert!(!polled.load(Ordering::SeqCst));
+        assert!(dropped.load(Ordering::SeqCst));
+
+        // When the future wins, the race's copy of the token is dropped.
+        let source = StopSource::new();
+        let token = source.token();
+        let probe = Probe::new(true);
+        assert_eq!(token.race(probe).await, Ok(1));
+        assert_eq!(source.token_count(), 1);
+        assert!(!token.is_stopped());
+
+        // A pending future loses once the source is cancelled, and is
+        // dropped along with the race.
+        let probe = Probe::new(false);
+        let (polled, dropped) = (probe.polled.clone(), probe.dropped.clone());
+        let mut work = Box::pin(token.race(probe));
+        let poll = poll_fn(|cx| Poll::Ready(work.as_mut().poll(cx))).await;
+        assert!(poll.is_pending());
+        assert!(polled.load(Ordering::SeqCst));
+        source.cancel();
+        assert!(work.as_mut().await.is_err());
+        assert!(!dropped.load(Ordering::SeqCst));
+        drop(work);
+        assert!(dropped.load(Ordering::SeqCst));
+    })
+}
+
+#[test]
+fn run_until() {
+    use std::future::{poll_fn, Future};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::Poll;
+    use stop_token::{StopToken, TimeoutKind};
+
+    task::block_on(async {
+        // A subsystem which finishes on its own returns its output.
+        let source = StopSource::new();
+        assert_eq!(source.token().run_until(async { 1 }).await, Ok(1));
+
+        // A subsystem started after shutdown never runs.
+        let started = Arc::new(AtomicBool::new(false));
+        let subsystem = {
+            let started = started.clone();
+            async move { started.store(true, Ordering::SeqCst) }
+        };
+        let err = StopToken::already_cancelled()
+            .run_until(subsystem)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), TimeoutKind::Stopped);
+        assert!(!started.load(Ordering::SeqCst));
+
+        // Cancelling the source stops a running subsystem.
+        let mut server = Box::pin(source.token().run_until(std::future::pending::<()>()));
+        let poll = poll_fn(|cx| Poll::Ready(server.as_mut().poll(cx))).await;
+        assert!(poll.is_pending());
+        source.cancel();
+        let err = server.as_mut().await.unwrap_err();
+        assert_eq!(err.kind(), TimeoutKind::Stopped);
+    })
+}
+
+#[test]
+fn until_or() {
+    use stop_token::future::Outcome;
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let outcome = async { 1 }.until_or(StopToken::never()).await;
+        assert_eq!(outcome, Outcome::Completed(1));
+        assert_eq!(outcome.into_result(), Ok(1));
+
+        let source = StopSource::new();
+        let work = task::spawn(std::future::pending::<()>().until_or(source.token()));
+        drop(source);
+        assert!(matches!(work.await, Outcome::Stopped(_)));
+    })
+}
+
+#[test]
+fn until() {
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        assert_eq!(async { 1 }.until(StopToken::never()).await, Ok(1));
+
+        let mut work = stream::repeat(1).until(StopToken::already_cancelled());
+        assert!(work.next().await.unwrap().is_err());
+    })
+}
+
+#[test]
+fn until_with() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let cancelled = Arc::new(AtomicUsize::new(0));
+        let on_cancel = {
+            let cancelled = cancelled.clone();
+            move |_: &_| {
+                cancelled.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+        let result = async { 1 }
+            .until_with(StopToken::never(), on_cancel.clone())
+            .await;
+        assert_eq!(result, Ok(1));
+        assert_eq!(cancelled.load(Ordering::SeqCst), 0);
+
+        let source = StopSource::new();
+        let work = task::spawn(std::future::pending::<()>().until_with(source.token(), on_cancel));
+        drop(source);
+        assert!(work.await.is_err());
+        assert_eq!(cancelled.load(Ordering::SeqCst), 1);
+    })
+}
+
+#[test]
+fn until_then() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let cleaned_up = AtomicBool::new(false);
+        let cleanup = async {
+            task::sleep(Duration::from_millis(10)).await;
+            cleaned_up.store(true, Ordering::SeqCst);
+        };
+        let result = async { 1 }.until_then(StopToken::never(), cleanup).await;
+        assert_eq!(result, Ok(1));
+        assert!(!cleaned_up.load(Ordering::SeqCst));
+
+        let cleanup = async {
+            task::sleep(Duration::from_millis(10)).await;
+            cleaned_up.store(true, Ordering::SeqCst);
+        };
+        let result = std::future::pending::<()>()
+            .until_then(StopToken::already_cancelled(), cleanup)
+            .await;
+        assert!(result.is_err());
+        assert!(cleaned_up.load(Ordering::SeqCst));
+    })
+}
+
+#[test]
+fn try_until() {
+    use stop_token::{StopToken, TimedOutError};
+
+    #[derive(Debug, PartialEq)]
+    enum Error {
+        Failed,
+        TimedOut,
+    }
+
+    impl From<TimedOutError> for Error {
+        fn from(_: TimedOutError) -> Error {
+            Error::TimedOut
+        }
+    }
+
+    task::block_on(async {
+        let ok = async { Ok::<_, Error>(1) }.try_until(StopToken::never());
+        assert_eq!(ok.await, Ok(1));
+        let failed = async { Err::<(), _>(Error::Failed) }.try_until(StopToken::never());
+        assert_eq!(failed.await, Err(Error::Failed));
+        let work = std::future::pending::<Result<(), Error>>();
+        assert_eq!(
+            work.try_until(StopToken::already_cancelled()).await,
+            Err(Error::TimedOut)
+        );
+    })
+}
+
+#[test]
+fn fused() {
+    use futures_core::future::FusedFuture;
+    use futures_core::stream::FusedStream;
+    use std::future::{poll_fn, Future};
+    use std::pin::Pin;
+    use std::task::Poll;
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let mut work = Box::pin(async { 1 }.timeout_at(StopToken::never()));
+        assert!(!work.is_terminated());
+        assert_eq!(work.as_mut().await, Ok(1));
+        assert!(work.is_terminated());
+        // Polling again is harmless.
+        let poll = poll_fn(|cx| Poll::Ready(work.as_mut().poll(cx))).await;
+        assert!(poll.is_pending());
+
+        let mut items = stream::once(1).timeout_at(StopToken::never());
+        assert_eq!(items.next().await, Some(Ok(1)));
+        assert!(!items.is_terminated());
+        assert_eq!(items.next().await, None);
+        assert!(items.is_terminated());
+        assert_eq!(Pin::new(&mut items).next().await, None);
+
+        // The cleanup future isn't polled again once it has completed.
+        let mut work = Box::pin(
+            std::future::pending::<()>().until_then(StopToken::already_cancelled(), async {}),
+        );
+        assert!(!work.is_terminated());
+        assert!(work.as_mut().await.is_err());
+        assert!(work.is_terminated());
+        let poll = poll_fn(|cx| Poll::Ready(work.as_mut().poll(cx))).await;
+        assert!(poll.is_pending());
+    })
+}
+
+#[test]
+fn until_done() {
+    use stop_token::stream::EndReason;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let mut work = stream::repeat(1).until_done(source.token());
+        assert_eq!(work.next().await, Some(1));
+        drop(source);
+        assert_eq!(work.next().await, None);
+        assert_eq!(work.end_reason(), Some(EndReason::TimedOut));
+
+        let source = StopSource::new();
+        let mut work = stream::once(1).until_done(source.token());
+        assert_eq!(work.next().await, Some(1));
+        assert_eq!(work.next().await, None);
+        assert_eq!(work.end_reason(), Some(EndReason::Exhausted));
+    })
+}
+
+#[test]
+fn drain_policy() {
+    use stop_token::stream::Policy;
+
+    task::block_on(async {
+        let (sender, receiver) = bounded(10);
+        for i in 0..3 {
+            sender.send(i).await.unwrap();
+        }
+
+        let source = StopSource::new();
+        let mut work = receiver
+            .timeout_at(source.token())
+            .with_policy(Policy::Drain);
+        assert_eq!(work.next().await, Some(Ok(0)));
+        drop(source);
+        assert_eq!(work.next().await, Some(Ok(1)));
+        assert_eq!(work.next().await, Some(Ok(2)));
+        assert!(work.next().await.unwrap().is_err());
+        sender.send(3).await.unwrap();
+        assert!(work.next().await.is_none());
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn timeout_between_items() {
+    task::block_on(async {
+        let (sender, receiver) = bounded(10);
+        let mut reads = receiver.timeout_between_items(Duration::from_millis(100));
+        task::spawn(async move {
+            for i in 0..4 {
+                task::sleep(Duration::from_millis(20)).await;
+                sender.send(i).await.unwrap();
+            }
+            // Hang up halfway through the second idle period.
+            task::sleep(Duration::from_millis(150)).await;
+        });
+
+        // 80ms in total, but never longer than the timeout between items.
+        for i in 0..4 {
+            assert_eq!(reads.next().await, Some(Ok(i)));
+        }
+        assert!(reads.next().await.unwrap().is_err());
+        assert_eq!(reads.next().await, None);
+    })
+}
+
+#[test]
+fn until_with_final() {
+    task::block_on(async {
+        let source = StopSource::new();
+        let mut work = stream::repeat(1).until_with_final(source.token(), || 0);
+        assert_eq!(work.next().await, Some(1));
+        drop(source);
+        assert_eq!(work.next().await, Some(0));
+        assert_eq!(work.next().await, None);
+
+        let source = StopSource::new();
+        let mut work = stream::once(1).until_with_final(source.token(), || 0);
+        assert_eq!(work.next().await, Some(1));
+        assert_eq!(work.next().await, None);
+    })
+}
+
+#[test]
+fn set_deadline() {
+    use std::future::{poll_fn, Future};
+    use std::task::Poll;
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let mut work = Box::pin(stream::repeat(1).timeout_at(source.token()));
+        assert_eq!(work.next().await, Some(Ok(1)));
+        let extended = StopSource::new();
+        work.as_mut().set_deadline(extended.token());
+        drop(source);
+        assert_eq!(work.next().await, Some(Ok(1)));
+        drop(extended);
+        assert!(work.next().await.unwrap().is_err());
+
+        let mut work = Box::pin(std::future::pending::<()>().timeout_at(StopToken::never()));
+        let poll = poll_fn(|cx| Poll::Ready(work.as_mut().poll(cx))).await;
+        assert!(poll.is_pending());
+        work.as_mut().set_deadline(StopToken::already_cancelled());
+        assert!(work.await.is_err());
+    })
+}
+
+#[test]
+fn sink_until() {
+    use futures_sink::Sink;
+    use std::future::poll_fn;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use stop_token::sink::SinkExt as _;
+
+    #[derive(Default)]
+    struct Recorder {
+        sent: Vec<u8>,
+        flushed: usize,
+        closed: bool,
+    }
+
+    impl Sink<u8> for Recorder {
+        type Error = io::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: u8) -> io::Result<()> {
+            self.sent.push(item);
+            Ok(())
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.flushed = self.sent.len();
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.closed = true;
+            self.poll_flush(cx)
+        }
+    }
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let mut sink = Box::pin(Recorder::default().until(source.token()));
+        poll_fn(|cx| sink.as_mut().poll_ready(cx)).await.unwrap();
+        sink.as_mut().start_send(1).unwrap();
+        assert!(!sink.is_stopped());
+
+        drop(source);
+        let err = poll_fn(|cx| sink.as_mut().poll_ready(cx))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(sink.is_stopped());
+        poll_fn(|cx| sink.as_mut().poll_close(cx)).await.unwrap();
+
+        let recorder = Pin::into_inner(sink).into_inner();
+        assert_eq!(recorder.sent, vec![1]);
+        assert_eq!(recorder.flushed, 1);
+        assert!(recorder.closed);
+    })
+}
+
+#[cfg(feature = "futures-io")]
+#[test]
+fn read_until() {
+    use async_std::io::{Cursor, ReadExt};
+    use std::io;
+    use stop_token::io::ReadUntil;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let mut reader = ReadUntil::new(Cursor::new(vec![1, 2, 3, 4]), source.token());
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2]);
+
+        drop(source);
+        let err = reader.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(reader.is_stopped());
+        assert_eq!(reader.into_inner().position(), 2);
+    })
+}
+
+#[cfg(feature = "futures-io")]
+#[test]
+fn write_until() {
+    use async_std::io::WriteExt;
+    use std::io;
+    use stop_token::io::WriteUntil;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let mut writer = WriteUntil::new(Vec::new(), source.token());
+        writer.write_all(b"hello").await.unwrap();
+
+        drop(source);
+        let err = writer.write_all(b"world").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(writer.is_stopped());
+        assert!(writer.write(b"!").await.is_err());
+        writer.flush().await.unwrap();
+        assert_eq!(writer.into_inner(), b"hello");
+    })
+}
+
+#[cfg(all(unix, feature = "futures-io"))]
+#[test]
+fn lines_until() {
+    use async_std::io::{BufReader, WriteExt};
+    use async_std::os::unix::net::UnixStream;
+    use stop_token::io::lines_until;
+    use stop_token::stream::EndReason;
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let (reader, mut writer) = UnixStream::pair().unwrap();
+        let source = StopSource::new();
+        let mut lines = lines_until(BufReader::new(reader), source.token());
+
+        writer.write_all(b"first\r\nsec").await.unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "first");
+        let pending = async_std::future::timeout(Duration::from_millis(10), lines.next()).await;
+        assert!(pending.is_err());
+
+        // The second line has been started, so it is finished first.
+        drop(source);
+        writer.write_all(b"ond\nthird\n").await.unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "second");
+        assert!(lines.next().await.is_none());
+        assert_eq!(lines.end_reason(), Some(EndReason::TimedOut));
+
+        let mut lines = lines_until(&b"last"[..], StopToken::never());
+        assert_eq!(lines.next().await.unwrap().unwrap(), "last");
+        assert!(lines.next().await.is_none());
+        assert_eq!(lines.end_reason(), Some(EndReason::Exhausted));
+    })
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn lines_until_tokio() {
+    use stop_token::io::lines_until;
+    use stop_token::StopToken;
+
+    let mut lines = lines_until(&b"one\ntwo"[..], StopToken::never());
+    assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("one"));
+    assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("two"));
+    assert_eq!(lines.next_line().await.unwrap(), None);
+}
+
+#[cfg(all(unix, feature = "futures-io"))]
+#[test]
+fn copy_until() {
+    use async_std::io::WriteExt;
+    use async_std::os::unix::net::UnixStream;
+    use std::io;
+
+    task::block_on(async {
+        let (reader, mut writer) = UnixStream::pair().unwrap();
+        let source = StopSource::new();
+        let copy = task::spawn(stop_token::io::copy_until(
+            reader,
+            Vec::new(),
+            source.token(),
+        ));
+
+        writer.write_all(b"hello").await.unwrap();
+        task::sleep(Duration::from_millis(20)).await;
+        drop(source);
+
+        let stopped = copy.await.unwrap_err();
+        assert!(stopped.is_timeout());
+        assert_eq!(stopped.copied(), 5);
+        assert_eq!(io::Error::from(stopped).kind(), io::ErrorKind::TimedOut);
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn interval_until() {
+    use stop_token::stream::interval_until;
+
+    task::block_on(async {
+        let mut ticks = interval_until(Duration::from_millis(10), Duration::from_millis(55));
+        let mut count = 0;
+        let mut last = None;
+        while let Some(tick) = ticks.next().await {
+            assert!(last.is_none_or(|last| tick > last));
+            last = Some(tick);
+            count += 1;
+        }
+        assert!((1..=5).contains(&count));
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn sleep_until_stopped() {
+    use std::time::Instant;
+    use stop_token::future::sleep_until_stopped;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let token = source.token();
+        let sleep = sleep_until_stopped(Duration::from_millis(10), token.clone());
+        assert!(sleep.await.is_ok());
+
+        let start = Instant::now();
+        task::spawn(async move {
+            task::sleep(Duration::from_millis(10)).await;
+            drop(source);
+        });
+        let err = sleep_until_stopped(Duration::from_secs(60), token)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), stop_token::TimeoutKind::Stopped);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn retry_until() {
+    use std::time::Instant;
+    use stop_token::retry::{retry_until, Backoff, RetryError};
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let backoff = Backoff::exponential(Duration::from_millis(1)).max_retries(2);
+        let mut attempts = 0;
+        let res = retry_until(StopToken::never(), backoff, || {
+            attempts += 1;
+            async { Err::<(), _>("refused") }
+        })
+        .await;
+        assert!(matches!(res, Err(RetryError::Failed("refused"))));
+        assert_eq!(attempts, 3);
+
+        // The second backoff would end past the deadline, so it isn't started.
+        let start = Instant::now();
+        let backoff = Backoff::exponential(Duration::from_millis(40));
+        let res = retry_until(Duration::from_millis(60), backoff, || async {
+            Err::<(), _>("refused")
+        })
+        .await;
+        match res {
+            Err(RetryError::TimedOut { last, .. }) => assert_eq!(last, Some("refused")),
+            _ => panic!("expected a timeout"),
+        }
+        assert!(start.elapsed() < Duration::from_millis(60));
+
+        let res = retry_until(
+            StopToken::already_cancelled(),
+            Backoff::default(),
+            || async { Ok::<_, ()>(1) },
+        )
+        .await;
+        assert!(matches!(res, Err(RetryError::TimedOut { last: None, .. })));
+    })
+}
+
+#[test]
+fn checkpoint() {
+    use std::future::{poll_fn, Future};
+    use std::task::Poll;
+    use stop_token::TimeoutKind;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let token = source.token();
+        let mut checkpoint = Box::pin(token.checkpoint());
+        // The first poll yields to the executor.
+        let poll = poll_fn(|cx| Poll::Ready(checkpoint.as_mut().poll(cx))).await;
+        assert!(poll.is_pending());
+        assert_eq!(checkpoint.await, Ok(()));
+
+        drop(source);
+        let err = token.checkpoint().await.unwrap_err();
+        assert_eq!(err.kind(), TimeoutKind::Stopped);
+    })
+}
+
+#[test]
+fn yield_now_if_needed() {
+    use std::future::{poll_fn, Future};
+    use std::task::Poll;
+    use stop_token::YieldBudget;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let token = source.token();
+
+        // Each budget counts its own calls, so interleaved loops don't make
+        // each other yield early.
+        let mut budget = YieldBudget::every(4);
+        let mut other = YieldBudget::every(4);
+        let mut yields = 0;
+        for _ in 0..8 {
+            for budget in [&mut budget, &mut other] {
+                let mut point = Box::pin(token.yield_now_if_needed(budget));
+                match poll_fn(|cx| Poll::Ready(point.as_mut().poll(cx))).await {
+                    Poll::Ready(stopped) => assert!(!stopped),
+                    Poll::Pending => {
+                        yields += 1;
+                        assert!(!point.await);
+                    }
+                }
+            }
+        }
+        assert_eq!(yields, 4);
+
+        // A stopped token yields right away, whatever the budget.
+        drop(source);
+        let mut point = Box::pin(token.yield_now_if_needed(&mut budget));
+        let poll = poll_fn(|cx| Poll::Ready(point.as_mut().poll(cx))).await;
+        assert!(poll.is_pending());
+        assert!(point.await);
+    })
+}
+
+#[test]
+fn as_flag() {
+    let parent = StopSource::new();
+    let child = StopSource::new();
+    child.attach_to(&parent.token());
+    let flag = child.token().as_flag();
+    assert!(!flag.is_set());
+
+    // Stopping a parent sets the flag, without anyone polling the token.
+    drop(parent);
+    assert!(flag.is_set());
+    assert!(flag.clone().is_set());
+
+    // Parents attached after the flag was made, and tokens waiting on all of
+    // their parents, set it too.
+    let first = StopSource::new();
+    let second = StopSource::new();
+    let child = StopSource::new();
+    let flag = stop_token::StopToken::all([first.token(), child.token()]).as_flag();
+    child.attach_to(&second.token());
+    drop(first);
+    assert!(!flag.is_set());
+    drop(second);
+    assert!(flag.is_set());
+
+    // Dropping the flag lets go of its token.
+    let source = StopSource::new();
+    let flag = source.token().as_flag();
+    assert_eq!(source.token_count(), 1);
+    drop(flag);
+    assert_eq!(source.token_count(), 0);
+}
+
+#[cfg(all(unix, feature = "ipc"))]
+#[test]
+fn ipc_fd() {
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let source = StopSource::new();
+        let token = StopToken::from_fd(source.export_fd().unwrap()).unwrap();
+        assert!(!token.is_stopped());
+
+        source.cancel();
+        // Stops once the other thread has seen the descriptor become readable.
+        token.clone().await;
+        assert!(token.is_stopped());
+    })
+}
+
+#[cfg(all(windows, feature = "ipc"))]
+#[test]
+fn ipc_event() {
+    use stop_token::StopToken;
+
+    task::block_on(async {
+        let name = format!("Local\\stop-token-test-{}", std::process::id());
+        let source = StopSource::new();
+        let _event = source.export_event(&name).unwrap();
+        let token = StopToken::open_event(&name).unwrap();
+        assert!(!token.is_stopped());
+
+        source.cancel();
+        // Stops once the other thread has seen the event being set.
+        token.clone().await;
+        assert!(token.is_stopped());
+
+        // The event has to exist to be opened.
+        assert!(StopToken::open_event("Local\\stop-token-test-missing").is_err());
+
+        // Setting the event from elsewhere cancels the source, here through a
+        // second source exporting the same event.
+        let name = format!("Local\\stop-token-test-trigger-{}", std::process::id());
+        let source = StopSource::new();
+        let _event = source.export_event(&name).unwrap();
+        let other = StopSource::new();
+        let _other_event = other.export_event(&name).unwrap();
+        assert!(!source.is_cancelled());
+
+        other.cancel();
+        source.token().await;
+        assert!(source.is_cancelled());
+    })
+}
+
+#[cfg(all(feature = "serde", feature = "async-io"))]
+#[test]
+fn budget() {
+    use std::time::SystemTime;
+    use stop_token::{Budget, Deadline};
+
+    task::block_on(async {
+        let deadline = Deadline::from(Duration::from_secs(60));
+        let budget = Budget::of(&deadline).unwrap();
+        assert!(budget.remaining() <= Duration::from_secs(60));
+        assert!(budget.expires_at().unwrap() > SystemTime::now());
+
+        let restored = Deadline::from(budget);
+        assert!(restored.remaining().unwrap() <= budget.remaining());
+
+        // An elapsed budget converts back into an elapsed deadline.
+        let expired = Budget::of(&Deadline::elapsed()).unwrap();
+        assert!(std::future::pending::<()>()
+            .timeout_at(expired)
+            .await
+            .is_err());
+        assert_eq!(expired.without_expiry(), Budget::new(Duration::ZERO));
+    })
+}
+
+#[cfg(all(feature = "tower", feature = "async-io"))]
+#[test]
+fn tower_layer() {
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use stop_token::tower::StopLayer;
+    use stop_token::Deadline;
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    /// Sleeps for the requested time, then echoes it back.
+    struct Sleep;
+
+    impl Service<Duration> for Sleep {
+        type Response = Duration;
+        type Error = io::Error;
+        type Future = Pin<Box<dyn Future<Output = io::Result<Duration>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Duration) -> Self::Future {
+            Box::pin(async move {
+                task::sleep(req).await;
+                Ok(req)
+            })
+        }
+    }
+
+    task::block_on(async {
+        let shutdown = StopSource::new();
+        let mut service = StopLayer::new(shutdown.token())
+            .timeout(Duration::from_millis(100))
+            .layer(Sleep);
+        assert!(service.call(Duration::ZERO).await.is_ok());
+        let err = service.call(Duration::from_secs(10)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // A deadline carried by the request is honoured too.
+        let mut service = StopLayer::new(shutdown.token())
+            .request_deadline(|req: &Duration| {
+                (*req > Duration::from_secs(1)).then(Deadline::elapsed)
+            })
+            .layer(Sleep);
+        assert!(service.call(Duration::ZERO).await.is_ok());
+        assert!(service.call(Duration::from_secs(10)).await.is_err());
+
+        // Calls made after shutdown fail right away.
+        drop(shutdown);
+        assert!(service.call(Duration::ZERO).await.is_err());
+    })
+}
+
+#[cfg(feature = "hyper")]
+#[tokio::test]
+async fn hyper_graceful_connection() {
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response};
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use stop_token::hyper::GracefulConnection;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    /// Answers `/slow` after a long while, and anything else right away.
+    async fn handle(req: Request<hyper::body::Incoming>) -> Result<Response<String>, Infallible> {
+        if req.uri().path() == "/slow" {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+        Ok(Response::new(String::from("done")))
+    }
+
+    // An idle keep-alive connection closes once the shutdown token stops.
+    let (mut client, server) = duplex(4096);
+    let source = StopSource::new();
+    let conn = http1::Builder::new().serve_connection(TokioIo::new(server), service_fn(handle));
+    let conn = tokio::spawn(GracefulConnection::new(conn, source.token()));
+    client
+        .write_all(b"GET / HTTP/1.1\r\nhost: test\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0; 1024];
+    let read = client.read(&mut buf).await.unwrap();
+    assert!(buf[..read].starts_with(b"HTTP/1.1 200 OK"));
+    drop(source);
+    assert!(conn.await.unwrap().unwrap().is_ok());
+
+    // A connection still busy once the drain timeout elapses is dropped.
+    let (mut client, server) = duplex(4096);
+    let source = StopSource::new();
+    let conn = http1::Builder::new().serve_connection(TokioIo::new(server), service_fn(handle));
+    let conn = tokio::spawn(
+        GracefulConnection::new(conn, source.token()).drain_timeout(Duration::from_millis(50)),
+    );
+    client
+        .write_all(b"GET /slow HTTP/1.1\r\nhost: test\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(source);
+    assert!(conn.await.unwrap().is_err());
+}
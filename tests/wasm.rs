@@ -0,0 +1,45 @@
+//! Tests for `wasm32-unknown-unknown`, where `std::time::Instant` panics.
+//!
+//! Run them with `wasm-pack test --node -- --features wasm`.
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use std::time::Duration;
+
+use async_std::prelude::*;
+
+use stop_token::prelude::*;
+use stop_token::{Deadline, StopSource};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn deadline() {
+    let source = StopSource::new();
+    let deadline = Deadline::from(source.token());
+    assert_eq!(deadline.remaining(), None);
+    assert_eq!(deadline.expires_at(), None);
+
+    let deadline = Deadline::from(Duration::from_secs(1));
+    assert!(deadline.remaining().unwrap() <= Duration::from_secs(1));
+}
+
+#[wasm_bindgen_test]
+async fn timed_out() {
+    let err = std::future::pending::<()>()
+        .timeout_at(Duration::from_millis(10))
+        .await
+        .unwrap_err();
+    assert!(err.elapsed().is_some());
+    assert!(err.budget().unwrap() <= Duration::from_millis(10));
+}
+
+#[wasm_bindgen_test]
+async fn interval_until() {
+    use stop_token::stream::interval_until;
+
+    let mut ticks = interval_until(Duration::from_millis(10), Duration::from_millis(35));
+    let mut last = Duration::ZERO;
+    while let Some(tick) = ticks.next().await {
+        assert!(tick >= last);
+        last = tick;
+    }
+}